@@ -1,19 +1,161 @@
+use std::time::Duration;
+
 pub struct Connections {
     pub db: Database,
-    pub s3_info: S3Info,
+    pub storage: Storage,
+    pub migrations: Migrations,
 }
 
+/// Where the fetched papers are recorded.
+#[derive(Debug, Clone)]
 pub enum Database {
     Postgresql(Postgresql),
+    /// A single file, for local experiments and tests
+    Sqlite(Sqlite),
 }
 
+#[derive(Debug, Clone)]
 pub struct Postgresql {
     pub url: String,
+    pub pool: PoolOptions,
+}
+
+/// Sizing of the Postgres connection pool.
+#[derive(Debug, Clone)]
+pub struct PoolOptions {
+    /// Connections open at most, queries beyond it wait for one
+    pub max_size: u32,
+    /// How long a query waits for a free connection before it fails
+    pub connection_timeout: Duration,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            max_size: 10,
+            connection_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sqlite {
+    /// Path of the database file, created if missing
+    pub path: String,
+}
+
+/// What happens at startup when the database schema differs
+/// from the migrations built into the binary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Migrations {
+    /// Applies pending migrations, only warns about unknown ones
+    #[default]
+    Apply,
+    /// Refuses to start unless every migration is applied and known
+    Verify,
 }
 
+/// Where the fetched files are stored.
+#[derive(Debug, Clone)]
+pub enum Storage {
+    /// An S3 compatible bucket (AWS, MinIO, ...)
+    S3(S3Info),
+    /// A local directory, for development and CI
+    Filesystem(Filesystem),
+}
+
+#[derive(Debug, Clone)]
 pub struct S3Info {
     pub endpoint: String,
     pub access_key: String,
     pub secret_key: String,
     pub bucket: String,
+    pub multipart: Multipart,
+}
+
+/// When and how objects are uploaded in parts. Large files are
+/// sent in several requests, and streams of unknown length
+/// don't have to be sent without a `Content-Length`.
+#[derive(Debug, Clone)]
+pub struct Multipart {
+    /// Objects larger than this many bytes are uploaded in parts
+    pub threshold: usize,
+    /// Bytes per part but the last, S3 requires at least 5 MiB
+    pub part_size: usize,
+    /// Parts uploaded at the same time
+    pub concurrency: usize,
+    /// Attempts at each part before the upload is aborted
+    pub part_attempts: u32,
+}
+
+impl Multipart {
+    /// The smallest part S3 accepts, only the last part may be smaller.
+    pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+    /// Checks the values that aren't checked by their type.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.part_size < Self::MIN_PART_SIZE {
+            return Err(format!(
+                "part size of {} bytes is below the {} bytes S3 requires",
+                self.part_size,
+                Self::MIN_PART_SIZE
+            ));
+        }
+        if self.concurrency == 0 {
+            return Err("concurrency must be at least 1".to_string());
+        }
+        if self.part_attempts == 0 {
+            return Err("part attempts must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for Multipart {
+    fn default() -> Self {
+        Self {
+            threshold: 8 * 1024 * 1024,
+            part_size: 8 * 1024 * 1024,
+            concurrency: 4,
+            part_attempts: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Filesystem {
+    /// Directory objects are written under, keys are used as relative paths
+    pub root: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multipart_validate() {
+        assert!(Multipart::default().validate().is_ok());
+        let smallest = Multipart {
+            part_size: Multipart::MIN_PART_SIZE,
+            ..Default::default()
+        };
+        assert!(smallest.validate().is_ok());
+
+        for invalid in [
+            Multipart {
+                part_size: Multipart::MIN_PART_SIZE - 1,
+                ..Default::default()
+            },
+            Multipart {
+                concurrency: 0,
+                ..Default::default()
+            },
+            Multipart {
+                part_attempts: 0,
+                ..Default::default()
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{invalid:?}");
+        }
+    }
 }
@@ -1,5 +1,283 @@
+use crate::config::BooleanQuery;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
 pub struct Fetcher {
     pub query: String,
     pub page_size: u64,
+    /// Stop after this many hits (with a PMCID) of the query, taken in
+    /// the order of `search.sort`. All are fetched if not set.
+    pub max_results: Option<u64>,
+    /// Options of the search requests
+    pub search: SearchOptions,
     pub upload_path_prefix: String,
+    /// Artifacts to fetch for each paper.
+    /// The first one that gets stored is the paper's primary artifact.
+    pub artifacts: Vec<Artifact>,
+    /// Download supplementary files of the stored papers,
+    /// the stage is skipped when not set.
+    pub supplementary: Option<Supplementary>,
+    /// Where to look for a paper's PDF, tried in order
+    /// until one of them serves an actual PDF.
+    pub pdf_sources: Vec<PdfSource>,
+    /// Hosts (and their subdomains) that PDFs may be downloaded from
+    /// when following a `fullTextUrlList` entry. Nothing outside of it is
+    /// followed, so links to paywalled publisher sites are never tried.
+    pub allowed_domains: Vec<String>,
+    /// Expand the corpus along the references and citations
+    /// of the stored papers, the stage is skipped when not set.
+    pub snowball: Option<Snowball>,
+    /// Record the references of every stored paper
+    /// as edges of the citation graph.
+    pub record_citations: bool,
+    /// How long a paper that is being stored when the run is cancelled
+    /// may take to finish, before it is abandoned and left for a resume.
+    pub shutdown_grace_period: Duration,
+    /// How long a paper may stay pending, i.e. claimed but not completely
+    /// stored, before it is taken for the leftover of a crashed attempt.
+    /// Such papers are removed along with their objects when a run starts.
+    pub pending_paper_timeout: Duration,
+    /// Where Europe PMC is reached, e.g. a mirror or a local stub server
+    pub endpoints: Endpoints,
+}
+
+/// URLs of the Europe PMC services. `{PMCID}` in the
+/// templates is replaced with the paper's PMCID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoints {
+    /// Search endpoint, the query and paging parameters are appended
+    pub search_url: String,
+    /// References and citations endpoint, with `{source}`, `{id}` and
+    /// `{direction}` placeholders, the paging parameters are appended
+    pub citations_url: String,
+    /// Template of the [`PdfSource::EuropePmcRender`] URL
+    pub pdf_url: String,
+    /// Template of the JATS XML URL
+    pub xml_url: String,
+    /// Template of the supplementary files archive URL
+    pub supplementary_url: String,
+    /// Extra query parameters of every search request, e.g. ones a
+    /// mirror needs. Europe PMC's own are in [`SearchOptions`]
+    pub search_params: Vec<(String, String)>,
+}
+
+impl Endpoints {
+    pub const REST_URL: &str = "https://www.ebi.ac.uk/europepmc/webservices/rest";
+    pub const SITE_URL: &str = "https://europepmc.org";
+
+    /// The endpoints of a server laid out like Europe PMC, with the REST
+    /// API under `rest_url` and the PDF renderer under `site_url`.
+    pub fn mirror(rest_url: &str, site_url: &str) -> Self {
+        let rest_url = rest_url.trim_end_matches('/');
+        let site_url = site_url.trim_end_matches('/');
+        Self {
+            search_url: format!("{rest_url}/search"),
+            citations_url: format!("{rest_url}/{{source}}/{{id}}/{{direction}}"),
+            pdf_url: format!("{site_url}/backend/ptpmcrender.fcgi?blobtype=pdf&accid={{PMCID}}"),
+            xml_url: format!("{rest_url}/{{PMCID}}/fullTextXML"),
+            supplementary_url: format!("{rest_url}/{{PMCID}}/supplementaryFiles"),
+            search_params: Vec::new(),
+        }
+    }
+
+    /// Fills the `{PMCID}` placeholder of a template.
+    pub fn url(template: &str, pmc_id: &str) -> String {
+        template.replace("{PMCID}", pmc_id)
+    }
+}
+
+/// Europe PMC itself.
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self::mirror(Self::REST_URL, Self::SITE_URL)
+    }
+}
+
+/// Options Europe PMC's search endpoint takes besides the query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    /// Order of the hits, by relevance if not set
+    pub sort: Option<Sort>,
+    /// Expand the query with synonyms (e.g. of MeSH terms),
+    /// Europe PMC's default applies if not set
+    pub synonym: Option<bool>,
+    /// Contact address sent along with every search, as asked
+    /// by Europe PMC's usage policy
+    pub email: Option<String>,
+}
+
+impl SearchOptions {
+    /// Checks the values that aren't checked by their type.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(email) = &self.email
+            && !is_email(email)
+        {
+            return Err(format!("invalid contact email `{email}`"));
+        }
+        Ok(())
+    }
+
+    /// The options as query parameters, leaving out the unset ones.
+    /// The result type is always `core`, the only one that has everything
+    /// the paper's metadata and the `fullTextUrlList` are taken from.
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![("resultType", "core".to_string())];
+        if let Some(sort) = &self.sort {
+            params.push(("sort", sort.to_string()));
+        }
+        if let Some(synonym) = self.synonym {
+            params.push(("synonym", synonym.to_string()));
+        }
+        if let Some(email) = &self.email {
+            params.push(("email", email.clone()));
+        }
+        params
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !value.contains(char::is_whitespace)
+        && !domain.contains('@')
+        && domain.split('.').count() > 1
+        && domain.split('.').all(|v| !v.is_empty())
+}
+
+/// A sort order Europe PMC accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sort {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+/// `FIELD asc|desc`, the form of the `sort` parameter.
+impl Display for Sort {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let order = if self.descending { "desc" } else { "asc" };
+        write!(f, "{} {order}", self.field.as_str())
+    }
+}
+
+/// Fields the hits can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    /// Number of citations
+    Cited,
+    /// Publication date
+    PublicationDate,
+    /// Date of the first publication, print or electronic
+    FirstPublicationDate,
+}
+
+impl SortField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortField::Cited => "CITED",
+            SortField::PublicationDate => "P_PDATE_D",
+            SortField::FirstPublicationDate => "FIRST_PDATE_D",
+        }
+    }
+}
+
+/// A file Europe PMC can provide for a paper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Artifact {
+    /// Rendered PDF
+    Pdf,
+    /// JATS full-text XML
+    Xml,
+}
+
+/// A place a paper's PDF can be downloaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PdfSource {
+    /// Europe PMC's `ptpmcrender.fcgi` renderer
+    EuropePmcRender,
+    /// Entries of the search result's `fullTextUrlList` from the given
+    /// site, e.g. `Europe_PMC`, `PubMedCentral` or `DOI`.
+    FullTextUrl { site: String },
+}
+
+/// Limits of the supplementary material stage.
+pub struct Supplementary {
+    /// Files larger than this (uncompressed, in bytes) are skipped
+    pub max_file_size: u64,
+    /// Lowercase extensions (without the dot) of the files to keep,
+    /// every file is kept if empty.
+    pub allowed_extensions: Vec<String>,
+}
+
+/// Limits of the citation snowballing stage.
+pub struct Snowball {
+    /// How many citation hops away from the query's papers to go
+    pub depth: u32,
+    /// Which edges to follow
+    pub directions: Vec<CitationDirection>,
+    /// Only papers also matching this query are kept
+    pub filter: Option<BooleanQuery>,
+    /// Stop listing the references (or citations) of a
+    /// paper after this many, all are listed if not set.
+    pub max_per_paper: Option<u64>,
+}
+
+/// An edge of the citation graph, seen from the paper it starts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CitationDirection {
+    /// Papers the paper cites
+    References,
+    /// Papers citing the paper
+    Citations,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_params() {
+        assert_eq!(
+            SearchOptions::default().params(),
+            vec![("resultType", "core".to_string())]
+        );
+
+        let options = SearchOptions {
+            sort: Some(Sort {
+                field: SortField::Cited,
+                descending: true,
+            }),
+            synonym: Some(false),
+            email: Some("lab@example.org".to_string()),
+        };
+        assert_eq!(
+            options.params(),
+            vec![
+                ("resultType", "core".to_string()),
+                ("sort", "CITED desc".to_string()),
+                ("synonym", "false".to_string()),
+                ("email", "lab@example.org".to_string()),
+            ]
+        );
+        assert!(options.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_email() {
+        for email in [
+            "",
+            "lab",
+            "@example.org",
+            "lab@example",
+            "lab@.org",
+            "a b@example.org",
+        ] {
+            let options = SearchOptions {
+                email: Some(email.to_string()),
+                ..Default::default()
+            };
+            assert!(options.validate().is_err(), "{email}");
+        }
+    }
 }
@@ -2,3 +2,4 @@ mod cm_blueprint;
 mod connections;
 
 pub use cm_blueprint::*;
+pub use connections::*;
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -111,6 +112,7 @@ impl BooleanQuery {
     }
 
     /// Create a NOT query
+    #[allow(clippy::should_implement_trait)]
     pub fn not(query: BooleanQuery) -> Self {
         BooleanQuery::Not(NotQuery {
             query: Box::new(query),
@@ -236,9 +238,11 @@ impl BooleanQuery {
             }
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
-        self.to_string_inner().replace(" ", "+")
+impl Display for BooleanQuery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_string_inner().replace(" ", "+"))
     }
 }
 
@@ -0,0 +1,122 @@
+use cortexmap_infra::ContentStream;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Object metadata key holding the hex encoded SHA-256 digest.
+pub const SHA256_METADATA_KEY: &str = "sha256";
+/// Object metadata key holding the size in bytes.
+pub const SIZE_METADATA_KEY: &str = "size-bytes";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    /// Hex encoded SHA-256 digest
+    pub sha256: String,
+    pub size_bytes: u64,
+}
+
+/// Gives access to the checksum of a [`checksum`] stream
+/// once it was consumed to the end.
+pub struct ChecksumHandle {
+    result: Arc<Mutex<Option<Checksum>>>,
+}
+
+impl ChecksumHandle {
+    /// `None` if the stream failed or wasn't consumed completely.
+    pub fn get(&self) -> Option<Checksum> {
+        self.result.lock().unwrap().clone()
+    }
+}
+
+/// Object metadata describing the checksum, see [`SHA256_METADATA_KEY`].
+pub fn checksum_metadata(checksum: &Checksum) -> HashMap<String, String> {
+    HashMap::from([
+        (SHA256_METADATA_KEY.to_string(), checksum.sha256.clone()),
+        (
+            SIZE_METADATA_KEY.to_string(),
+            checksum.size_bytes.to_string(),
+        ),
+    ])
+}
+
+struct ChecksumState {
+    inner: ContentStream,
+    hasher: Option<Sha256>,
+    size_bytes: u64,
+    result: Arc<Mutex<Option<Checksum>>>,
+}
+
+/// Hashes the content while it passes through,
+/// so the file never has to be held in memory.
+pub fn checksum(stream: ContentStream) -> (ContentStream, ChecksumHandle) {
+    let result = Arc::new(Mutex::new(None));
+    let state = ChecksumState {
+        inner: stream,
+        hasher: Some(Sha256::new()),
+        size_bytes: 0,
+        result: result.clone(),
+    };
+
+    let stream = futures::stream::unfold(state, |mut state| async move {
+        // The hasher is gone once the stream ended or failed.
+        let hasher = state.hasher.as_mut()?;
+        match state.inner.next().await {
+            Some(Ok(chunk)) => {
+                hasher.update(&chunk);
+                state.size_bytes += chunk.len() as u64;
+                Some((Ok(chunk), state))
+            }
+            Some(Err(e)) => {
+                state.hasher = None;
+                Some((Err(e), state))
+            }
+            None => {
+                let hasher = state.hasher.take()?;
+                *state.result.lock().unwrap() = Some(Checksum {
+                    sha256: format!("{:x}", hasher.finalize()),
+                    size_bytes: state.size_bytes,
+                });
+                None
+            }
+        }
+    });
+
+    (Box::pin(stream), ChecksumHandle { result })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use cortexmap_infra::InfraError;
+
+    #[tokio::test]
+    async fn test_checksum_across_chunks() {
+        let chunks = vec![Ok(Bytes::from("hello ")), Ok(Bytes::from("world"))];
+        let (stream, handle) = checksum(Box::pin(futures::stream::iter(chunks)));
+        assert_eq!(handle.get(), None);
+
+        stream.collect::<Vec<_>>().await;
+        assert_eq!(
+            handle.get(),
+            Some(Checksum {
+                sha256: "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+                    .to_string(),
+                size_bytes: 11,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_checksum_failed_stream() {
+        let chunks = vec![
+            Ok(Bytes::from("hello ")),
+            Err(InfraError::StreamAborted("connection reset".to_string())),
+        ];
+        let (stream, handle) = checksum(Box::pin(futures::stream::iter(chunks)));
+
+        stream.collect::<Vec<_>>().await;
+        assert_eq!(handle.get(), None);
+    }
+}
@@ -6,15 +6,41 @@ pub enum FetchError {
     #[error("Infra Error: {0}")]
     InfraError(#[from] InfraError),
 
-    #[error("Reqwest Error: {0}")]
-    ReqwestError(#[from] reqwest::Error),
-
     #[error("Serde Error: {0}")]
     SerdeError(#[from] serde_json::Error),
 
+    #[error("Zip Error: {0}")]
+    ZipError(#[from] async_zip::error::ZipError),
+
+    #[error("IO Error: {0}")]
+    IoError(#[from] std::io::Error),
+
     #[error("Join Error: {0}")]
     JoinError(tokio::task::JoinError),
 
     #[error("Invalid PDF Source: {0}")]
     InvalidPdfSource(String),
+
+    #[error("Invalid XML Source: {0}")]
+    InvalidXmlSource(String),
+
+    #[error("No artifact could be stored for paper: {0}")]
+    NoArtifacts(String),
+
+    #[error("Paper not found: {0}")]
+    PaperNotFound(String),
+
+    /// A value read from the database can't describe a stored object.
+    #[error("Invalid record: {0}")]
+    InvalidRecord(String),
+
+    #[error("Fetch run not found: {0}")]
+    RunNotFound(i64),
+
+    #[error("Invalid search options: {0}")]
+    InvalidSearchOptions(String),
+
+    /// The run was cancelled, what is left of it can be resumed.
+    #[error("Cancelled")]
+    Cancelled,
 }
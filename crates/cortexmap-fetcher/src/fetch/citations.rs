@@ -0,0 +1,178 @@
+use crate::FetchError;
+use cortexmap_core::blueprint::{CitationDirection, Endpoints};
+use cortexmap_infra::{HttpInfra, InfraContext};
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+
+/// Largest page the references and citations endpoints serve.
+const MAX_PAGE_SIZE: u64 = 1000;
+
+/// A record of the Europe PMC database, identified the way
+/// the references and citations endpoints expect it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorkId {
+    /// e.g. `MED` for PubMed or `PMC`
+    pub source: String,
+    pub id: String,
+}
+
+impl WorkId {
+    pub fn pmc(pmc_id: &str) -> Self {
+        Self {
+            source: "PMC".to_string(),
+            id: pmc_id.to_string(),
+        }
+    }
+
+    pub fn med(pmid: &str) -> Self {
+        Self {
+            source: "MED".to_string(),
+            id: pmid.to_string(),
+        }
+    }
+}
+
+/// `SOURCE:ID`, the form works are recorded in the citation graph with.
+impl Display for WorkId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.source, self.id)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CitationPage {
+    #[serde(default)]
+    hit_count: u64,
+    reference_list: Option<ReferenceList>,
+    citation_list: Option<CitationList>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReferenceList {
+    #[serde(default)]
+    reference: Vec<CitedWork>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CitationList {
+    #[serde(default)]
+    citation: Vec<CitedWork>,
+}
+
+/// Only the identifiers are kept, unmatched
+/// references have neither of them.
+#[derive(Debug, Deserialize)]
+struct CitedWork {
+    id: Option<String>,
+    source: Option<String>,
+}
+
+impl CitationPage {
+    fn into_works(self) -> Vec<Option<WorkId>> {
+        let works = match (self.reference_list, self.citation_list) {
+            (Some(v), _) => v.reference,
+            (_, Some(v)) => v.citation,
+            (None, None) => Vec::new(),
+        };
+        works
+            .into_iter()
+            .map(|v| {
+                Some(WorkId {
+                    source: v.source?,
+                    id: v.id?,
+                })
+            })
+            .collect()
+    }
+}
+
+fn direction_path(direction: CitationDirection) -> &'static str {
+    match direction {
+        CitationDirection::References => "references",
+        CitationDirection::Citations => "citations",
+    }
+}
+
+/// Lists the works `work` cites or is cited by, at most `limit` of them.
+pub async fn fetch_citations<I: HttpInfra>(
+    work: &WorkId,
+    direction: CitationDirection,
+    limit: Option<u64>,
+    endpoints: &Endpoints,
+    ctx: InfraContext<I>,
+) -> Result<Vec<WorkId>, FetchError> {
+    let page_size = limit.unwrap_or(MAX_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let mut works = Vec::new();
+    let mut seen = 0;
+    for page in 1.. {
+        let url = format!(
+            "{}?format=json&pageSize={page_size}&page={page}",
+            endpoints
+                .citations_url
+                .replace("{source}", &work.source)
+                .replace("{id}", &work.id)
+                .replace("{direction}", direction_path(direction))
+        );
+        let resp = ctx.infra.get(&url).await?;
+        let body: CitationPage = serde_json::from_slice(&resp.bytes().await?)?;
+        let hit_count = body.hit_count;
+
+        let page_works = body.into_works();
+        if page_works.is_empty() {
+            break;
+        }
+        seen += page_works.len() as u64;
+        works.extend(page_works.into_iter().flatten());
+
+        if seen >= hit_count || limit.is_some_and(|v| seen >= v) {
+            break;
+        }
+    }
+    if let Some(limit) = limit {
+        works.truncate(limit as usize);
+    }
+
+    Ok(works)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_references_page() {
+        let page: CitationPage = serde_json::from_str(
+            r#"{"version": "6.9", "hitCount": 3, "request": {"id": "PMC1", "source": "PMC"},
+                "referenceList": {"reference": [
+                    {"id": "10856286", "source": "MED", "citationType": "JOURNAL ARTICLE"},
+                    {"title": "An unmatched reference"},
+                    {"id": "PMC2", "source": "PMC"}
+                ]}}"#,
+        )
+        .unwrap();
+        assert_eq!(page.hit_count, 3);
+        let works = page.into_works();
+        assert_eq!(works.len(), 3);
+        assert_eq!(
+            works[0],
+            Some(WorkId {
+                source: "MED".to_string(),
+                id: "10856286".to_string()
+            })
+        );
+        assert_eq!(works[1], None);
+    }
+
+    #[test]
+    fn test_citations_page() {
+        let page: CitationPage = serde_json::from_str(
+            r#"{"hitCount": 1, "citationList": {"citation": [{"id": "123", "source": "MED"}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(page.into_works().into_iter().flatten().count(), 1);
+
+        let empty: CitationPage = serde_json::from_str(r#"{"hitCount": 0}"#).unwrap();
+        assert!(empty.into_works().is_empty());
+    }
+}
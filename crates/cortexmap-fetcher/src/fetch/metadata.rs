@@ -1,37 +1,400 @@
 use crate::FetchError;
-use cortexmap_core::blueprint::Blueprint;
-use cortexmap_infra::{HttpInfra, InfraContext};
-use serde::Deserialize;
-
-const PUBMOD_URL: &str = "https://www.ebi.ac.uk/europepmc/webservices/rest/search?format=json&pageSize={pageSize}&query={query}";
+use cortexmap_core::blueprint::{Endpoints, SearchOptions};
+use cortexmap_infra::{
+    Bibliography, HttpInfra, InfraContext, MeshHeading, PaperAuthor, PaperKeyword, PaperMetadata,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 #[derive(Debug, Deserialize)]
 pub struct PMCIDs {
+    #[serde(rename = "hitCount", default)]
+    pub hit_count: u64,
+    /// Cursor of the following page, equal to the requested
+    /// one (or missing) once the last page was reached
+    #[serde(rename = "nextCursorMark", default)]
+    pub next_cursor_mark: Option<String>,
     #[serde(rename = "resultList")]
     pub result: SearchResult,
 }
 
+impl PMCIDs {
+    /// Cursor of the page after the one fetched with `cursor_mark`,
+    /// `None` if this was the last one.
+    pub fn next_page(&self, cursor_mark: &str) -> Option<String> {
+        self.next_cursor_mark
+            .clone()
+            .filter(|v| v != cursor_mark && !self.result.result.is_empty())
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SearchResult {
     pub result: Vec<SearchData>,
 }
-#[derive(Debug, Deserialize)]
+
+/// A single search hit, with the fields of the `core` result type we keep.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct SearchData {
     #[serde(default)]
     pub pmcid: Option<String>,
+    pub pmid: Option<String>,
+    pub doi: Option<String>,
+    pub title: Option<String>,
+    pub abstract_text: Option<String>,
+    pub author_list: Option<AuthorList>,
+    pub journal_info: Option<JournalInfo>,
+    /// `YYYY-MM-DD`
+    pub first_publication_date: Option<String>,
+    pub mesh_heading_list: Option<MeshHeadingList>,
+    pub keyword_list: Option<KeywordList>,
+    pub license: Option<String>,
+    /// `Y` or `N`
+    pub is_open_access: Option<String>,
+    pub full_text_url_list: Option<FullTextUrlList>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AuthorList {
+    #[serde(default)]
+    pub author: Vec<Author>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Author {
+    /// Missing for collective authors, which only have `collectiveName`
+    pub full_name: Option<String>,
+    pub collective_name: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub author_id: Option<AuthorId>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AuthorId {
+    /// e.g. `ORCID`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalInfo {
+    pub journal: Option<Journal>,
+    /// `YYYY-MM-DD`
+    pub print_publication_date: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Journal {
+    pub title: Option<String>,
+    pub issn: Option<String>,
+    pub essn: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeshHeadingList {
+    #[serde(default)]
+    pub mesh_heading: Vec<MeshHeadingData>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeshHeadingData {
+    pub descriptor_name: String,
+    /// `Y` or `N`
+    #[serde(rename = "majorTopic_YN")]
+    pub major_topic: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct KeywordList {
+    #[serde(default)]
+    pub keyword: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FullTextUrlList {
+    #[serde(default)]
+    pub full_text_url: Vec<FullTextUrl>,
+}
+
+/// Another location of the paper's full text.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FullTextUrl {
+    /// `OA` (open access), `F` (free), `S` (subscription), ...
+    pub availability_code: Option<String>,
+    /// `pdf`, `html`, `doi`, ...
+    pub document_style: Option<String>,
+    /// `Europe_PMC`, `PubMedCentral`, `DOI`, ...
+    pub site: Option<String>,
+    pub url: String,
+}
+
+impl SearchData {
+    /// Splits the search hit into the rows of the metadata tables.
+    pub fn to_bibliography(&self, paper_id: i64) -> Bibliography {
+        let journal = self.journal_info.as_ref();
+        let publication_date = self
+            .first_publication_date
+            .as_deref()
+            .or_else(|| journal?.print_publication_date.as_deref())
+            .and_then(|date| chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+        let journal = journal.and_then(|v| v.journal.as_ref());
+
+        let metadata = PaperMetadata {
+            paper_id,
+            title: self.title.clone(),
+            abstract_text: self.abstract_text.clone(),
+            journal_title: journal.and_then(|v| v.title.clone()),
+            journal_issn: journal.and_then(|v| v.issn.clone().or_else(|| v.essn.clone())),
+            publication_date,
+            doi: self.doi.clone(),
+            pmid: self.pmid.clone(),
+            license: self.license.clone(),
+            is_open_access: self.is_open_access.as_deref().map(is_yes),
+        };
+
+        let authors = self
+            .author_list
+            .iter()
+            .flat_map(|v| &v.author)
+            .filter_map(|author| {
+                let full_name = author
+                    .full_name
+                    .clone()
+                    .or_else(|| author.collective_name.clone())?;
+                let orcid = author
+                    .author_id
+                    .as_ref()
+                    .filter(|id| id.kind.eq_ignore_ascii_case("ORCID"))
+                    .map(|id| id.value.clone());
+                Some((full_name, author, orcid))
+            })
+            .enumerate()
+            .map(|(position, (full_name, author, orcid))| PaperAuthor {
+                paper_id,
+                position: position as i32,
+                full_name,
+                first_name: author.first_name.clone(),
+                last_name: author.last_name.clone(),
+                orcid,
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        let mesh_headings = self
+            .mesh_heading_list
+            .iter()
+            .flat_map(|v| &v.mesh_heading)
+            .filter(|heading| seen.insert(heading.descriptor_name.clone()))
+            .map(|heading| MeshHeading {
+                paper_id,
+                descriptor_name: heading.descriptor_name.clone(),
+                major_topic: heading.major_topic.as_deref().is_some_and(is_yes),
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        let keywords = self
+            .keyword_list
+            .iter()
+            .flat_map(|v| &v.keyword)
+            .filter(|keyword| seen.insert(keyword.to_string()))
+            .map(|keyword| PaperKeyword {
+                paper_id,
+                keyword: keyword.clone(),
+            })
+            .collect();
+
+        Bibliography {
+            metadata,
+            authors,
+            mesh_headings,
+            keywords,
+        }
+    }
+}
+
+fn is_yes(value: &str) -> bool {
+    value.eq_ignore_ascii_case("Y")
 }
 
+/// Fetches the page of search results at `cursor_mark`,
+/// `*` being the first one.
 pub async fn fetch_metadata<I: HttpInfra>(
-    blueprint: &Blueprint,
+    query: &str,
+    page_size: u64,
+    cursor_mark: &str,
+    options: &SearchOptions,
+    endpoints: &Endpoints,
     ctx: InfraContext<I>,
 ) -> Result<PMCIDs, FetchError> {
-    let url = PUBMOD_URL
-        .replace("{query}", blueprint.fetcher.query.as_str())
-        .replace(
-            "{pageSize}",
-            blueprint.fetcher.page_size.to_string().as_str(),
-        );
+    let url = search_url(query, page_size, cursor_mark, options, endpoints);
     let resp = ctx.infra.get(&url).await?;
     let body = serde_json::from_slice(&resp.bytes().await?)?;
     Ok(body)
 }
+
+/// The query is expected to be encoded already, like
+/// the [`Display`](std::fmt::Display) of a `BooleanQuery`.
+fn search_url(
+    query: &str,
+    page_size: u64,
+    cursor_mark: &str,
+    options: &SearchOptions,
+    endpoints: &Endpoints,
+) -> String {
+    let mut params = url::form_urlencoded::Serializer::new(String::new());
+    params
+        .append_pair("format", "json")
+        .append_pair("pageSize", &page_size.to_string())
+        .append_pair("cursorMark", cursor_mark);
+    for (name, value) in options.params() {
+        params.append_pair(name, &value);
+    }
+    for (name, value) in &endpoints.search_params {
+        params.append_pair(name, value);
+    }
+
+    format!("{}?{}&query={query}", endpoints.search_url, params.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cortexmap_core::blueprint::{Sort, SortField};
+
+    const CORE_RESULT: &str = r#"{
+        "id": "12345",
+        "source": "MED",
+        "pmid": "12345",
+        "pmcid": "PMC67890",
+        "doi": "10.1000/xyz123",
+        "title": "Motor cortex dynamics in mice",
+        "authorString": "Doe J, Roe R, Brain Consortium.",
+        "authorList": {"author": [
+            {"fullName": "Doe J", "firstName": "Jane", "lastName": "Doe", "initials": "J",
+             "authorId": {"type": "ORCID", "value": "0000-0002-1825-0097"}},
+            {"fullName": "Roe R", "firstName": "Richard", "lastName": "Roe", "initials": "R"},
+            {"collectiveName": "Brain Consortium"}
+        ]},
+        "journalInfo": {
+            "volume": "12",
+            "printPublicationDate": "2024-02-01",
+            "journal": {"title": "Journal of Neuroscience", "issn": "0270-6474", "essn": "1529-2401"}
+        },
+        "abstractText": "We recorded M1 activity.",
+        "isOpenAccess": "Y",
+        "license": "cc by",
+        "firstPublicationDate": "2024-01-15",
+        "meshHeadingList": {"meshHeading": [
+            {"majorTopic_YN": "Y", "descriptorName": "Motor Cortex"},
+            {"majorTopic_YN": "N", "descriptorName": "Mice"},
+            {"majorTopic_YN": "N", "descriptorName": "Mice"}
+        ]},
+        "keywordList": {"keyword": ["optogenetics", "M1"]}
+    }"#;
+
+    #[test]
+    fn test_core_result_to_bibliography() {
+        let data: SearchData = serde_json::from_str(CORE_RESULT).unwrap();
+        assert_eq!(data.pmcid.as_deref(), Some("PMC67890"));
+
+        let bibliography = data.to_bibliography(7);
+        let metadata = bibliography.metadata;
+        assert_eq!(metadata.paper_id, 7);
+        assert_eq!(
+            metadata.journal_title.as_deref(),
+            Some("Journal of Neuroscience")
+        );
+        assert_eq!(metadata.journal_issn.as_deref(), Some("0270-6474"));
+        assert_eq!(
+            metadata.publication_date,
+            chrono::NaiveDate::from_ymd_opt(2024, 1, 15)
+        );
+        assert_eq!(metadata.is_open_access, Some(true));
+
+        let authors = bibliography.authors;
+        assert_eq!(authors.len(), 3);
+        assert_eq!(authors[0].orcid.as_deref(), Some("0000-0002-1825-0097"));
+        assert_eq!(authors[1].orcid, None);
+        assert_eq!(authors[2].full_name, "Brain Consortium");
+        assert_eq!(authors[2].position, 2);
+
+        assert_eq!(bibliography.mesh_headings.len(), 2);
+        assert!(bibliography.mesh_headings[0].major_topic);
+        assert_eq!(bibliography.keywords.len(), 2);
+    }
+
+    #[test]
+    fn test_search_url() {
+        let mut endpoints = Endpoints::mirror("http://localhost:8080/rest/", "");
+        let options = SearchOptions::default();
+        assert_eq!(
+            search_url("\"motor+cortex\"", 25, "AoE/1+2=", &options, &endpoints),
+            "http://localhost:8080/rest/search?format=json&pageSize=25\
+             &cursorMark=AoE%2F1%2B2%3D&resultType=core&query=\"motor+cortex\""
+        );
+
+        endpoints.search_params = vec![("fields".to_string(), "pmcid".to_string())];
+        let options = SearchOptions {
+            sort: Some(Sort {
+                field: SortField::FirstPublicationDate,
+                descending: false,
+            }),
+            email: Some("lab@example.org".to_string()),
+            ..Default::default()
+        };
+        assert!(
+            search_url("cortex", 25, "*", &options, &endpoints).ends_with(
+                "&resultType=core&sort=FIRST_PDATE_D+asc&email=lab%40example.org\
+                 &fields=pmcid&query=cortex"
+            )
+        );
+    }
+
+    #[test]
+    fn test_search_page_cursor() {
+        let page: PMCIDs = serde_json::from_str(
+            r#"{"version": "6.9", "hitCount": 2, "nextCursorMark": "AoIIP4AAACg0MTM3NTA4Ng==",
+                "resultList": {"result": [{"id": "1", "pmcid": "PMC1"}, {"id": "2"}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(page.hit_count, 2);
+        assert_eq!(
+            page.next_cursor_mark.as_deref(),
+            Some("AoIIP4AAACg0MTM3NTA4Ng==")
+        );
+        assert_eq!(page.result.result.len(), 2);
+        assert_eq!(
+            page.next_page("*").as_deref(),
+            Some("AoIIP4AAACg0MTM3NTA4Ng==")
+        );
+        assert_eq!(page.next_page("AoIIP4AAACg0MTM3NTA4Ng=="), None);
+    }
+
+    #[test]
+    fn test_search_record_round_trip() {
+        let data: SearchData = serde_json::from_str(CORE_RESULT).unwrap();
+        let record = serde_json::to_string(&data).unwrap();
+        let data: SearchData = serde_json::from_str(&record).unwrap();
+        let bibliography = data.to_bibliography(7);
+        assert_eq!(bibliography.authors.len(), 3);
+        assert_eq!(bibliography.metadata.doi.as_deref(), Some("10.1000/xyz123"));
+    }
+
+    #[test]
+    fn test_lite_result() {
+        let data: SearchData = serde_json::from_str(r#"{"id": "1", "pmcid": "PMC1"}"#).unwrap();
+        let bibliography = data.to_bibliography(1);
+        assert_eq!(bibliography.metadata.title, None);
+        assert!(bibliography.authors.is_empty());
+    }
+}
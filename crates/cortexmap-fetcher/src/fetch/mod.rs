@@ -1,2 +1,304 @@
+pub mod citations;
 pub mod metadata;
 pub mod pdf;
+pub mod supplementary;
+pub mod xml;
+
+use crate::FetchError;
+use crate::fetch::metadata::SearchData;
+use cortexmap_core::blueprint::{Artifact, CitationDirection, Endpoints, Fetcher};
+use cortexmap_infra::{ContentStream, HttpInfra, InfraContext, ResponseCM};
+
+pub struct ArtifactStream {
+    pub stream: ContentStream,
+    pub pmc_id: String,
+    pub artifact: Artifact,
+    /// URL the artifact is downloaded from
+    pub source_url: String,
+    /// Value of the `Content-Length` header, if the server sent one.
+    pub content_length: Option<u64>,
+    /// Candidate URLs that weren't tried, in order, for when this
+    /// one turns out not to be a complete file while uploading
+    pub fallback: Vec<String>,
+}
+
+/// All artifacts that could be fetched for a paper.
+pub struct PaperStreams {
+    pub pmc_id: String,
+    pub artifacts: Vec<ArtifactStream>,
+    /// Search hit the paper was found with, if any
+    pub metadata: Option<SearchData>,
+    pub origin: Origin,
+}
+
+/// How a paper was reached.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Origin {
+    /// A hit of the harvest query
+    #[default]
+    Query,
+    /// Found along an edge of the citation graph while snowballing
+    Citation {
+        /// PMCID of the paper the edge starts from
+        from: String,
+        direction: CitationDirection,
+        /// Hops away from the query's papers, starting at 1
+        depth: u32,
+    },
+}
+
+impl Origin {
+    /// Value of the `reached_via` column
+    pub fn reached_via(&self) -> &'static str {
+        match self {
+            Origin::Query => "query",
+            Origin::Citation {
+                direction: CitationDirection::References,
+                ..
+            } => "reference",
+            Origin::Citation {
+                direction: CitationDirection::Citations,
+                ..
+            } => "citation",
+        }
+    }
+}
+
+/// What to fetch for a paper, resolved from the blueprint and the search hit.
+pub struct PaperRequest {
+    pub pmc_id: String,
+    pub artifacts: Vec<Artifact>,
+    /// Candidate URLs of the PDF, see [`pdf::pdf_candidates`]
+    pub pdf_urls: Vec<String>,
+    /// Hosts the PDF downloads may be redirected to
+    pub allowed_domains: Vec<String>,
+    pub xml_url: String,
+    /// Search hit the paper was found with, if any
+    pub metadata: Option<SearchData>,
+    pub origin: Origin,
+}
+
+impl PaperRequest {
+    pub fn new(pmc_id: String, metadata: Option<SearchData>, fetcher: &Fetcher) -> Self {
+        let pdf_urls = pdf::pdf_candidates(
+            &pmc_id,
+            metadata.as_ref(),
+            &fetcher.pdf_sources,
+            &fetcher.allowed_domains,
+            &fetcher.endpoints,
+        );
+        Self {
+            xml_url: Endpoints::url(&fetcher.endpoints.xml_url, &pmc_id),
+            pmc_id,
+            artifacts: fetcher.artifacts.clone(),
+            pdf_urls,
+            allowed_domains: fetcher.allowed_domains.clone(),
+            metadata,
+            origin: Origin::Query,
+        }
+    }
+}
+
+pub async fn fetch_artifact<I: HttpInfra + Send + Sync + 'static>(
+    request: &PaperRequest,
+    artifact: Artifact,
+    ctx: InfraContext<I>,
+) -> Result<ArtifactStream, FetchError> {
+    let pmc_id = request.pmc_id.clone();
+    match artifact {
+        Artifact::Pdf => {
+            let candidates = request.pdf_urls.clone();
+            pdf::fetch_pdf(pmc_id, candidates, &request.allowed_domains, ctx).await
+        }
+        Artifact::Xml => xml::fetch_xml(pmc_id, request.xml_url.clone(), ctx).await,
+    }
+}
+
+/// Fetches the requested artifacts of a paper,
+/// skipping the ones that aren't available.
+pub async fn fetch_artifacts<I: HttpInfra + Send + Sync + 'static>(
+    request: PaperRequest,
+    ctx: InfraContext<I>,
+) -> PaperStreams {
+    let mut streams = Vec::with_capacity(request.artifacts.len());
+    for &artifact in &request.artifacts {
+        match fetch_artifact(&request, artifact, ctx.clone()).await {
+            Ok(stream) => streams.push(stream),
+            Err(e) => tracing::warn!("Skipping {artifact:?} of paper {}: {e}", request.pmc_id),
+        }
+    }
+
+    PaperStreams {
+        pmc_id: request.pmc_id,
+        artifacts: streams,
+        metadata: request.metadata,
+        origin: request.origin,
+    }
+}
+
+pub(crate) fn invalid_source(artifact: Artifact, reason: String) -> FetchError {
+    match artifact {
+        Artifact::Pdf => FetchError::InvalidPdfSource(reason),
+        Artifact::Xml => FetchError::InvalidXmlSource(reason),
+    }
+}
+
+/// Turns the response into an [`ArtifactStream`] after checking that
+/// its `Content-Type` (if any) is one of `accepted`.
+pub(crate) fn into_artifact_stream(
+    pmc_id: String,
+    artifact: Artifact,
+    source_url: String,
+    response: ResponseCM,
+    accepted: &[&str],
+) -> Result<ArtifactStream, FetchError> {
+    // Europe PMC answers some requests with an HTML error page and status 200.
+    if let Some(content_type) = response.content_type()
+        && !is_content_type(content_type, accepted)
+    {
+        return Err(invalid_source(
+            artifact,
+            format!("{pmc_id}: unexpected content type `{content_type}`"),
+        ));
+    }
+    let content_length = response.content_length();
+
+    Ok(ArtifactStream {
+        stream: response.into_stream(),
+        pmc_id,
+        artifact,
+        source_url,
+        content_length,
+        fallback: Vec::new(),
+    })
+}
+
+fn is_content_type(content_type: &str, accepted: &[&str]) -> bool {
+    // Strip parameters like `; charset=binary`
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    accepted.iter().any(|v| v.eq_ignore_ascii_case(media_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pdf_content_types() {
+        let accepted = pdf::PDF_CONTENT_TYPES.as_slice();
+        assert!(is_content_type("application/pdf", accepted));
+        assert!(is_content_type("Application/PDF; charset=binary", accepted));
+        assert!(is_content_type("application/octet-stream", accepted));
+        assert!(!is_content_type("text/html; charset=UTF-8", accepted));
+        assert!(!is_content_type("", accepted));
+    }
+
+    #[test]
+    fn test_xml_content_types() {
+        let accepted = xml::XML_CONTENT_TYPES.as_slice();
+        assert!(is_content_type("application/xml;charset=UTF-8", accepted));
+        assert!(is_content_type("text/xml", accepted));
+        assert!(!is_content_type("text/html", accepted));
+    }
+
+    /// Runs the fixture queries against Europe PMC, replayed from
+    /// `cassettes/`. Record them with `CORTEXMAP_CASSETTES=record`.
+    #[tokio::test]
+    #[ignore = "needs the cassettes, record them with \
+                CORTEXMAP_CASSETTES=record cargo test -p cortexmap-fetcher \
+                test_fixture_cassettes -- --ignored"]
+    async fn test_fixture_cassettes() {
+        use crate::validate::validate;
+        use cortexmap_core::blueprint::{PdfSource, SearchOptions};
+        use cortexmap_core::config::Config;
+        use cortexmap_infra::FetchRun;
+        use futures::TryStreamExt;
+        use mock_infra::{CassetteHttpInfra, CassetteMode};
+        use std::collections::HashSet;
+        use std::path::Path;
+        use std::sync::Arc;
+        use std_infra::StdHttpInfra;
+
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let fixtures = manifest_dir.join("../cortexmap-core/src/fixtures");
+        let cassettes = manifest_dir.join("cassettes");
+        let mode = CassetteMode::from_env();
+        if mode == CassetteMode::Record {
+            std::fs::create_dir_all(&cassettes).unwrap();
+        }
+        let options = SearchOptions::default();
+        let endpoints = Endpoints::default();
+
+        for entry in std::fs::read_dir(fixtures).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            let config = Config::from_yaml(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            let query = config.query.unwrap().to_string();
+            let http = CassetteHttpInfra::new(
+                StdHttpInfra::new(),
+                cassettes.join(format!("{name}.json")),
+                mode,
+            )
+            .unwrap();
+            let ctx = InfraContext {
+                infra: Arc::new(http),
+            };
+
+            let first = metadata::fetch_metadata(
+                &query,
+                5,
+                FetchRun::FIRST_CURSOR,
+                &options,
+                &endpoints,
+                ctx.clone(),
+            )
+            .await
+            .unwrap();
+            assert!(first.result.result.len() <= 5, "{name}");
+            if let Some(cursor) = first.next_page(FetchRun::FIRST_CURSOR) {
+                let second =
+                    metadata::fetch_metadata(&query, 5, &cursor, &options, &endpoints, ctx.clone())
+                        .await
+                        .unwrap();
+                let seen = first
+                    .result
+                    .result
+                    .iter()
+                    .filter_map(|v| v.pmid.as_deref().or(v.pmcid.as_deref()))
+                    .collect::<HashSet<_>>();
+                assert!(
+                    second
+                        .result
+                        .result
+                        .iter()
+                        .filter_map(|v| v.pmid.as_deref().or(v.pmcid.as_deref()))
+                        .all(|v| !seen.contains(v)),
+                    "{name}"
+                );
+            }
+
+            let open_access = first
+                .result
+                .result
+                .iter()
+                .find(|v| v.pmcid.is_some() && v.is_open_access.as_deref() == Some("Y"));
+            if let Some(hit) = open_access {
+                let pmc_id = hit.pmcid.clone().unwrap();
+                let candidates = pdf::pdf_candidates(
+                    &pmc_id,
+                    Some(hit),
+                    &[PdfSource::EuropePmcRender],
+                    &[],
+                    &endpoints,
+                );
+                let artifact = pdf::fetch_pdf(pmc_id, candidates, &[], ctx.clone())
+                    .await
+                    .unwrap();
+                let (stream, verdict) = validate(artifact);
+                let content = stream.map_ok(|v| v.to_vec()).try_concat().await.unwrap();
+                assert!(verdict.failure().is_none(), "{name}");
+                assert!(content.starts_with(b"%PDF-"), "{name}");
+            }
+        }
+    }
+}
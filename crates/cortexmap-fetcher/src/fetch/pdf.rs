@@ -1,33 +1,338 @@
 use crate::FetchError;
-use bytes::Bytes;
-use cortexmap_infra::{HttpInfra, InfraContext};
-use futures::stream::Stream;
-use std::pin::Pin;
+use crate::fetch::metadata::SearchData;
+use crate::fetch::{ArtifactStream, into_artifact_stream};
+use crate::validate::check_head;
+use cortexmap_core::blueprint::{Artifact, Endpoints, PdfSource};
+use cortexmap_infra::{HttpInfra, InfraContext, RequestCM, ResponseCM};
+use url::Url;
 
-const URL: &str = "https://europepmc.org/backend/ptpmcrender.fcgi?amp;blobtype=pdf&accid={PMCID}";
+/// Media types we accept for a PDF download.
+/// `application/octet-stream` is let through since
+/// the magic bytes are checked anyway while uploading.
+pub(crate) const PDF_CONTENT_TYPES: [&str; 3] = [
+    "application/pdf",
+    "application/x-pdf",
+    "application/octet-stream",
+];
 
-pub struct PdfStream {
-    pub stream: Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send + Sync>>,
-    pub pmc_id: String,
+/// `fullTextUrlList` entries with this availability need a subscription.
+const SUBSCRIPTION_ONLY: &str = "S";
+
+/// Redirects followed at most for a candidate, like reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+/// Lists the URLs to try for the PDF of a paper, in the order of `sources`.
+/// `fullTextUrlList` entries are only considered if their host is allowed,
+/// and within a site the ones marked as PDF come first.
+pub fn pdf_candidates(
+    pmc_id: &str,
+    metadata: Option<&SearchData>,
+    sources: &[PdfSource],
+    allowed_domains: &[String],
+    endpoints: &Endpoints,
+) -> Vec<String> {
+    let full_text_urls = metadata
+        .and_then(|v| v.full_text_url_list.as_ref())
+        .map(|v| v.full_text_url.as_slice())
+        .unwrap_or_default();
+
+    let mut candidates = Vec::new();
+    for source in sources {
+        match source {
+            PdfSource::EuropePmcRender => {
+                candidates.push(Endpoints::url(&endpoints.pdf_url, pmc_id))
+            }
+            PdfSource::FullTextUrl { site } => {
+                let mut entries = full_text_urls
+                    .iter()
+                    .filter(|v| v.site.as_deref() == Some(site.as_str()))
+                    .filter(|v| v.availability_code.as_deref() != Some(SUBSCRIPTION_ONLY))
+                    .filter(|v| is_allowed_domain(&v.url, allowed_domains))
+                    .collect::<Vec<_>>();
+                // Stable, so the order of Europe PMC is kept otherwise.
+                entries.sort_by_key(|v| v.document_style.as_deref() != Some("pdf"));
+                candidates.extend(entries.into_iter().map(|v| v.url.clone()));
+            }
+        }
+    }
+    candidates.dedup();
+    candidates
+}
+
+fn is_allowed_domain(url: &str, allowed_domains: &[String]) -> bool {
+    let Some(host) = host_of(url) else {
+        return false;
+    };
+    allowed_domains.iter().any(|domain| {
+        let domain = domain.to_ascii_lowercase();
+        host == domain || host.ends_with(&format!(".{domain}"))
+    })
+}
+
+fn host_of(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()
+        .and_then(|v| v.host_str().map(str::to_ascii_lowercase))
 }
 
+/// Tries the candidate URLs in order and returns the first one
+/// that answers with something that starts like a PDF. The
+/// candidates after it are kept as the stream's `fallback`, whether
+/// it is a complete PDF only shows once it was read to the end.
 pub async fn fetch_pdf<I: HttpInfra + Send + Sync + 'static>(
     pmc_id: String,
+    candidates: Vec<String>,
+    allowed_domains: &[String],
     ctx: InfraContext<I>,
-) -> Result<PdfStream, FetchError> {
-    let url = URL.replace("{PMCID}", &pmc_id);
-    let response = ctx.infra.get(&url).await?;
-
-    let stream = futures::stream::unfold(response, |mut resp| async move {
-        match resp.chunk().await {
-            Ok(Some(chunk)) => Some((Ok(chunk), resp)),
-            Ok(None) => None,
-            Err(e) => Some((Err(e), resp)),
+) -> Result<ArtifactStream, FetchError> {
+    let mut last_err = None;
+    let mut candidates = candidates.into_iter();
+    while let Some(url) = candidates.next() {
+        let res = match get_candidate(&pmc_id, &url, allowed_domains, ctx.clone()).await {
+            Ok(response) => into_artifact_stream(
+                pmc_id.clone(),
+                Artifact::Pdf,
+                response.url.clone(),
+                response,
+                &PDF_CONTENT_TYPES,
+            ),
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(stream) => match check_head(stream).await {
+                Ok(mut stream) => {
+                    stream.fallback = candidates.collect();
+                    return Ok(stream);
+                }
+                Err(e) => last_err = Some(e),
+            },
+            Err(e) => last_err = Some(e),
         }
-    });
+        if let Some(e) = &last_err {
+            tracing::debug!("No PDF of paper {pmc_id} at {url}: {e}");
+        }
+    }
 
-    Ok(PdfStream {
-        stream: Box::pin(stream),
-        pmc_id,
-    })
+    Err(last_err.unwrap_or_else(|| {
+        FetchError::InvalidPdfSource(format!("{pmc_id}: no PDF source available"))
+    }))
+}
+
+/// `GET`s the candidate and follows its redirects one by one, so
+/// that no hop leaves the candidate's host for one that isn't
+/// allowed, see [`pdf_candidates`]. Error statuses fail.
+async fn get_candidate<I: HttpInfra + Send + Sync + 'static>(
+    pmc_id: &str,
+    url: &str,
+    allowed_domains: &[String],
+    ctx: InfraContext<I>,
+) -> Result<ResponseCM, FetchError> {
+    let host = host_of(url);
+    let mut current = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let request = RequestCM::get(&current).follow_redirects(false);
+        let response = ctx.infra.send(request).await?;
+        if !response.status.is_redirection() {
+            return Ok(response.error_for_status()?);
+        }
+
+        // `Location` may be relative to the URL redirecting.
+        let next = response
+            .headers
+            .get("location")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| Url::parse(&current).ok()?.join(v).ok())
+            .ok_or_else(|| {
+                FetchError::InvalidPdfSource(format!(
+                    "{pmc_id}: {current} redirects without a valid location"
+                ))
+            })?
+            .to_string();
+        if host_of(&next) != host && !is_allowed_domain(&next, allowed_domains) {
+            return Err(FetchError::InvalidPdfSource(format!(
+                "{pmc_id}: redirected to {next}, which isn't an allowed domain"
+            )));
+        }
+        current = next;
+    }
+
+    Err(FetchError::InvalidPdfSource(format!(
+        "{pmc_id}: more than {MAX_REDIRECTS} redirects from {url}"
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::metadata::{FullTextUrl, FullTextUrlList};
+
+    fn full_text_url(site: &str, style: &str, availability: &str, url: &str) -> FullTextUrl {
+        FullTextUrl {
+            availability_code: Some(availability.to_string()),
+            document_style: Some(style.to_string()),
+            site: Some(site.to_string()),
+            url: url.to_string(),
+        }
+    }
+
+    fn metadata() -> SearchData {
+        SearchData {
+            full_text_url_list: Some(FullTextUrlList {
+                full_text_url: vec![
+                    full_text_url(
+                        "PubMedCentral",
+                        "html",
+                        "OA",
+                        "https://www.ncbi.nlm.nih.gov/pmc/articles/PMC1",
+                    ),
+                    full_text_url(
+                        "PubMedCentral",
+                        "pdf",
+                        "OA",
+                        "https://www.ncbi.nlm.nih.gov/pmc/articles/PMC1/pdf",
+                    ),
+                    full_text_url("DOI", "doi", "S", "https://doi.org/10.1000/paywalled"),
+                    full_text_url(
+                        "DOI",
+                        "doi",
+                        "F",
+                        "https://publisher.example.com/10.1000/free",
+                    ),
+                ],
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_candidates_follow_source_order() {
+        let sources = [
+            PdfSource::FullTextUrl {
+                site: "PubMedCentral".to_string(),
+            },
+            PdfSource::EuropePmcRender,
+        ];
+        let candidates = pdf_candidates(
+            "PMC1",
+            Some(&metadata()),
+            &sources,
+            &["nih.gov".to_string()],
+            &Endpoints::default(),
+        );
+        assert_eq!(
+            candidates,
+            vec![
+                "https://www.ncbi.nlm.nih.gov/pmc/articles/PMC1/pdf",
+                "https://www.ncbi.nlm.nih.gov/pmc/articles/PMC1",
+                "https://europepmc.org/backend/ptpmcrender.fcgi?blobtype=pdf&accid=PMC1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_candidates_respect_allowlist() {
+        let sources = [PdfSource::FullTextUrl {
+            site: "DOI".to_string(),
+        }];
+        let allowed = ["doi.org".to_string(), "example.com".to_string()];
+        let endpoints = Endpoints::default();
+        // The subscription-only link is dropped even though its domain is allowed.
+        assert_eq!(
+            pdf_candidates("PMC1", Some(&metadata()), &sources, &allowed, &endpoints),
+            vec!["https://publisher.example.com/10.1000/free"]
+        );
+        assert!(pdf_candidates("PMC1", Some(&metadata()), &sources, &[], &endpoints).is_empty());
+        assert!(pdf_candidates("PMC1", None, &sources, &allowed, &endpoints).is_empty());
+    }
+
+    #[test]
+    fn test_allowed_domain() {
+        let allowed = ["nih.gov".to_string()];
+        assert!(is_allowed_domain(
+            "https://www.ncbi.nlm.nih.gov/pmc",
+            &allowed
+        ));
+        assert!(is_allowed_domain("https://NIH.gov/x", &allowed));
+        assert!(!is_allowed_domain("https://evilnih.gov/x", &allowed));
+        assert!(!is_allowed_domain("not a url", &allowed));
+    }
+
+    #[tokio::test]
+    async fn test_redirect_hops() {
+        use mock_infra::{MockInfra, MockResponse};
+
+        let infra = MockInfra::new();
+        let allowed = ["example.com".to_string()];
+        // Back to an allowed host in the end, but through one that isn't.
+        infra.http.route(
+            "https://example.com/PMC1.pdf",
+            [MockResponse::redirect("https://evil.test/hop")],
+        );
+        infra.http.route(
+            "https://evil.test/hop",
+            [MockResponse::redirect("https://example.com/final.pdf")],
+        );
+        infra.http.route(
+            "https://example.com/PMC2.pdf",
+            [MockResponse::redirect("https://cdn.example.com/PMC2.pdf")],
+        );
+        infra.http.route(
+            "https://cdn.example.com/PMC2.pdf",
+            [MockResponse::redirect("/files/PMC2.pdf")],
+        );
+        infra.http.route(
+            "https://cdn.example.com/files/PMC2.pdf",
+            [MockResponse::pdf("%PDF-1.7")],
+        );
+        infra.http.route(
+            "https://example.com/loop",
+            [MockResponse::redirect("https://example.com/loop")],
+        );
+
+        let fetch = |pmc_id: &str, url: &str| {
+            fetch_pdf(
+                pmc_id.to_string(),
+                vec![url.to_string()],
+                &allowed,
+                infra.context(),
+            )
+        };
+        let res = fetch("PMC1", "https://example.com/PMC1.pdf").await;
+        assert!(matches!(res, Err(FetchError::InvalidPdfSource(_))));
+        assert!(
+            !infra
+                .http
+                .requested_urls()
+                .contains(&"https://evil.test/hop".to_string())
+        );
+
+        let stream = fetch("PMC2", "https://example.com/PMC2.pdf").await.unwrap();
+        assert_eq!(stream.source_url, "https://cdn.example.com/files/PMC2.pdf");
+
+        let res = fetch("PMC3", "https://example.com/loop").await;
+        assert!(matches!(res, Err(FetchError::InvalidPdfSource(_))));
+    }
+
+    #[tokio::test]
+    async fn test_fallback_candidates() {
+        use mock_infra::{MockInfra, MockResponse};
+
+        let infra = MockInfra::new();
+        let candidates = ["http://a.test/1", "http://b.test/1", "http://c.test/1"];
+        infra.http.route(candidates[0], [MockResponse::json("{}")]);
+        infra
+            .http
+            .route(candidates[1], [MockResponse::pdf("%PDF-1.7")]);
+
+        let stream = fetch_pdf(
+            "PMC1".to_string(),
+            candidates.map(str::to_string).to_vec(),
+            &[],
+            infra.context(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream.source_url, candidates[1]);
+        assert_eq!(stream.fallback, [candidates[2]]);
+    }
 }
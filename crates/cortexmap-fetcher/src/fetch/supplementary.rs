@@ -0,0 +1,21 @@
+use crate::FetchError;
+use cortexmap_core::blueprint::Endpoints;
+use cortexmap_infra::{HttpInfra, InfraContext};
+use futures::{AsyncBufRead, TryStreamExt};
+
+/// Fetches the zip archive with the supplementary files of a paper.
+/// The archive is returned as a reader, so it can be unpacked while
+/// it's still being downloaded.
+pub async fn fetch_supplementary<I: HttpInfra + Send + Sync + 'static>(
+    pmc_id: &str,
+    endpoints: &Endpoints,
+    ctx: InfraContext<I>,
+) -> Result<impl AsyncBufRead + Unpin + Send, FetchError> {
+    let url = Endpoints::url(&endpoints.supplementary_url, pmc_id);
+    let response = ctx.infra.get(&url).await?;
+
+    Ok(response
+        .into_stream()
+        .map_err(std::io::Error::other)
+        .into_async_read())
+}
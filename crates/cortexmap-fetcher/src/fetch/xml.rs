@@ -0,0 +1,17 @@
+use crate::FetchError;
+use crate::fetch::{ArtifactStream, into_artifact_stream};
+use cortexmap_core::blueprint::Artifact;
+use cortexmap_infra::{HttpInfra, InfraContext};
+
+/// Media types we accept for a JATS XML download.
+pub(crate) const XML_CONTENT_TYPES: [&str; 2] = ["application/xml", "text/xml"];
+
+pub async fn fetch_xml<I: HttpInfra + Send + Sync + 'static>(
+    pmc_id: String,
+    url: String,
+    ctx: InfraContext<I>,
+) -> Result<ArtifactStream, FetchError> {
+    let response = ctx.infra.get(&url).await?;
+
+    into_artifact_stream(pmc_id, Artifact::Xml, url, response, &XML_CONTENT_TYPES)
+}
@@ -1,34 +1,1269 @@
-use crate::fetch::metadata::fetch_metadata;
-use crate::fetch::pdf::fetch_pdf;
-use crate::{upload, FetchError};
+use crate::fetch::metadata::{SearchData, fetch_metadata};
+use crate::fetch::{PaperRequest, fetch_artifacts};
+use crate::progress::{FetchEvent, Progress};
+use crate::{FetchError, graph, snowball, supplementary, upload};
 use cortexmap_core::blueprint::Blueprint;
-use cortexmap_infra::{DatabaseInfra, HttpInfra, InfraContext, S3Infra};
+use cortexmap_infra::{
+    DatabaseInfra, FetchItem, FetchItemOutcome, FetchRun, HttpInfra, InfraContext, NewFetchItem,
+    NewFetchRun, Paper, S3Infra,
+};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tokio_util::sync::CancellationToken;
 
+/// Starts a new run harvesting the blueprint's query
+/// and drives it until every page was fetched.
+///
+/// Once `cancel` is cancelled no new paper is started, running downloads
+/// and the paper being stored get the blueprint's grace period to finish,
+/// and [`FetchError::Cancelled`] is returned. The run can be [`resume`]d,
+/// which also catches up on the stages skipped for a paper stored meanwhile.
+/// What happens along the way is reported to `progress`.
 pub async fn fetch<I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static>(
     blueprint: &Blueprint,
     ctx: InfraContext<I>,
+    cancel: CancellationToken,
+    progress: Progress,
+) -> Result<FetchRun, FetchError> {
+    validate(blueprint)?;
+    let run = ctx
+        .infra
+        .insert_fetch_run(NewFetchRun {
+            query: blueprint.fetcher.query.clone(),
+            page_size: blueprint.fetcher.page_size as i64,
+        })
+        .await?;
+    tracing::info!("Started fetch run {} for query: {}", run.id, run.query);
+
+    drive(run, blueprint, ctx, cancel, progress).await
+}
+
+/// Picks an interrupted run back up: the items that weren't
+/// stored yet are retried, then paging continues from the
+/// run's last cursor. Completed runs are returned as they are.
+/// Cancellation and progress work like with [`fetch`].
+pub async fn resume<I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    run_id: i64,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+    cancel: CancellationToken,
+    progress: Progress,
+) -> Result<FetchRun, FetchError> {
+    validate(blueprint)?;
+    let run = ctx
+        .infra
+        .get_fetch_run(run_id)
+        .await?
+        .ok_or(FetchError::RunNotFound(run_id))?;
+    if run.status == FetchRun::COMPLETED {
+        return Ok(run);
+    }
+    if run.query != blueprint.fetcher.query {
+        tracing::warn!(
+            "Resuming fetch run {run_id} with its own query `{}`, not the blueprint's",
+            run.query
+        );
+    }
+    tracing::info!("Resuming fetch run {run_id} at cursor {}", run.cursor_mark);
+
+    drive(run, blueprint, ctx, cancel, progress).await
+}
+
+pub(crate) fn validate(blueprint: &Blueprint) -> Result<(), FetchError> {
+    blueprint
+        .fetcher
+        .search
+        .validate()
+        .map_err(FetchError::InvalidSearchOptions)
+}
+
+async fn drive<I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    run: FetchRun,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+    cancel: CancellationToken,
+    progress: Progress,
+) -> Result<FetchRun, FetchError> {
+    progress.emit(FetchEvent::RunStarted {
+        run_id: run.id,
+        query: run.query.clone(),
+    });
+    // Papers a crashed attempt left half stored keep their PMCIDs
+    // claimed, so they are cleaned up before anything is fetched.
+    upload::sweep_pending_papers(blueprint, ctx.clone()).await?;
+    let res = drive_pages(&run, blueprint, ctx.clone(), &cancel, &progress).await;
+    if let Err(FetchError::Cancelled) = &res {
+        progress.emit(FetchEvent::RunCancelled { run_id: run.id });
+        tracing::info!(
+            "Fetch run {} cancelled at cursor {}, resume it to continue",
+            run.id,
+            // Failing to look it up mustn't hide the cancellation.
+            ctx.infra
+                .get_fetch_run(run.id)
+                .await
+                .ok()
+                .flatten()
+                .map_or(run.cursor_mark.clone(), |v| v.cursor_mark)
+        );
+    }
+    res?;
+
+    ctx.infra
+        .update_fetch_run_status(run.id, FetchRun::COMPLETED)
+        .await?;
+    tracing::info!("Completed fetch run {}", run.id);
+    progress.emit(FetchEvent::RunCompleted { run_id: run.id });
+
+    Ok(ctx.infra.get_fetch_run(run.id).await?.unwrap_or(run))
+}
+
+async fn drive_pages<I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    run: &FetchRun,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+    cancel: &CancellationToken,
+    progress: &Progress,
 ) -> Result<(), FetchError> {
-    let meta = fetch_metadata(blueprint, ctx.clone()).await?;
+    // Leftovers of an interrupted attempt go first,
+    // failed ones included since this is a retry.
+    process_items(run.id, true, blueprint, ctx.clone(), cancel, progress).await?;
+
+    // Hits past `max_results` are never recorded, so
+    // the ones already recorded count towards it.
+    let mut remaining = None;
+    let mut recorded = HashSet::new();
+    if let Some(max) = blueprint.fetcher.max_results {
+        recorded = ctx
+            .infra
+            .get_fetch_items(run.id)
+            .await?
+            .into_iter()
+            .map(|v| v.pmc_id)
+            .collect();
+        remaining = Some(max.saturating_sub(recorded.len() as u64));
+    }
+    let mut cursor_mark = run.cursor_mark.clone();
+    let mut hit_count_known = false;
+    while remaining != Some(0) {
+        if cancel.is_cancelled() {
+            return Err(FetchError::Cancelled);
+        }
+        let page = fetch_metadata(
+            &run.query,
+            run.page_size as u64,
+            &cursor_mark,
+            &blueprint.fetcher.search,
+            &blueprint.fetcher.endpoints,
+            ctx.clone(),
+        )
+        .await?;
+        let next_cursor_mark = page.next_page(&cursor_mark);
+        let hits = page.result.result;
+        tracing::debug!(
+            "Fetched {} of {} hits for run {} at cursor {cursor_mark}",
+            hits.len(),
+            page.hit_count,
+            run.id
+        );
+        if !hit_count_known {
+            hit_count_known = true;
+            progress.emit(FetchEvent::HitCount {
+                run_id: run.id,
+                hit_count: page.hit_count,
+            });
+        }
+        progress.emit(FetchEvent::PageFetched {
+            run_id: run.id,
+            cursor_mark: cursor_mark.clone(),
+            hits: hits.len(),
+        });
+
+        // Hits come in the order of the search options' sort, which is
+        // the order they are cut off and processed in. Pages can overlap,
+        // so hits recorded already are passed over before the cut.
+        let items = hits
+            .into_iter()
+            .filter_map(|v| {
+                Some(NewFetchItem {
+                    run_id: run.id,
+                    pmc_id: v.pmcid.clone()?,
+                    search_record: serde_json::to_string(&v).ok(),
+                })
+            })
+            .filter(|v| !recorded.contains(&v.pmc_id))
+            .take(remaining.unwrap_or(u64::MAX) as usize)
+            .collect::<Vec<_>>();
+        if remaining.is_some() {
+            recorded.extend(items.iter().map(|v| v.pmc_id.clone()));
+        }
+        // The last page is recorded under its own cursor, so a
+        // crash before completing the run only fetches it again.
+        let inserted = ctx
+            .infra
+            .insert_fetch_page(
+                run.id,
+                items,
+                next_cursor_mark.as_deref().unwrap_or(&cursor_mark),
+            )
+            .await?;
+        if let Some(remaining) = &mut remaining {
+            *remaining = remaining.saturating_sub(inserted as u64);
+        }
+
+        process_items(run.id, false, blueprint, ctx.clone(), cancel, progress).await?;
+
+        match next_cursor_mark {
+            Some(v) => cursor_mark = v,
+            None => break,
+        }
+    }
 
-    let pdf_streams = futures::future::join_all(
-        meta.result
-            .result
+    if let Some(config) = &blueprint.fetcher.snowball {
+        // The frontier isn't tracked, so a resumed snowball starts over
+        // from the run's papers. What it stored before is expanded
+        // again but not downloaded again.
+        let seeds = ctx
+            .infra
+            .get_fetch_items(run.id)
+            .await?
             .into_iter()
-            .filter_map(|v| v.pmcid)
-            .map(|pmc_id| tokio::spawn(fetch_pdf(pmc_id, ctx.clone()))),
-    )
-    .await
-    .into_iter()
-    // Ignoring all errors for now.
-    // We need more powerful type to
-    // catch list of errors (and to
-    // avoid failing on the first one).
-    // TODO: maybe we could use `tailcall-valid`
-    // for this or have some nexted FetchErrors'
-    // variant.
-    .flatten()
-    .flatten()
-    .collect::<Vec<_>>();
-
-    upload::upload(pdf_streams, blueprint, ctx).await
+            .filter(|v| v.state == FetchItem::DONE)
+            .map(|v| v.pmc_id)
+            .collect();
+        snowball::snowball(seeds, config, blueprint, ctx.clone(), cancel, progress).await?;
+    }
+
+    Ok(())
+}
+
+/// Fetches and stores the pending items of a run (and the failed
+/// ones if `retry_failed`), recording the outcome of each.
+/// Items skipped because of a cancellation stay as they were.
+async fn process_items<I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    run_id: i64,
+    retry_failed: bool,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+    cancel: &CancellationToken,
+    progress: &Progress,
+) -> Result<(), FetchError> {
+    let mut items = Vec::new();
+    for item in ctx.infra.get_unfinished_fetch_items(run_id).await? {
+        if !retry_failed && item.state != FetchItem::PENDING {
+            continue;
+        }
+        // Stored by an attempt that crashed or was cancelled before recording
+        // it, or by another run, so what follows the upload may be missing.
+        if let Some(paper) = ctx.infra.get_paper_by_pmcid(&item.pmc_id).await?
+            && paper.status == Paper::STORED
+        {
+            finish_paper(&paper, blueprint, ctx.clone()).await;
+            ctx.infra
+                .update_fetch_item(item.id, FetchItemOutcome::done(paper.id))
+                .await?;
+            continue;
+        }
+        items.push(item);
+    }
+
+    let requests = items
+        .iter()
+        .map(|item| {
+            let metadata = item
+                .search_record
+                .as_deref()
+                .and_then(|v| serde_json::from_str::<SearchData>(v).ok());
+            PaperRequest::new(item.pmc_id.clone(), metadata, &blueprint.fetcher)
+        })
+        .collect();
+    let stored = store_papers(requests, blueprint, ctx.clone(), cancel, progress).await;
+
+    for (item, paper) in items.into_iter().zip(stored) {
+        let outcome = match paper {
+            Ok(paper) => FetchItemOutcome::done(paper.id),
+            Err(FetchError::Cancelled) => continue,
+            Err(e) => FetchItemOutcome::failed(e.to_string()),
+        };
+        ctx.infra.update_fetch_item(item.id, outcome).await?;
+    }
+
+    if cancel.is_cancelled() {
+        return Err(FetchError::Cancelled);
+    }
+    Ok(())
+}
+
+/// Fetches the papers concurrently, then stores them one by one
+/// and runs the stages of [`finish_paper`] for each.
+/// The results are in the order of `requests`, papers that weren't
+/// (completely) stored because of a cancellation are
+/// [`FetchError::Cancelled`].
+pub(crate) async fn store_papers<I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    requests: Vec<PaperRequest>,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+    cancel: &CancellationToken,
+    progress: &Progress,
+) -> Vec<Result<Paper, FetchError>> {
+    if cancel.is_cancelled() {
+        return requests
+            .iter()
+            .map(|_| Err(FetchError::Cancelled))
+            .collect();
+    }
+
+    let pmc_ids = requests
+        .iter()
+        .map(|v| v.pmc_id.clone())
+        .collect::<Vec<_>>();
+    let downloads = requests
+        .into_iter()
+        .map(|request| tokio::spawn(fetch_artifacts(request, ctx.clone())))
+        .collect::<Vec<_>>();
+    let aborts = downloads
+        .iter()
+        .map(|v| v.abort_handle())
+        .collect::<Vec<_>>();
+    let fetched = tokio::select! {
+        fetched = futures::future::join_all(downloads) => fetched,
+        _ = grace_expired(cancel, blueprint.fetcher.shutdown_grace_period) => {
+            aborts.iter().for_each(AbortHandle::abort);
+            tracing::warn!("Abandoned {} downloads after the grace period", aborts.len());
+            return pmc_ids.iter().map(|_| Err(FetchError::Cancelled)).collect();
+        }
+    };
+
+    let mut stored = Vec::with_capacity(fetched.len());
+    for (pmc_id, paper) in pmc_ids.into_iter().zip(fetched) {
+        let paper = match paper {
+            // Nothing new is started once cancelled.
+            _ if cancel.is_cancelled() => Err(FetchError::Cancelled),
+            Ok(paper) => tokio::select! {
+                res = upload::upload_paper(paper, blueprint, ctx.clone(), progress) => res,
+                _ = grace_expired(cancel, blueprint.fetcher.shutdown_grace_period) => {
+                    tracing::warn!("Abandoned paper {pmc_id} after the grace period");
+                    Err(FetchError::Cancelled)
+                }
+            },
+            Err(e) => Err(FetchError::JoinError(e)),
+        };
+        match &paper {
+            Ok(paper) => progress.emit(FetchEvent::PaperStored {
+                pmc_id: pmc_id.clone(),
+                paper_id: paper.id,
+                bytes: paper.size_bytes.unwrap_or_default() as u64,
+            }),
+            Err(FetchError::Cancelled) => {}
+            Err(e) => progress.emit(FetchEvent::ItemFailed {
+                pmc_id: pmc_id.clone(),
+                error: e.to_string(),
+            }),
+        }
+        let paper = match paper {
+            // Reported as not stored, so the item stays pending
+            // and a resume runs the rest of it.
+            Ok(_) if cancel.is_cancelled() => {
+                tracing::info!("Stored paper {pmc_id}, leaving the rest of it since cancelled");
+                Err(FetchError::Cancelled)
+            }
+            Ok(paper) => {
+                tracing::info!("Uploaded paper: {:?}", paper);
+                finish_paper(&paper, blueprint, ctx.clone()).await;
+                Ok(paper)
+            }
+            Err(e) => {
+                tracing::warn!("Skipping paper {pmc_id}: {e}");
+                Err(e)
+            }
+        };
+        stored.push(paper);
+    }
+
+    stored
+}
+
+/// Runs the stages that follow the upload of a paper, as configured.
+/// Their failures are logged, the paper is stored either way.
+async fn finish_paper<I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    paper: &Paper,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+) {
+    if let Some(config) = &blueprint.fetcher.supplementary
+        && let Err(e) =
+            supplementary::store_missing_supplementary(paper, config, blueprint, ctx.clone()).await
+    {
+        tracing::warn!(
+            "Skipping supplementary files of paper {}: {e}",
+            paper.pmc_id
+        );
+    }
+    if blueprint.fetcher.record_citations
+        && let Err(e) =
+            graph::record_citations(&paper.pmc_id, &blueprint.fetcher.endpoints, ctx.clone()).await
+    {
+        tracing::warn!("Missing citations of paper {}: {e}", paper.pmc_id);
+    }
+}
+
+/// Resolves `grace_period` after the cancellation.
+async fn grace_expired(cancel: &CancellationToken, grace_period: Duration) {
+    cancel.cancelled().await;
+    tokio::time::sleep(grace_period).await;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use cortexmap_core::blueprint::{
+        Artifact, Connections, Database, Endpoints, Fetcher, Filesystem, Migrations, PdfSource,
+        PoolOptions, Postgresql, SearchOptions, Sort, SortField, Storage, Supplementary,
+    };
+    use cortexmap_infra::{ContentType, NewPaper, NewPendingPaper};
+    use futures::StreamExt;
+    use mock_infra::{MockInfra, MockResponse};
+    use std::sync::Arc;
+
+    const PDF: &[u8] = b"%PDF-1.7\n...\n%%EOF\n";
+
+    pub(crate) fn endpoints() -> Endpoints {
+        Endpoints::mirror("http://europepmc.test/rest", "http://europepmc.test")
+    }
+
+    pub(crate) fn search_prefix() -> String {
+        format!("{}?", endpoints().search_url)
+    }
+
+    pub(crate) fn pdf_url(pmc_id: &str) -> String {
+        Endpoints::url(&endpoints().pdf_url, pmc_id)
+    }
+
+    pub(crate) fn blueprint() -> Blueprint {
+        Blueprint {
+            fetcher: Fetcher {
+                query: "cortex".to_string(),
+                page_size: 2,
+                max_results: None,
+                search: SearchOptions::default(),
+                upload_path_prefix: "papers".to_string(),
+                artifacts: vec![Artifact::Pdf],
+                supplementary: None,
+                pdf_sources: vec![PdfSource::EuropePmcRender],
+                allowed_domains: Vec::new(),
+                snowball: None,
+                record_citations: false,
+                shutdown_grace_period: Duration::from_secs(1),
+                pending_paper_timeout: Duration::from_secs(3600),
+                endpoints: endpoints(),
+            },
+            connections: Connections {
+                db: Database::Postgresql(Postgresql {
+                    url: "postgres://unused".to_string(),
+                    pool: PoolOptions::default(),
+                }),
+                storage: Storage::Filesystem(Filesystem {
+                    root: "unused".to_string(),
+                }),
+                migrations: Migrations::default(),
+            },
+        }
+    }
+
+    /// A search page with a hit for each PMCID.
+    pub(crate) fn page(pmc_ids: &[&str], next_cursor_mark: &str) -> MockResponse {
+        let hits = pmc_ids
+            .iter()
+            .map(|v| format!(r#"{{"pmcid": "{v}", "title": "Paper {v}"}}"#))
+            .collect::<Vec<_>>()
+            .join(",");
+        MockResponse::json(format!(
+            r#"{{"hitCount": {}, "nextCursorMark": "{next_cursor_mark}",
+                "resultList": {{"result": [{hits}]}}}}"#,
+            pmc_ids.len()
+        ))
+    }
+
+    async fn run(infra: &Arc<MockInfra>) -> Result<FetchRun, FetchError> {
+        fetch(
+            &blueprint(),
+            infra.context(),
+            CancellationToken::new(),
+            Progress::none(),
+        )
+        .await
+    }
+
+    fn item_states(infra: &MockInfra) -> Vec<(String, String)> {
+        infra
+            .db
+            .fetch_items()
+            .into_iter()
+            .map(|v| (v.pmc_id, v.state))
+            .collect()
+    }
+
+    fn requests_of(infra: &MockInfra, url: &str) -> usize {
+        infra
+            .http
+            .requested_urls()
+            .iter()
+            .filter(|v| *v == url)
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_fetch_stores_papers() {
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [page(&["PMC1", "PMC2"], "c1"), page(&[], "c1")],
+        );
+        infra.http.route(&pdf_url("PMC1"), [MockResponse::pdf(PDF)]);
+        infra.http.route(&pdf_url("PMC2"), [MockResponse::pdf(PDF)]);
+
+        let (progress, events) = Progress::channel();
+        let run = fetch(
+            &blueprint(),
+            infra.context(),
+            CancellationToken::new(),
+            progress,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(run.status, FetchRun::COMPLETED);
+        assert_eq!(infra.db.papers().len(), 2);
+        assert!(infra.db.papers().iter().all(|v| v.status == Paper::STORED));
+        assert_eq!(infra.db.artifacts().len(), 2);
+        let object = infra.s3.object("papers/PMC1/PMC1.pdf").unwrap();
+        assert_eq!(object.content, PDF);
+        assert_eq!(
+            object.metadata[crate::checksum::SIZE_METADATA_KEY],
+            PDF.len().to_string()
+        );
+        assert!(
+            item_states(&infra)
+                .iter()
+                .all(|(_, v)| v == FetchItem::DONE)
+        );
+
+        let events = events.collect::<Vec<_>>().await;
+        assert!(events.contains(&FetchEvent::HitCount {
+            run_id: run.id,
+            hit_count: 2
+        }));
+        let stored = events
+            .iter()
+            .filter(|v| matches!(v, FetchEvent::PaperStored { .. }))
+            .count();
+        assert_eq!(stored, 2);
+        assert_eq!(
+            events.last(),
+            Some(&FetchEvent::RunCompleted { run_id: run.id })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_hits() {
+        let infra = MockInfra::new();
+        // Pages overlap, and PMC3 was stored by an earlier run.
+        infra.http.route_prefix(
+            &search_prefix(),
+            [
+                page(&["PMC1", "PMC2"], "c1"),
+                page(&["PMC2", "PMC3"], "c2"),
+                page(&[], "c2"),
+            ],
+        );
+        for pmc_id in ["PMC1", "PMC2", "PMC3"] {
+            infra.http.route(&pdf_url(pmc_id), [MockResponse::pdf(PDF)]);
+        }
+        let stored = infra
+            .db
+            .insert_paper(NewPaper {
+                pmc_id: "PMC3".to_string(),
+                s3_key: "papers/PMC3/PMC3.pdf".to_string(),
+                uid: "earlier".to_string(),
+                query: "cortex".to_string(),
+                sha256: "abc".to_string(),
+                size_bytes: 3,
+                pdf_source_url: None,
+                reached_via: "query".to_string(),
+                reached_from: None,
+                snowball_depth: 0,
+            })
+            .await
+            .unwrap();
+
+        run(&infra).await.unwrap();
+
+        assert_eq!(requests_of(&infra, &pdf_url("PMC2")), 1);
+        assert_eq!(requests_of(&infra, &pdf_url("PMC3")), 0);
+        assert_eq!(infra.db.papers().len(), 3);
+        let items = infra.db.fetch_items();
+        assert_eq!(items.len(), 3);
+        assert!(items.iter().all(|v| v.state == FetchItem::DONE));
+        assert_eq!(items[2].paper_id, Some(stored.id));
+    }
+
+    #[tokio::test]
+    async fn test_max_results_with_overlapping_pages() {
+        let infra = MockInfra::new();
+        // Sorted by citations, PMC2 moved onto the second page meanwhile.
+        infra.http.route_prefix(
+            &search_prefix(),
+            [
+                page(&["PMC1", "PMC2"], "c1"),
+                page(&["PMC2", "PMC3", "PMC4"], "c2"),
+                page(&["PMC5"], "c3"),
+            ],
+        );
+        infra
+            .http
+            .route_prefix(&pdf_url(""), [MockResponse::pdf(PDF)]);
+
+        let mut blueprint = blueprint();
+        blueprint.fetcher.max_results = Some(3);
+        blueprint.fetcher.search.sort = Some(Sort {
+            field: SortField::Cited,
+            descending: true,
+        });
+        let run = fetch(
+            &blueprint,
+            infra.context(),
+            CancellationToken::new(),
+            Progress::none(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(run.status, FetchRun::COMPLETED);
+        let items = item_states(&infra);
+        let pmc_ids = items.iter().map(|(v, _)| v.as_str()).collect::<Vec<_>>();
+        assert_eq!(pmc_ids, ["PMC1", "PMC2", "PMC3"]);
+        let searches = infra
+            .http
+            .requested_urls()
+            .into_iter()
+            .filter(|v| v.starts_with(&search_prefix()))
+            .collect::<Vec<_>>();
+        assert_eq!(searches.len(), 2);
+        assert!(searches.iter().all(|v| v.contains("sort=CITED")));
+    }
+
+    /// A zip archive with a CSV file for each name.
+    async fn archive(names: &[&str]) -> Vec<u8> {
+        use async_zip::base::write::ZipFileWriter;
+        use async_zip::{Compression, ZipEntryBuilder};
+
+        let mut writer = ZipFileWriter::new(Vec::new());
+        for name in names {
+            let entry = ZipEntryBuilder::new((*name).into(), Compression::Deflate);
+            writer.write_entry_whole(entry, b"a,b\n").await.unwrap();
+        }
+        writer.close().await.unwrap()
+    }
+
+    /// Records PMC1 as stored by an earlier run.
+    async fn stored_paper(infra: &MockInfra) -> Paper {
+        infra
+            .db
+            .insert_paper(NewPaper {
+                pmc_id: "PMC1".to_string(),
+                s3_key: "papers/PMC1/PMC1.pdf".to_string(),
+                uid: "earlier".to_string(),
+                query: "cortex".to_string(),
+                sha256: "abc".to_string(),
+                size_bytes: 3,
+                pdf_source_url: None,
+                reached_via: "query".to_string(),
+                reached_from: None,
+                snowball_depth: 0,
+            })
+            .await
+            .unwrap()
+    }
+
+    fn supplementary_blueprint() -> Blueprint {
+        let mut blueprint = blueprint();
+        blueprint.fetcher.supplementary = Some(Supplementary {
+            max_file_size: 1024,
+            allowed_extensions: Vec::new(),
+        });
+        blueprint
+    }
+
+    #[tokio::test]
+    async fn test_supplementary_of_stored_papers() {
+        let archive = archive(&["data/table1.csv"]).await;
+
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [
+                page(&["PMC1"], "c1"),
+                page(&[], "c1"),
+                page(&["PMC1"], "c1"),
+                page(&[], "c1"),
+            ],
+        );
+        let supplementary_url = Endpoints::url(&endpoints().supplementary_url, "PMC1");
+        infra.http.route(
+            &supplementary_url,
+            [MockResponse::ok("application/zip", archive)],
+        );
+        // Stored by an earlier run without the stage configured.
+        let stored = stored_paper(&infra).await;
+
+        let blueprint = supplementary_blueprint();
+        for _ in 0..2 {
+            fetch(
+                &blueprint,
+                infra.context(),
+                CancellationToken::new(),
+                Progress::none(),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(requests_of(&infra, &pdf_url("PMC1")), 0);
+        // The second run finds the files recorded already.
+        assert_eq!(requests_of(&infra, &supplementary_url), 1);
+        let files = infra.db.supplementary_files();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].paper_id, stored.id);
+        assert!(infra.s3.object(&files[0].s3_key).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_supplementary_record_faults() {
+        let archive = archive(&["a.csv", "b.csv", "c.csv"]).await;
+        let infra = MockInfra::new();
+        infra
+            .http
+            .route_prefix(&search_prefix(), [page(&["PMC1"], "c1"), page(&[], "c1")]);
+        let supplementary_url = Endpoints::url(&endpoints().supplementary_url, "PMC1");
+        infra.http.route(
+            &supplementary_url,
+            [MockResponse::ok("application/zip", archive)],
+        );
+        stored_paper(&infra).await;
+        // The first file fails at its metadata, the second at its record.
+        infra.s3.faults.inject("set_metadata_s3", 1);
+        infra.db.faults.inject("insert_supplementary_file", 1);
+
+        fetch(
+            &supplementary_blueprint(),
+            infra.context(),
+            CancellationToken::new(),
+            Progress::none(),
+        )
+        .await
+        .unwrap();
+
+        let files = infra.db.supplementary_files();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].filename, "c.csv");
+        // The skipped files leave no objects behind.
+        assert_eq!(infra.s3.keys(), [files[0].s3_key.clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_citation_graph_identities() {
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [
+                MockResponse::json(
+                    r#"{"hitCount": 2, "nextCursorMark": "c1", "resultList": {"result": [
+                        {"pmcid": "PMC1", "pmid": "1"}, {"pmcid": "PMC2", "pmid": "2"}
+                    ]}}"#,
+                ),
+                page(&[], "c1"),
+            ],
+        );
+        infra.http.route(&pdf_url("PMC1"), [MockResponse::pdf(PDF)]);
+        infra.http.route(&pdf_url("PMC2"), [MockResponse::pdf(PDF)]);
+        let references = |works: &str| {
+            MockResponse::json(format!(
+                r#"{{"hitCount": 2, "referenceList": {{"reference": [{works}]}}}}"#
+            ))
+        };
+        // Both are looked up by their PMID, and cite each other that way.
+        infra.http.route_prefix(
+            "http://europepmc.test/rest/MED/1/references?",
+            [references(
+                r#"{"id": "2", "source": "MED"}, {"id": "3", "source": "MED"}"#,
+            )],
+        );
+        infra.http.route_prefix(
+            "http://europepmc.test/rest/MED/2/references?",
+            [references(r#"{"id": "1", "source": "MED"}"#)],
+        );
+
+        let mut blueprint = blueprint();
+        blueprint.fetcher.record_citations = true;
+        fetch(
+            &blueprint,
+            infra.context(),
+            CancellationToken::new(),
+            Progress::none(),
+        )
+        .await
+        .unwrap();
+
+        // PMC2 was cited by its PMID before it was stored.
+        let edges = infra
+            .db
+            .citations()
+            .into_iter()
+            .map(|v| (v.citing, v.cited))
+            .collect::<HashSet<_>>();
+        let edge = |citing: &str, cited: &str| (citing.to_string(), cited.to_string());
+        assert_eq!(
+            edges,
+            HashSet::from([
+                edge("PMC:PMC1", "PMC:PMC2"),
+                edge("PMC:PMC1", "MED:3"),
+                edge("PMC:PMC2", "PMC:PMC1"),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_downloads() {
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [page(&["PMC1", "PMC2", "PMC3"], "c1"), page(&[], "c1")],
+        );
+        infra.http.route(
+            &pdf_url("PMC1"),
+            [MockResponse::new(http::StatusCode::INTERNAL_SERVER_ERROR)],
+        );
+        // Europe PMC's error pages come with status 200.
+        infra.http.route(
+            &pdf_url("PMC2"),
+            [MockResponse::ok("text/html", "<html>Not found</html>")],
+        );
+        infra.http.route(&pdf_url("PMC3"), [MockResponse::pdf(PDF)]);
+
+        let run = run(&infra).await.unwrap();
+
+        assert_eq!(run.status, FetchRun::COMPLETED);
+        assert_eq!(infra.s3.keys(), ["papers/PMC3/PMC3.pdf"]);
+        let items = infra.db.fetch_items();
+        assert_eq!(
+            items.iter().map(|v| v.state.as_str()).collect::<Vec<_>>(),
+            [FetchItem::FAILED, FetchItem::FAILED, FetchItem::DONE]
+        );
+        // Artifacts that can't be downloaded are skipped, which leaves no artifact.
+        assert!(items[0].last_error.as_ref().unwrap().contains("PMC1"));
+        assert!(items[1].last_error.as_ref().unwrap().contains("PMC2"));
+    }
+
+    #[tokio::test]
+    async fn test_truncated_downloads() {
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [page(&["PMC1", "PMC2", "PMC3"], "c1"), page(&[], "c1")],
+        );
+        // Missing trailer, dropped connection and a short body.
+        infra
+            .http
+            .route(&pdf_url("PMC1"), [MockResponse::pdf(&PDF[..12])]);
+        infra.http.route(
+            &pdf_url("PMC2"),
+            [MockResponse::pdf(&PDF[..12]).body_error("connection reset")],
+        );
+        infra.http.route(
+            &pdf_url("PMC3"),
+            [MockResponse::pdf(PDF).content_length(PDF.len() as u64 + 100)],
+        );
+
+        run(&infra).await.unwrap();
+
+        assert!(infra.s3.keys().is_empty());
+        assert!(infra.db.papers().is_empty());
+        assert!(
+            item_states(&infra)
+                .iter()
+                .all(|(_, v)| v == FetchItem::FAILED)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_incomplete_pdf_falls_back() {
+        let publisher_url = "https://publisher.example.com/PMC1.pdf";
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [
+                MockResponse::json(format!(
+                    r#"{{"hitCount": 1, "nextCursorMark": "c1", "resultList": {{"result": [
+                        {{"pmcid": "PMC1", "fullTextUrlList": {{"fullTextUrl": [
+                            {{"url": "{publisher_url}", "site": "DOI",
+                              "availabilityCode": "OA", "documentStyle": "pdf"}}
+                        ]}}}}
+                    ]}}}}"#
+                )),
+                page(&[], "c1"),
+            ],
+        );
+        // Starts like a PDF, but it's cut off.
+        infra
+            .http
+            .route(&pdf_url("PMC1"), [MockResponse::pdf(&PDF[..12])]);
+        infra.http.route(publisher_url, [MockResponse::pdf(PDF)]);
+
+        let mut blueprint = blueprint();
+        blueprint.fetcher.pdf_sources = vec![
+            PdfSource::EuropePmcRender,
+            PdfSource::FullTextUrl {
+                site: "DOI".to_string(),
+            },
+        ];
+        blueprint.fetcher.allowed_domains = vec!["example.com".to_string()];
+        fetch(
+            &blueprint,
+            infra.context(),
+            CancellationToken::new(),
+            Progress::none(),
+        )
+        .await
+        .unwrap();
+
+        let papers = infra.db.papers();
+        assert_eq!(papers.len(), 1);
+        assert_eq!(papers[0].pdf_source_url.as_deref(), Some(publisher_url));
+        let object = infra.s3.object("papers/PMC1/PMC1.pdf").unwrap();
+        assert_eq!(object.content, PDF);
+    }
+
+    #[tokio::test]
+    async fn test_insert_fault() {
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [page(&["PMC1", "PMC2"], "c1"), page(&[], "c1")],
+        );
+        infra.http.route(&pdf_url("PMC1"), [MockResponse::pdf(PDF)]);
+        infra.http.route(&pdf_url("PMC2"), [MockResponse::pdf(PDF)]);
+        infra.db.faults.inject("insert_pending_paper", 1);
+
+        run(&infra).await.unwrap();
+
+        assert_eq!(
+            item_states(&infra),
+            [
+                ("PMC1".to_string(), FetchItem::FAILED.to_string()),
+                ("PMC2".to_string(), FetchItem::DONE.to_string()),
+            ]
+        );
+        assert_eq!(infra.db.papers().len(), 1);
+        // Nothing is uploaded without a claimed PMCID.
+        assert_eq!(infra.s3.keys(), ["papers/PMC2/PMC2.pdf"]);
+    }
+
+    #[tokio::test]
+    async fn test_finalize_fault() {
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [page(&["PMC1", "PMC2"], "c1"), page(&[], "c1")],
+        );
+        infra.http.route(&pdf_url("PMC1"), [MockResponse::pdf(PDF)]);
+        infra.http.route(&pdf_url("PMC2"), [MockResponse::pdf(PDF)]);
+        infra.db.faults.inject("finalize_paper", 1);
+
+        run(&infra).await.unwrap();
+
+        assert_eq!(
+            item_states(&infra),
+            [
+                ("PMC1".to_string(), FetchItem::FAILED.to_string()),
+                ("PMC2".to_string(), FetchItem::DONE.to_string()),
+            ]
+        );
+        // The uploaded object is deleted along with the pending paper.
+        let papers = infra.db.papers();
+        assert_eq!(
+            papers.iter().map(|v| v.pmc_id.as_str()).collect::<Vec<_>>(),
+            ["PMC2"]
+        );
+        assert_eq!(infra.s3.keys(), ["papers/PMC2/PMC2.pdf"]);
+        assert_eq!(infra.db.artifacts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_pending_papers() {
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [
+                page(&["PMC1"], "c1"),
+                page(&[], "c1"),
+                page(&["PMC1"], "c1"),
+                page(&[], "c1"),
+            ],
+        );
+        infra.http.route(
+            &pdf_url("PMC1"),
+            [MockResponse::pdf(PDF), MockResponse::pdf(PDF)],
+        );
+        // What a crash halfway through uploading PMC1 leaves behind.
+        let left = infra
+            .db
+            .insert_pending_paper(NewPendingPaper {
+                pmc_id: "PMC1".to_string(),
+                s3_key: "papers/PMC1/PMC1.pdf".to_string(),
+                uid: "uid-PMC1".to_string(),
+                query: "cortex".to_string(),
+                reached_via: "query".to_string(),
+                reached_from: None,
+                snowball_depth: 0,
+            })
+            .await
+            .unwrap();
+        infra
+            .s3
+            .insert("papers/PMC1/PMC1.xml", ContentType::Xml, "<article/>");
+        infra
+            .s3
+            .insert("papers/PMC12/PMC12.pdf", ContentType::Pdf, PDF);
+
+        // Too recent to be taken for a leftover, so the PMCID stays taken.
+        run(&infra).await.unwrap();
+        assert_eq!(item_states(&infra)[0].1, FetchItem::FAILED);
+        assert_eq!(infra.db.papers()[0].id, left.id);
+
+        let mut blueprint = blueprint();
+        blueprint.fetcher.pending_paper_timeout = Duration::ZERO;
+        let run = fetch(
+            &blueprint,
+            infra.context(),
+            CancellationToken::new(),
+            Progress::none(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(run.status, FetchRun::COMPLETED);
+        let papers = infra.db.papers();
+        assert_eq!(papers.len(), 1);
+        assert_ne!(papers[0].id, left.id);
+        assert_eq!(papers[0].status, Paper::STORED);
+        assert_eq!(
+            infra.s3.keys(),
+            ["papers/PMC1/PMC1.pdf", "papers/PMC12/PMC12.pdf"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_search_error() {
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [
+                page(&["PMC1"], "c1"),
+                MockResponse::send_error("connection refused"),
+                page(&["PMC2"], "c2"),
+                page(&[], "c2"),
+            ],
+        );
+        infra.http.route(&pdf_url("PMC1"), [MockResponse::pdf(PDF)]);
+        infra.http.route(&pdf_url("PMC2"), [MockResponse::pdf(PDF)]);
+
+        assert!(run(&infra).await.is_err());
+        let interrupted = infra.db.fetch_runs().remove(0);
+        assert_eq!(interrupted.status, FetchRun::RUNNING);
+        assert_eq!(interrupted.cursor_mark, "c1");
+
+        let run = resume(
+            interrupted.id,
+            &blueprint(),
+            infra.context(),
+            CancellationToken::new(),
+            Progress::none(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(run.status, FetchRun::COMPLETED);
+        assert_eq!(infra.db.papers().len(), 2);
+        assert_eq!(requests_of(&infra, &pdf_url("PMC1")), 1);
+    }
+
+    /// Cancels `cancel` after `delay`, in the background.
+    fn cancel_after(cancel: &CancellationToken, delay: Duration) {
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            cancel.cancel();
+        });
+    }
+
+    /// A PDF download that takes `delay` after its first bytes.
+    fn slow_pdf(delay: Duration) -> MockResponse {
+        let (head, rest) = PDF.split_at(8);
+        MockResponse::pdf(PDF)
+            .chunks([Bytes::from_static(head), Bytes::from_static(rest)])
+            .chunk_delay(delay)
+    }
+
+    async fn resume_run(infra: &Arc<MockInfra>, blueprint: &Blueprint) -> FetchRun {
+        let run_id = infra.db.fetch_runs()[0].id;
+        resume(
+            run_id,
+            blueprint,
+            infra.context(),
+            CancellationToken::new(),
+            Progress::none(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cancel_before_first_page() {
+        let infra = MockInfra::new();
+        infra
+            .http
+            .route_prefix(&search_prefix(), [page(&["PMC1"], "c1"), page(&[], "c1")]);
+        infra.http.route(&pdf_url("PMC1"), [MockResponse::pdf(PDF)]);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let (progress, events) = Progress::channel();
+        let res = fetch(&blueprint(), infra.context(), cancel, progress).await;
+        assert!(matches!(res, Err(FetchError::Cancelled)));
+        assert!(infra.http.requested_urls().is_empty());
+        let events = events.collect::<Vec<_>>().await;
+        assert!(matches!(
+            events.last(),
+            Some(FetchEvent::RunCancelled { .. })
+        ));
+
+        let run = resume_run(&infra, &blueprint()).await;
+        assert_eq!(run.status, FetchRun::COMPLETED);
+        assert_eq!(
+            item_states(&infra),
+            [("PMC1".to_string(), "done".to_string())]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_during_upload() {
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [page(&["PMC1", "PMC2"], "c1"), page(&[], "c1")],
+        );
+        // Done well within the grace period of a second.
+        let slow = slow_pdf(Duration::from_millis(300));
+        infra.http.route(&pdf_url("PMC1"), [slow.clone()]);
+        infra.http.route(&pdf_url("PMC2"), [slow]);
+        let references = "http://europepmc.test/rest/PMC/PMC1/references?";
+        let references_requested = || {
+            let urls = infra.http.requested_urls();
+            urls.iter().filter(|v| v.starts_with(references)).count()
+        };
+        let mut blueprint = blueprint();
+        blueprint.fetcher.record_citations = true;
+
+        let cancel = CancellationToken::new();
+        cancel_after(&cancel, Duration::from_millis(100));
+        let res = fetch(&blueprint, infra.context(), cancel, Progress::none()).await;
+        assert!(matches!(res, Err(FetchError::Cancelled)));
+        // PMC1 got stored, but not recorded as done since its
+        // citations were skipped. PMC2 wasn't started.
+        let papers = infra.db.papers();
+        assert_eq!(papers.len(), 1);
+        assert_eq!(papers[0].status, Paper::STORED);
+        assert!(
+            item_states(&infra)
+                .iter()
+                .all(|(_, v)| v == FetchItem::PENDING)
+        );
+        assert_eq!(references_requested(), 0);
+
+        let run = resume_run(&infra, &blueprint).await;
+        assert_eq!(run.status, FetchRun::COMPLETED);
+        assert!(
+            item_states(&infra)
+                .iter()
+                .all(|(_, v)| v == FetchItem::DONE)
+        );
+        assert_eq!(infra.db.papers().len(), 2);
+        assert_eq!(requests_of(&infra, &pdf_url("PMC1")), 1);
+        assert_eq!(references_requested(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_beyond_grace_period() {
+        let infra = MockInfra::new();
+        infra.http.route_prefix(
+            &search_prefix(),
+            [page(&["PMC1", "PMC2"], "c1"), page(&[], "c1")],
+        );
+        let stuck = slow_pdf(Duration::from_secs(60));
+        infra
+            .http
+            .route(&pdf_url("PMC1"), [stuck, MockResponse::pdf(PDF)]);
+        infra.http.route(&pdf_url("PMC2"), [MockResponse::pdf(PDF)]);
+
+        let cancel = CancellationToken::new();
+        cancel_after(&cancel, Duration::from_millis(100));
+        let res = fetch(&blueprint(), infra.context(), cancel, Progress::none()).await;
+        assert!(matches!(res, Err(FetchError::Cancelled)));
+        // The upload was abandoned with its PMCID claimed.
+        let papers = infra.db.papers();
+        assert_eq!(papers.len(), 1);
+        assert_eq!(papers[0].status, Paper::PENDING);
+        assert!(infra.s3.keys().is_empty());
+        assert!(
+            item_states(&infra)
+                .iter()
+                .all(|(_, v)| v == FetchItem::PENDING)
+        );
+
+        // The claim is swept once it is taken for a leftover.
+        let mut blueprint = blueprint();
+        blueprint.fetcher.pending_paper_timeout = Duration::ZERO;
+        let run = resume_run(&infra, &blueprint).await;
+        assert_eq!(run.status, FetchRun::COMPLETED);
+        let papers = infra.db.papers();
+        assert_eq!(papers.len(), 2);
+        assert!(papers.iter().all(|v| v.status == Paper::STORED));
+        assert_eq!(
+            infra.s3.keys(),
+            ["papers/PMC1/PMC1.pdf", "papers/PMC2/PMC2.pdf"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_during_downloads() {
+        let infra = MockInfra::new();
+        infra
+            .http
+            .route_prefix(&search_prefix(), [page(&["PMC1"], "c1"), page(&[], "c1")]);
+        // Its first bytes, checked while downloading, never show up in time.
+        let stalled = MockResponse::pdf(PDF)
+            .chunks([Bytes::new(), Bytes::from_static(PDF)])
+            .chunk_delay(Duration::from_secs(60));
+        infra.http.route(&pdf_url("PMC1"), [stalled]);
+
+        let started = tokio::time::Instant::now();
+        let cancel = CancellationToken::new();
+        cancel_after(&cancel, Duration::from_millis(100));
+        let res = fetch(&blueprint(), infra.context(), cancel, Progress::none()).await;
+        assert!(matches!(res, Err(FetchError::Cancelled)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(infra.db.papers().is_empty());
+        assert_eq!(item_states(&infra)[0].1, FetchItem::PENDING);
+    }
 }
@@ -0,0 +1,252 @@
+use crate::FetchError;
+use crate::fetch::citations::{WorkId, fetch_citations};
+use cortexmap_core::blueprint::{CitationDirection, Endpoints};
+use cortexmap_infra::{Citation, DatabaseInfra, HttpInfra, InfraContext};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+
+/// The citation endpoints know most papers by their PubMed id, so
+/// the stored paper's PMID is preferred over its PMCID when known.
+/// The graph knows every stored paper by its PMCID though, see [`normalize`].
+pub async fn work_id<I: DatabaseInfra>(
+    pmc_id: &str,
+    ctx: InfraContext<I>,
+) -> Result<WorkId, FetchError> {
+    let pmid = match ctx.infra.get_paper_by_pmcid(pmc_id).await? {
+        Some(paper) => ctx
+            .infra
+            .get_bibliography(paper.id)
+            .await?
+            .and_then(|v| v.metadata.pmid),
+        None => None,
+    };
+
+    Ok(match pmid {
+        Some(pmid) => WorkId::med(&pmid),
+        None => WorkId::pmc(pmc_id),
+    })
+}
+
+/// Gives the works with a known PMCID their `PMC` identity, so a paper
+/// is a single node of the graph whichever id it was found with. PMIDs
+/// of stored papers are known, `known` maps further PMIDs to PMCIDs.
+pub(crate) async fn normalize<I: DatabaseInfra>(
+    works: Vec<WorkId>,
+    mut known: HashMap<String, String>,
+    ctx: InfraContext<I>,
+) -> Result<Vec<WorkId>, FetchError> {
+    let pmids = works
+        .iter()
+        .filter(|v| v.source == "MED" && !known.contains_key(&v.id))
+        .map(|v| v.id.clone())
+        .collect::<Vec<_>>();
+    if !pmids.is_empty() {
+        known.extend(ctx.infra.get_pmcids_by_pmids(&pmids).await?);
+    }
+
+    Ok(works
+        .into_iter()
+        .map(|v| match known.get(&v.id) {
+            Some(pmc_id) if v.source == "MED" => WorkId::pmc(pmc_id),
+            _ => v,
+        })
+        .collect())
+}
+
+/// Moves the edges recorded for a newly stored paper under its
+/// PubMed id to its `PMC` identity, see [`normalize`].
+pub(crate) async fn merge_work<I: DatabaseInfra>(
+    pmc_id: &str,
+    pmid: &str,
+    ctx: InfraContext<I>,
+) -> Result<(), FetchError> {
+    let (from, to) = (WorkId::med(pmid), WorkId::pmc(pmc_id));
+    ctx.infra
+        .rename_work(&from.to_string(), &to.to_string())
+        .await?;
+    Ok(())
+}
+
+/// The edges between `work` and the works it is linked to in `direction`.
+pub(crate) fn edges(
+    work: &WorkId,
+    direction: CitationDirection,
+    others: &[WorkId],
+) -> Vec<Citation> {
+    others
+        .iter()
+        .map(|other| match direction {
+            CitationDirection::References => Citation {
+                citing: work.to_string(),
+                cited: other.to_string(),
+            },
+            CitationDirection::Citations => Citation {
+                citing: other.to_string(),
+                cited: work.to_string(),
+            },
+        })
+        .collect()
+}
+
+/// Records the reference list of a stored paper in the citation graph.
+/// Returns how many edges were new.
+pub async fn record_citations<I: HttpInfra + DatabaseInfra>(
+    pmc_id: &str,
+    endpoints: &Endpoints,
+    ctx: InfraContext<I>,
+) -> Result<usize, FetchError> {
+    let work = work_id(pmc_id, ctx.clone()).await?;
+    let references = fetch_citations(
+        &work,
+        CitationDirection::References,
+        None,
+        endpoints,
+        ctx.clone(),
+    )
+    .await?;
+    let references = normalize(references, HashMap::new(), ctx.clone()).await?;
+
+    let node = WorkId::pmc(pmc_id);
+    Ok(ctx
+        .infra
+        .insert_citations(edges(&node, CitationDirection::References, &references))
+        .await?)
+}
+
+/// Writes the whole citation graph as GraphML.
+pub async fn export_graphml<I: DatabaseInfra, W: Write>(
+    ctx: InfraContext<I>,
+    writer: W,
+) -> Result<(), FetchError> {
+    let citations = ctx.infra.get_citations().await?;
+    Ok(write_graphml(&citations, writer)?)
+}
+
+/// Writes the whole citation graph as a CSV edge list.
+pub async fn export_csv<I: DatabaseInfra, W: Write>(
+    ctx: InfraContext<I>,
+    writer: W,
+) -> Result<(), FetchError> {
+    let citations = ctx.infra.get_citations().await?;
+    Ok(write_csv(&citations, writer)?)
+}
+
+/// Writes the edges as a directed GraphML graph, with
+/// a node for every work that appears in an edge.
+pub fn write_graphml<W: Write>(citations: &[Citation], mut writer: W) -> std::io::Result<()> {
+    let nodes = citations
+        .iter()
+        .flat_map(|v| [v.citing.as_str(), v.cited.as_str()])
+        .collect::<BTreeSet<_>>();
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(writer, r#"  <graph id="citations" edgedefault="directed">"#)?;
+    for node in nodes {
+        writeln!(writer, r#"    <node id="{}"/>"#, escape_xml(node))?;
+    }
+    for citation in citations {
+        writeln!(
+            writer,
+            r#"    <edge source="{}" target="{}"/>"#,
+            escape_xml(&citation.citing),
+            escape_xml(&citation.cited)
+        )?;
+    }
+    writeln!(writer, "  </graph>")?;
+    writeln!(writer, "</graphml>")?;
+    writer.flush()
+}
+
+/// Writes the edges as CSV with a `citing,cited` header.
+pub fn write_csv<W: Write>(citations: &[Citation], mut writer: W) -> std::io::Result<()> {
+    writeln!(writer, "citing,cited")?;
+    for citation in citations {
+        writeln!(
+            writer,
+            "{},{}",
+            escape_csv(&citation.citing),
+            escape_csv(&citation.cited)
+        )?;
+    }
+    writer.flush()
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn citation(citing: &str, cited: &str) -> Citation {
+        Citation {
+            citing: citing.to_string(),
+            cited: cited.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_edges() {
+        let work = WorkId {
+            source: "MED".to_string(),
+            id: "1".to_string(),
+        };
+        let others = [WorkId {
+            source: "PMC".to_string(),
+            id: "PMC2".to_string(),
+        }];
+        assert_eq!(
+            edges(&work, CitationDirection::References, &others),
+            vec![citation("MED:1", "PMC:PMC2")]
+        );
+        assert_eq!(
+            edges(&work, CitationDirection::Citations, &others),
+            vec![citation("PMC:PMC2", "MED:1")]
+        );
+    }
+
+    #[test]
+    fn test_graphml() {
+        let mut out = Vec::new();
+        write_graphml(
+            &[citation("MED:1", "MED:2"), citation("MED:1", "AGR:a&b")],
+            &mut out,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(r#"<graph id="citations" edgedefault="directed">"#));
+        assert_eq!(out.matches("<node ").count(), 3);
+        assert!(out.contains(r#"<edge source="MED:1" target="AGR:a&amp;b"/>"#));
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut out = Vec::new();
+        write_csv(
+            &[citation("MED:1", "MED:2"), citation("MED:1", "ETH:a,b")],
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "citing,cited\nMED:1,MED:2\nMED:1,\"ETH:a,b\"\n"
+        );
+    }
+}
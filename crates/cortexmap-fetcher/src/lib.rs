@@ -1,8 +1,25 @@
+mod checksum;
 mod fetcher;
 mod error;
 mod fetch;
+mod graph;
+mod plan;
+mod progress;
+mod snowball;
+mod supplementary;
 mod upload;
+mod validate;
+mod verify;
 
 pub use fetcher::*;
 pub use error::*;
-pub use fetch::pdf::PdfStream;
+pub use fetch::citations::WorkId;
+pub use fetch::metadata::SearchData;
+pub use fetch::{ArtifactStream, Origin, PaperRequest, PaperStreams};
+pub use checksum::{Checksum, SHA256_METADATA_KEY, SIZE_METADATA_KEY};
+pub use verify::*;
+pub use plan::*;
+pub use progress::*;
+pub use graph::*;
+pub use snowball::snowball;
+pub use supplementary::store_supplementary;
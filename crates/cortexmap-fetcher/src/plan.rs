@@ -0,0 +1,175 @@
+use crate::FetchError;
+use crate::fetch::PaperRequest;
+use crate::fetch::metadata::fetch_metadata;
+use crate::upload::determine_key;
+use cortexmap_core::blueprint::Blueprint;
+use cortexmap_infra::{DatabaseInfra, FetchRun, HttpInfra, InfraContext};
+use std::collections::HashSet;
+
+/// What [`fetch`](crate::fetch) would do for a blueprint.
+#[derive(Debug, Clone, Default)]
+pub struct FetchPlan {
+    pub query: String,
+    /// Number of hits Europe PMC reports for the query
+    pub hit_count: u64,
+    /// Papers that would be downloaded
+    pub papers: Vec<PlannedPaper>,
+    /// PMCIDs of the hits that are already stored
+    pub skipped: Vec<String>,
+    /// Hits without a PMCID, which have no full text to fetch
+    pub without_pmc_id: u64,
+}
+
+/// A paper that would be downloaded.
+#[derive(Debug, Clone)]
+pub struct PlannedPaper {
+    pub pmc_id: String,
+    /// Keys its artifacts would be written to, if available
+    pub keys: Vec<String>,
+    /// URLs that would be tried for its PDF, in order
+    pub pdf_urls: Vec<String>,
+}
+
+impl FetchPlan {
+    /// All keys that would be written, supplementary files aside
+    /// since they are only known once the archive is read.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.papers
+            .iter()
+            .flat_map(|v| v.keys.iter().map(String::as_str))
+    }
+}
+
+/// Pages through the search results like a fetch run and checks which
+/// papers are stored already, without fetching or writing anything.
+pub async fn plan<I: HttpInfra + DatabaseInfra + Send + Sync + 'static>(
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+) -> Result<FetchPlan, FetchError> {
+    crate::fetcher::validate(blueprint)?;
+    let fetcher = &blueprint.fetcher;
+    let mut plan = FetchPlan {
+        query: fetcher.query.clone(),
+        ..Default::default()
+    };
+    let mut seen = HashSet::new();
+
+    let mut cursor_mark = FetchRun::FIRST_CURSOR.to_string();
+    loop {
+        let page = fetch_metadata(
+            &fetcher.query,
+            fetcher.page_size,
+            &cursor_mark,
+            &fetcher.search,
+            &fetcher.endpoints,
+            ctx.clone(),
+        )
+        .await?;
+        // Like a run, the count of the first page is the one reported.
+        if cursor_mark == FetchRun::FIRST_CURSOR {
+            plan.hit_count = page.hit_count;
+        }
+        let next_cursor_mark = page.next_page(&cursor_mark);
+
+        for hit in page.result.result {
+            let Some(pmc_id) = hit.pmcid.clone() else {
+                plan.without_pmc_id += 1;
+                continue;
+            };
+            // Pages can overlap when results are added while paging.
+            if !seen.insert(pmc_id.clone()) {
+                continue;
+            }
+            // Like a run, stored papers count towards the limit.
+            if fetcher
+                .max_results
+                .is_some_and(|max| (plan.papers.len() + plan.skipped.len()) as u64 >= max)
+            {
+                return Ok(plan);
+            }
+            if ctx.infra.get_paper_by_pmcid(&pmc_id).await?.is_some() {
+                plan.skipped.push(pmc_id);
+                continue;
+            }
+
+            let request = PaperRequest::new(pmc_id, Some(hit), fetcher);
+            plan.papers.push(PlannedPaper {
+                keys: request
+                    .artifacts
+                    .iter()
+                    .map(|&v| determine_key(&request.pmc_id, v, blueprint))
+                    .collect(),
+                pdf_urls: request.pdf_urls,
+                pmc_id: request.pmc_id,
+            });
+        }
+
+        match next_cursor_mark {
+            Some(v) => cursor_mark = v,
+            None => break,
+        }
+    }
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::tests::{blueprint, page, pdf_url, search_prefix};
+    use cortexmap_infra::NewPaper;
+    use mock_infra::{MockInfra, MockResponse};
+
+    #[tokio::test]
+    async fn test_plan() {
+        let infra = MockInfra::new();
+        // Pages report the hit count of the whole query.
+        let first = MockResponse::json(
+            r#"{"hitCount": 5, "nextCursorMark": "c1", "resultList": {"result": [
+                {"pmcid": "PMC1"}, {"pmcid": "PMC2"}
+            ]}}"#,
+        );
+        let overlapping = MockResponse::json(
+            r#"{"hitCount": 5, "nextCursorMark": "c2", "resultList": {"result": [
+                {"pmcid": "PMC2"}, {"pmid": "7"}, {"pmcid": "PMC3"}, {"pmcid": "PMC4"}
+            ]}}"#,
+        );
+        let pages = [first, overlapping, page(&[], "c2")];
+        infra
+            .http
+            .route_prefix(&search_prefix(), pages.iter().chain(&pages).cloned());
+        infra
+            .db
+            .insert_paper(NewPaper {
+                pmc_id: "PMC2".to_string(),
+                s3_key: "papers/PMC2/PMC2.pdf".to_string(),
+                uid: "earlier".to_string(),
+                query: "cortex".to_string(),
+                sha256: "abc".to_string(),
+                size_bytes: 3,
+                pdf_source_url: None,
+                reached_via: "query".to_string(),
+                reached_from: None,
+                snowball_depth: 0,
+            })
+            .await
+            .unwrap();
+
+        let mut blueprint = blueprint();
+        let plan = super::plan(&blueprint, infra.context()).await.unwrap();
+        assert_eq!(plan.hit_count, 5);
+        let planned = plan.papers.iter().map(|v| v.pmc_id.as_str());
+        assert_eq!(planned.collect::<Vec<_>>(), ["PMC1", "PMC3", "PMC4"]);
+        assert_eq!(plan.skipped, ["PMC2"]);
+        assert_eq!(plan.without_pmc_id, 1);
+        assert_eq!(plan.papers[0].pdf_urls, [pdf_url("PMC1")]);
+        assert_eq!(plan.keys().count(), 3);
+
+        // The stored paper counts towards the limit, like in a run.
+        blueprint.fetcher.max_results = Some(3);
+        let plan = super::plan(&blueprint, infra.context()).await.unwrap();
+        let planned = plan.papers.iter().map(|v| v.pmc_id.as_str());
+        assert_eq!(planned.collect::<Vec<_>>(), ["PMC1", "PMC3"]);
+        assert_eq!(plan.skipped, ["PMC2"]);
+    }
+}
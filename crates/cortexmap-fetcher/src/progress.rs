@@ -0,0 +1,97 @@
+use cortexmap_core::blueprint::Artifact;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
+
+/// Something that happened during a fetch run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchEvent {
+    /// A run was started or resumed
+    RunStarted { run_id: i64, query: String },
+    /// Number of hits of the query, known after the first page
+    HitCount { run_id: i64, hit_count: u64 },
+    /// A page of search results was recorded
+    PageFetched {
+        run_id: i64,
+        cursor_mark: String,
+        hits: usize,
+    },
+    /// An artifact started downloading (and streaming into storage)
+    DownloadStarted {
+        pmc_id: String,
+        artifact: Artifact,
+        /// Expected size, if the server sent it
+        content_length: Option<u64>,
+    },
+    /// An artifact was downloaded and stored
+    DownloadFinished {
+        pmc_id: String,
+        artifact: Artifact,
+        bytes: u64,
+    },
+    /// A paper and its artifacts were recorded
+    PaperStored {
+        pmc_id: String,
+        paper_id: i64,
+        /// Size of the primary artifact
+        bytes: u64,
+    },
+    /// A paper couldn't be stored
+    ItemFailed { pmc_id: String, error: String },
+    /// Every page was fetched and every item attempted
+    RunCompleted { run_id: i64 },
+    /// The run was cancelled and can be resumed
+    RunCancelled { run_id: i64 },
+}
+
+/// Where the events of a run go. Cheap to clone, and events are
+/// dropped silently when nobody listens (anymore).
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    sender: Option<UnboundedSender<FetchEvent>>,
+}
+
+impl Progress {
+    /// Discards all events.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Sends the events to the returned receiver.
+    pub fn channel() -> (Self, UnboundedReceiver<FetchEvent>) {
+        let (sender, receiver) = unbounded();
+        (
+            Self {
+                sender: Some(sender),
+            },
+            receiver,
+        )
+    }
+
+    pub(crate) fn emit(&self, event: FetchEvent) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.unbounded_send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_channel() {
+        let (progress, mut events) = Progress::channel();
+        progress
+            .clone()
+            .emit(FetchEvent::RunCompleted { run_id: 1 });
+        drop(progress);
+        assert_eq!(
+            events.next().await,
+            Some(FetchEvent::RunCompleted { run_id: 1 })
+        );
+        assert_eq!(events.next().await, None);
+
+        // Nobody listening is fine.
+        Progress::none().emit(FetchEvent::RunCompleted { run_id: 1 });
+    }
+}
@@ -0,0 +1,196 @@
+use crate::FetchError;
+use crate::fetch::citations::{WorkId, fetch_citations};
+use crate::fetch::metadata::{SearchData, fetch_metadata};
+use crate::fetch::{Origin, PaperRequest};
+use crate::fetcher::store_papers;
+use crate::graph::{edges, normalize, work_id};
+use crate::progress::Progress;
+use cortexmap_core::blueprint::{Blueprint, Snowball};
+use cortexmap_core::config::BooleanQuery;
+use cortexmap_infra::{DatabaseInfra, FetchRun, HttpInfra, InfraContext, Paper, S3Infra};
+use std::collections::{HashMap, HashSet};
+use tokio_util::sync::CancellationToken;
+
+/// Works looked up with a single search, which keeps the query short.
+const RESOLVE_BATCH_SIZE: usize = 50;
+
+/// Expands the corpus from `seeds` (stored PMCIDs) along the citation
+/// graph, up to `config.depth` hops away. Works found on the way are
+/// looked up with a search, and the open access ones matching the
+/// filter go through the normal download and upload pipeline.
+/// Returns the papers stored along the way, or [`FetchError::Cancelled`]
+/// once cancelled, leaving the rest for a resume of the run.
+pub async fn snowball<I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    seeds: Vec<String>,
+    config: &Snowball,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+    cancel: &CancellationToken,
+    progress: &Progress,
+) -> Result<Vec<Paper>, FetchError> {
+    let mut visited = seeds.iter().cloned().collect::<HashSet<_>>();
+    let mut frontier = seeds;
+    let mut stored = Vec::new();
+
+    for depth in 1..=config.depth {
+        let mut requests = Vec::new();
+        let mut next_frontier = Vec::new();
+        for pmc_id in &frontier {
+            if cancel.is_cancelled() {
+                return Err(FetchError::Cancelled);
+            }
+            let work = work_id(pmc_id, ctx.clone()).await?;
+            let node = WorkId::pmc(pmc_id);
+            for &direction in &config.directions {
+                let works = match fetch_citations(
+                    &work,
+                    direction,
+                    config.max_per_paper,
+                    &blueprint.fetcher.endpoints,
+                    ctx.clone(),
+                )
+                .await
+                {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::warn!("Skipping {direction:?} of paper {pmc_id}: {e}");
+                        continue;
+                    }
+                };
+                let resolved =
+                    resolve(&works, config.filter.as_ref(), blueprint, ctx.clone()).await;
+                // The search tells the PMCIDs of the works it found.
+                let known = resolved
+                    .iter()
+                    .flatten()
+                    .filter_map(|v| Some((v.pmid.clone()?, v.pmcid.clone()?)))
+                    .collect();
+                let works = normalize(works, known, ctx.clone()).await?;
+                ctx.infra
+                    .insert_citations(edges(&node, direction, &works))
+                    .await?;
+                let hits = match resolved {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::warn!("Skipping {direction:?} of paper {pmc_id}: {e}");
+                        continue;
+                    }
+                };
+
+                for hit in hits {
+                    let Some(found) = hit.pmcid.clone() else {
+                        continue;
+                    };
+                    if !visited.insert(found.clone()) {
+                        continue;
+                    }
+                    // Already stored papers are expanded, but not downloaded again.
+                    if ctx.infra.get_paper_by_pmcid(&found).await?.is_some() {
+                        next_frontier.push(found);
+                        continue;
+                    }
+                    let mut request = PaperRequest::new(found, Some(hit), &blueprint.fetcher);
+                    request.origin = Origin::Citation {
+                        from: pmc_id.clone(),
+                        direction,
+                        depth,
+                    };
+                    requests.push(request);
+                }
+            }
+        }
+        tracing::info!(
+            "Snowballing found {} new papers at depth {depth}",
+            requests.len()
+        );
+
+        // Failures are logged by `store_papers` already.
+        for paper in store_papers(requests, blueprint, ctx.clone(), cancel, progress)
+            .await
+            .into_iter()
+            .flatten()
+        {
+            next_frontier.push(paper.pmc_id.clone());
+            stored.push(paper);
+        }
+        if cancel.is_cancelled() {
+            return Err(FetchError::Cancelled);
+        }
+        if next_frontier.is_empty() {
+            break;
+        }
+        frontier = next_frontier;
+    }
+
+    Ok(stored)
+}
+
+/// Looks the works up, keeping the open access ones that match `filter`.
+async fn resolve<I: HttpInfra>(
+    works: &[WorkId],
+    filter: Option<&BooleanQuery>,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+) -> Result<Vec<SearchData>, FetchError> {
+    let mut by_source = HashMap::<&str, Vec<&str>>::new();
+    for work in works {
+        by_source
+            .entry(work.source.as_str())
+            .or_default()
+            .push(work.id.as_str());
+    }
+
+    let mut hits = Vec::new();
+    for (source, ids) in by_source {
+        for batch in ids.chunks(RESOLVE_BATCH_SIZE) {
+            let query = resolve_query(source, batch, filter).to_string();
+            let page = fetch_metadata(
+                &query,
+                batch.len() as u64,
+                FetchRun::FIRST_CURSOR,
+                &blueprint.fetcher.search,
+                &blueprint.fetcher.endpoints,
+                ctx.clone(),
+            )
+            .await?;
+            hits.extend(page.result.result);
+        }
+    }
+
+    Ok(hits)
+}
+
+fn resolve_query(source: &str, ids: &[&str], filter: Option<&BooleanQuery>) -> BooleanQuery {
+    let mut queries = vec![
+        BooleanQuery::field("SRC", source),
+        BooleanQuery::or(
+            ids.iter()
+                .map(|&id| BooleanQuery::field("EXT_ID", id))
+                .collect(),
+        ),
+        BooleanQuery::field("OPEN_ACCESS", "y"),
+    ];
+    queries.extend(filter.cloned());
+    BooleanQuery::and(queries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_query() {
+        let query = resolve_query("MED", &["1", "2"], None);
+        assert_eq!(
+            query.to_string(),
+            "(SRC:MED+AND+(EXT_ID:1+OR+EXT_ID:2)+AND+OPEN_ACCESS:y)"
+        );
+
+        let filter = BooleanQuery::phrase("motor cortex");
+        let query = resolve_query("PMC", &["PMC1"], Some(&filter));
+        assert_eq!(
+            query.to_string(),
+            "(SRC:PMC+AND+EXT_ID:PMC1+AND+OPEN_ACCESS:y+AND+\"motor+cortex\")"
+        );
+    }
+}
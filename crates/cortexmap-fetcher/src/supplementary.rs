@@ -0,0 +1,363 @@
+use crate::FetchError;
+use crate::checksum::{checksum, checksum_metadata};
+use crate::fetch::supplementary::fetch_supplementary;
+use crate::upload::paper_prefix;
+use async_zip::base::read::stream::ZipFileReader;
+use bytes::Bytes;
+use cortexmap_core::blueprint::{Blueprint, Supplementary};
+use cortexmap_infra::{
+    ContentType, DatabaseInfra, HttpInfra, InfraContext, InfraError, NewSupplementaryFile, Paper,
+    S3Infra, SupplementaryFile,
+};
+use futures::channel::mpsc;
+use futures::{AsyncBufRead, AsyncRead, AsyncReadExt, SinkExt, StreamExt};
+
+/// Size of the chunks read from an archive entry.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Number of chunks buffered between unpacking and uploading.
+const CHUNK_BUFFER: usize = 4;
+
+/// A file of the archive, its content arrives while it's unpacked.
+struct ArchiveEntry {
+    path: String,
+    content: mpsc::Receiver<Result<Bytes, InfraError>>,
+}
+
+/// Downloads the supplementary files of a stored paper and uploads each of
+/// them under the paper's prefix. The archive is unpacked while streaming,
+/// so neither the archive nor the files in it are held in memory.
+pub async fn store_supplementary<I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    paper: &Paper,
+    config: &Supplementary,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+) -> Result<Vec<SupplementaryFile>, FetchError> {
+    let archive =
+        fetch_supplementary(&paper.pmc_id, &blueprint.fetcher.endpoints, ctx.clone()).await?;
+
+    // Unpacking and uploading run concurrently, the channels
+    // in between make sure only a few chunks are in flight.
+    let (entries, receiver) = mpsc::channel(0);
+    let (unpacked, stored) = futures::join!(
+        unpack(archive, config, entries),
+        store_entries(receiver, paper, blueprint, ctx),
+    );
+    unpacked?;
+
+    Ok(stored)
+}
+
+/// Like [`store_supplementary`] for a paper stored earlier, e.g. by a run
+/// without the stage configured. Nothing is fetched if the paper has
+/// supplementary files recorded already.
+pub(crate) async fn store_missing_supplementary<
+    I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static,
+>(
+    paper: &Paper,
+    config: &Supplementary,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+) -> Result<Vec<SupplementaryFile>, FetchError> {
+    let recorded = ctx.infra.get_supplementary_files(paper.id).await?;
+    if !recorded.is_empty() {
+        return Ok(Vec::new());
+    }
+    store_supplementary(paper, config, blueprint, ctx).await
+}
+
+async fn unpack<R: AsyncBufRead + Unpin>(
+    archive: R,
+    config: &Supplementary,
+    mut entries: mpsc::Sender<ArchiveEntry>,
+) -> Result<(), FetchError> {
+    let mut zip = ZipFileReader::new(archive);
+    while let Some(mut reading) = zip.next_with_entry().await? {
+        let entry = reading.reader().entry();
+        let name = entry.filename().as_str().unwrap_or_default().to_string();
+        let is_dir = entry.dir().unwrap_or_default();
+        // Zero if the size is only known after the data (data descriptor).
+        let declared_size = entry.uncompressed_size();
+
+        match sanitize_path(&name) {
+            Some(path)
+                if !is_dir
+                    && is_allowed(&path, config)
+                    && declared_size <= config.max_file_size =>
+            {
+                let (mut content, receiver) = mpsc::channel(CHUNK_BUFFER);
+                let entry = ArchiveEntry {
+                    path,
+                    content: receiver,
+                };
+                if entries.send(entry).await.is_err() {
+                    // Nobody is storing the entries anymore.
+                    return Ok(());
+                }
+                copy_entry(reading.reader_mut(), config.max_file_size, &mut content).await;
+            }
+            _ => tracing::debug!("Skipping supplementary file `{name}` ({declared_size} bytes)"),
+        }
+
+        zip = reading.skip().await?;
+    }
+
+    Ok(())
+}
+
+/// Forwards the entry in chunks, ending with an error
+/// if it can't be read or turns out to be too large.
+async fn copy_entry<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_size: u64,
+    content: &mut mpsc::Sender<Result<Bytes, InfraError>>,
+) {
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut size = 0;
+    loop {
+        let chunk = match reader.read(&mut buf).await {
+            Ok(0) => return,
+            Ok(n) => {
+                size += n as u64;
+                if size > max_size {
+                    Err(InfraError::StreamAborted(format!(
+                        "file is larger than {max_size} bytes"
+                    )))
+                } else {
+                    Ok(Bytes::copy_from_slice(&buf[..n]))
+                }
+            }
+            Err(e) => Err(InfraError::StreamAborted(e.to_string())),
+        };
+
+        let failed = chunk.is_err();
+        if content.send(chunk).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+async fn store_entries<I: DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    mut entries: mpsc::Receiver<ArchiveEntry>,
+    paper: &Paper,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+) -> Vec<SupplementaryFile> {
+    let mut stored = Vec::new();
+    while let Some(entry) = entries.next().await {
+        let path = entry.path.clone();
+        match store_entry(entry, paper, blueprint, ctx.clone()).await {
+            Ok(file) => {
+                tracing::info!("Uploaded supplementary file: {:?}", file);
+                stored.push(file);
+            }
+            Err(e) => tracing::warn!(
+                "Skipping supplementary file {path} of paper {}: {e}",
+                paper.pmc_id
+            ),
+        }
+    }
+
+    stored
+}
+
+async fn store_entry<I: DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    entry: ArchiveEntry,
+    paper: &Paper,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+) -> Result<SupplementaryFile, FetchError> {
+    let prefix = paper_prefix(&paper.pmc_id, blueprint);
+    let key = format!("{prefix}/supplementary/{}", entry.path);
+    let content_type = guess_content_type(&entry.path);
+
+    let (stream, digest) = checksum(Box::pin(entry.content));
+    ctx.infra.put_s3(&key, content_type.clone(), stream).await?;
+    let digest = digest.get().ok_or_else(|| {
+        InfraError::StreamAborted(format!("{key}: upload finished before the stream ended"))
+    })?;
+
+    let res = match ctx
+        .infra
+        .set_metadata_s3(&key, content_type.clone(), checksum_metadata(&digest))
+        .await
+    {
+        Ok(()) => {
+            ctx.infra
+                .insert_supplementary_file(NewSupplementaryFile {
+                    paper_id: paper.id,
+                    filename: entry.path,
+                    s3_key: key.clone(),
+                    content_type: content_type.to_string(),
+                    sha256: digest.sha256,
+                    size_bytes: digest.size_bytes as i64,
+                })
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    // The file is skipped, so its object isn't kept either.
+    if res.is_err()
+        && let Err(e) = ctx.infra.delete_s3(&key).await
+    {
+        tracing::warn!("Failed to delete {key}: {e}");
+    }
+    Ok(res?)
+}
+
+/// Turns the name of an archive entry into a relative path
+/// that can't escape the paper's prefix.
+fn sanitize_path(name: &str) -> Option<String> {
+    let segments = name
+        .split(['/', '\\'])
+        .filter(|v| !v.is_empty() && *v != "." && *v != "..")
+        .collect::<Vec<_>>();
+    if segments.is_empty() {
+        None
+    } else {
+        Some(segments.join("/"))
+    }
+}
+
+fn extension(path: &str) -> Option<String> {
+    let filename = path.rsplit('/').next()?;
+    let (_, extension) = filename.rsplit_once('.')?;
+    Some(extension.to_ascii_lowercase())
+}
+
+fn is_allowed(path: &str, config: &Supplementary) -> bool {
+    if config.allowed_extensions.is_empty() {
+        return true;
+    }
+    extension(path).is_some_and(|extension| {
+        config
+            .allowed_extensions
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&extension))
+    })
+}
+
+fn guess_content_type(path: &str) -> ContentType {
+    let media_type = match extension(path).as_deref() {
+        Some("pdf") => return ContentType::Pdf,
+        Some("xml") => return ContentType::Xml,
+        Some("txt") => return ContentType::Text,
+        Some("csv") => "text/csv",
+        Some("tsv") => "text/tab-separated-values",
+        Some("json") => "application/json",
+        Some("zip") => "application/zip",
+        Some("xls") => "application/vnd.ms-excel",
+        Some("xlsx") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        Some("doc") => "application/msword",
+        Some("docx") => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        Some("ppt") => "application/vnd.ms-powerpoint",
+        Some("pptx") => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("tif" | "tiff") => "image/tiff",
+        Some("mp4") => "video/mp4",
+        Some("avi") => "video/x-msvideo",
+        Some("mov") => "video/quicktime",
+        _ => "application/octet-stream",
+    };
+    ContentType::Other(media_type.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_zip::base::write::ZipFileWriter;
+    use async_zip::{Compression, ZipEntryBuilder};
+    use futures::AsyncWriteExt;
+
+    fn config(allowed_extensions: &[&str]) -> Supplementary {
+        Supplementary {
+            max_file_size: 1024,
+            allowed_extensions: allowed_extensions.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_sanitize_path() {
+        assert_eq!(
+            sanitize_path("data/table1.csv"),
+            Some("data/table1.csv".to_string())
+        );
+        assert_eq!(
+            sanitize_path("../../etc/passwd"),
+            Some("etc/passwd".to_string())
+        );
+        assert_eq!(sanitize_path("a\\.\\b.txt"), Some("a/b.txt".to_string()));
+        assert_eq!(sanitize_path("/./"), None);
+    }
+
+    #[test]
+    fn test_allowed_extensions() {
+        assert!(is_allowed("coords.xyz", &config(&[])));
+        assert!(is_allowed("data/Table1.CSV", &config(&["csv", "xlsx"])));
+        assert!(!is_allowed("movie.mp4", &config(&["csv", "xlsx"])));
+        assert!(!is_allowed("README", &config(&["csv"])));
+    }
+
+    #[test]
+    fn test_guess_content_type() {
+        assert_eq!(guess_content_type("a/b/table.csv").to_string(), "text/csv");
+        assert_eq!(
+            guess_content_type("figure.PDF").to_string(),
+            "application/pdf"
+        );
+        assert_eq!(
+            guess_content_type("blob").to_string(),
+            "application/octet-stream"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unpack_archive() {
+        let mut writer = ZipFileWriter::new(Vec::new());
+        for (name, data) in [
+            ("data/table.csv", b"a,b\n1,2\n".as_slice()),
+            ("movie.mp4", b"not a movie".as_slice()),
+            ("declared_too_large.csv", [b'x'; 2048].as_slice()),
+        ] {
+            let entry = ZipEntryBuilder::new(name.into(), Compression::Deflate);
+            writer.write_entry_whole(entry, data).await.unwrap();
+        }
+        // Streamed entries only declare their size after the data.
+        let entry = ZipEntryBuilder::new("streamed_too_large.csv".into(), Compression::Deflate);
+        let mut entry_writer = writer.write_entry_stream(entry).await.unwrap();
+        entry_writer.write_all(&[b'y'; 4096]).await.unwrap();
+        entry_writer.close().await.unwrap();
+        let archive = writer.close().await.unwrap();
+
+        let config = config(&["csv"]);
+        let (entries, mut receiver) = mpsc::channel::<ArchiveEntry>(0);
+        let collect = async {
+            let mut files = Vec::new();
+            while let Some(entry) = receiver.next().await {
+                let chunks = entry.content.collect::<Vec<_>>().await;
+                files.push((entry.path, chunks));
+            }
+            files
+        };
+        let (unpacked, files) = futures::join!(
+            unpack(futures::io::Cursor::new(archive), &config, entries),
+            collect,
+        );
+        unpacked.unwrap();
+
+        assert_eq!(files.len(), 2);
+        let (path, chunks) = &files[0];
+        assert_eq!(path, "data/table.csv");
+        let content = chunks
+            .iter()
+            .map(|chunk| chunk.as_ref().unwrap().to_vec())
+            .collect::<Vec<_>>()
+            .concat();
+        assert_eq!(content, b"a,b\n1,2\n");
+
+        let (path, chunks) = &files[1];
+        assert_eq!(path, "streamed_too_large.csv");
+        assert!(chunks.last().unwrap().is_err());
+    }
+}
@@ -1,49 +1,315 @@
-use crate::{FetchError, PdfStream};
-use cortexmap_core::blueprint::Blueprint;
-use cortexmap_infra::{ContentType, DatabaseInfra, InfraContext, NewPaper, S3Infra};
-use futures::StreamExt;
+use crate::FetchError;
+use crate::checksum::{Checksum, checksum, checksum_metadata};
+use crate::fetch::{ArtifactStream, Origin, PaperStreams, pdf};
+use crate::graph;
+use crate::progress::{FetchEvent, Progress};
+use crate::validate::validate;
+use cortexmap_core::blueprint::{Artifact, Blueprint};
+use cortexmap_infra::{
+    ContentType, DatabaseInfra, HttpInfra, InfraContext, InfraError, NewPaperArtifact,
+    NewPendingPaper, Paper, PaperUpdate, S3Infra,
+};
 
-pub async fn upload<I: DatabaseInfra + S3Infra + Send + Sync + 'static>(
-    streams: Vec<PdfStream>,
+/// An artifact that made it into S3.
+struct StoredArtifact {
+    artifact: Artifact,
+    key: String,
+    source_url: String,
+    digest: Checksum,
+}
+
+/// Stores the paper's artifacts and its record, so that either both end
+/// up in place or neither does:
+///
+/// 1. A pending paper claims the PMCID, so a taken one fails before
+///    anything is uploaded.
+/// 2. The artifacts are uploaded. A PDF that turns out incomplete
+///    is replaced by the next candidate that answers with one.
+/// 3. The paper is finalized along with its artifacts, atomically.
+///
+/// If 2. or 3. fails, the uploaded objects and the pending paper are
+/// removed again. What a crash or an abandoned upload leaves behind is
+/// removed by [`sweep_pending_papers`].
+pub(crate) async fn upload_paper<I: HttpInfra + DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    paper: PaperStreams,
     blueprint: &Blueprint,
     ctx: InfraContext<I>,
-) -> Result<(), FetchError> {
-    for stream in streams {
-        // TODO: skip if the paper alr exists in the DB.
-
-        let key = determine_key(&stream.pmc_id, blueprint);
-        // Map the stream to skip errors and unwrap Ok values
-        let byte_stream = stream
-            .stream
-            .filter_map(|result| async move { result.ok() });
-        let res = ctx
+    progress: &Progress,
+) -> Result<Paper, FetchError> {
+    let Some(first) = paper.artifacts.first() else {
+        return Err(FetchError::NoArtifacts(paper.pmc_id));
+    };
+    let pending = ctx
+        .infra
+        .insert_pending_paper(NewPendingPaper {
+            pmc_id: paper.pmc_id.clone(),
+            s3_key: determine_key(&paper.pmc_id, first.artifact, blueprint),
+            uid: uuid::Uuid::new_v4().to_string(),
+            query: blueprint.fetcher.query.clone(),
+            reached_via: paper.origin.reached_via().to_string(),
+            reached_from: match &paper.origin {
+                Origin::Query => None,
+                Origin::Citation { from, .. } => Some(from.clone()),
+            },
+            snowball_depth: match paper.origin {
+                Origin::Query => 0,
+                Origin::Citation { depth, .. } => depth as i32,
+            },
+        })
+        .await?;
+
+    let mut stored = Vec::with_capacity(paper.artifacts.len());
+    let mut last_err = None;
+    for stream in paper.artifacts {
+        let artifact = stream.artifact;
+        match upload_with_fallback(stream, blueprint, ctx.clone(), progress).await {
+            Ok(v) => stored.push(v),
+            Err(e) => {
+                tracing::warn!("Skipping {artifact:?} of paper {}: {e}", paper.pmc_id);
+                last_err = Some(e);
+            }
+        }
+    }
+
+    let record = match finalize(&pending, stored, last_err, ctx.clone()).await {
+        Ok(v) => v,
+        Err(e) => {
+            if let Err(e) = remove_paper(&pending, ctx.clone()).await {
+                tracing::warn!(
+                    "Left paper {} pending for the recovery sweep: {e}",
+                    pending.pmc_id
+                );
+            }
+            return Err(e);
+        }
+    };
+
+    // The files are stored at this point, so missing metadata
+    // is reported but doesn't fail the paper.
+    if let Some(metadata) = paper.metadata {
+        if let Err(e) = ctx
             .infra
-            .put_s3(&key, ContentType::Pdf, Box::pin(byte_stream))
-            .await;
-        if let Ok(()) = res {
-            // TODO: Upgrade err handling here.
-            ctx.infra
-                .insert_paper(NewPaper {
-                    pmc_id: stream.pmc_id,
-                    s3_key: key,
-                    uid: uuid::Uuid::new_v4().to_string(),
-                    query: blueprint.fetcher.query.clone(),
-                })
-                .await
-                .map(|paper| {
-                    tracing::info!("Uploaded paper: {:?}", paper);
-                }).ok();
+            .insert_bibliography(metadata.to_bibliography(record.id))
+            .await
+        {
+            tracing::warn!("Missing metadata of paper {}: {e}", record.pmc_id);
+        }
+        // Other papers may have cited it by its PubMed id already.
+        if let Some(pmid) = &metadata.pmid
+            && let Err(e) = graph::merge_work(&record.pmc_id, pmid, ctx.clone()).await
+        {
+            tracing::warn!(
+                "Paper {} left apart in the citation graph: {e}",
+                record.pmc_id
+            );
+        }
+    }
+
+    Ok(record)
+}
+
+/// Records the stored artifacts of the pending paper and marks it stored.
+/// Fails with the last upload error if no artifact got stored.
+async fn finalize<I: DatabaseInfra + Send + Sync + 'static>(
+    pending: &Paper,
+    stored: Vec<StoredArtifact>,
+    last_err: Option<FetchError>,
+    ctx: InfraContext<I>,
+) -> Result<Paper, FetchError> {
+    // The first artifact that got stored is the primary one.
+    let Some(primary) = stored.first() else {
+        return Err(last_err.unwrap_or_else(|| FetchError::NoArtifacts(pending.pmc_id.clone())));
+    };
+    let update = PaperUpdate {
+        s3_key: Some(primary.key.clone()),
+        query: None,
+        sha256: Some(primary.digest.sha256.clone()),
+        size_bytes: Some(primary.digest.size_bytes as i64),
+        pdf_source_url: stored
+            .iter()
+            .find(|v| v.artifact == Artifact::Pdf)
+            .map(|v| Some(v.source_url.clone())),
+    };
+    let artifacts = stored
+        .into_iter()
+        .map(|v| NewPaperArtifact {
+            paper_id: pending.id,
+            kind: artifact_kind(v.artifact).to_string(),
+            s3_key: v.key,
+            sha256: v.digest.sha256,
+            size_bytes: v.digest.size_bytes as i64,
+        })
+        .collect();
+
+    Ok(ctx
+        .infra
+        .finalize_paper(pending.id, update, artifacts)
+        .await?)
+}
+
+/// Removes the papers left pending for longer than the blueprint's
+/// `pending_paper_timeout` by crashed or abandoned attempts, along with
+/// whatever of their objects made it into S3, and returns how many.
+/// Papers that can't be removed are kept abandoned for the next sweep.
+pub(crate) async fn sweep_pending_papers<I: DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+) -> Result<usize, FetchError> {
+    let timeout = blueprint.fetcher.pending_paper_timeout;
+    let mut removed = 0;
+    for paper in ctx.infra.abandon_pending_papers(timeout).await? {
+        match remove_paper(&paper, ctx.clone()).await {
+            Ok(()) => {
+                tracing::info!(
+                    "Removed paper {} left pending since {}",
+                    paper.pmc_id,
+                    paper.created_at
+                );
+                removed += 1;
+            }
+            Err(e) => tracing::warn!("Failed to remove abandoned paper {}: {e}", paper.pmc_id),
+        }
+    }
+    Ok(removed)
+}
+
+/// Deletes the objects in the paper's "directory", then its record.
+async fn remove_paper<I: DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    paper: &Paper,
+    ctx: InfraContext<I>,
+) -> Result<(), FetchError> {
+    // Only listed with the slash, `PMC1` is a prefix of `PMC12` as well.
+    let prefix = match paper.s3_key.rsplit_once('/') {
+        Some((dir, _)) => format!("{dir}/"),
+        None => paper.s3_key.clone(),
+    };
+    let mut keys = Vec::new();
+    let mut continuation = None;
+    loop {
+        let list = ctx.infra.list_s3(&prefix, continuation).await?;
+        keys.extend(list.keys);
+        match list.continuation {
+            Some(v) => continuation = Some(v),
+            None => break,
         }
     }
+    for key in keys {
+        ctx.infra.delete_s3(&key).await?;
+    }
 
+    ctx.infra.delete_paper(paper.id).await?;
     Ok(())
 }
 
-fn determine_key(pmcid: &str, blueprint: &Blueprint) -> String {
+/// Uploads the artifact, moving on to its `fallback` candidates
+/// while it fails validation.
+async fn upload_with_fallback<I: HttpInfra + S3Infra + Send + Sync + 'static>(
+    mut stream: ArtifactStream,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+    progress: &Progress,
+) -> Result<StoredArtifact, FetchError> {
+    loop {
+        let pmc_id = stream.pmc_id.clone();
+        let source_url = stream.source_url.clone();
+        let fallback = std::mem::take(&mut stream.fallback);
+        match upload_artifact(stream, blueprint, ctx.clone(), progress).await {
+            Err(e @ FetchError::InvalidPdfSource(_)) if !fallback.is_empty() => {
+                tracing::warn!(
+                    "Trying the next PDF source of paper {pmc_id} after {source_url}: {e}"
+                );
+                let allowed_domains = &blueprint.fetcher.allowed_domains;
+                stream = pdf::fetch_pdf(pmc_id, fallback, allowed_domains, ctx.clone()).await?;
+            }
+            res => return res,
+        }
+    }
+}
+
+async fn upload_artifact<I: S3Infra + Send + Sync + 'static>(
+    stream: ArtifactStream,
+    blueprint: &Blueprint,
+    ctx: InfraContext<I>,
+    progress: &Progress,
+) -> Result<StoredArtifact, FetchError> {
+    let artifact = stream.artifact;
+    let pmc_id = stream.pmc_id.clone();
+    let source_url = stream.source_url.clone();
+    let key = determine_key(&stream.pmc_id, artifact, blueprint);
+    progress.emit(FetchEvent::DownloadStarted {
+        pmc_id: pmc_id.clone(),
+        artifact,
+        content_length: stream.content_length,
+    });
+
+    // The validated stream errors out on transport failures and
+    // unexpected payloads, which aborts the object instead of storing it.
+    let (byte_stream, verdict) = validate(stream);
+    let (byte_stream, digest) = checksum(byte_stream);
+    let res = ctx
+        .infra
+        .put_s3(&key, content_type(artifact), byte_stream)
+        .await;
+    if let Some(e) = verdict.failure() {
+        return Err(e);
+    }
+    res?;
+    let digest = digest.get().ok_or_else(|| {
+        InfraError::StreamAborted(format!("{key}: upload finished before the stream ended"))
+    })?;
+
+    if let Err(e) = ctx
+        .infra
+        .set_metadata_s3(&key, content_type(artifact), checksum_metadata(&digest))
+        .await
+    {
+        // The artifact is skipped, so its object isn't kept either.
+        if let Err(e) = ctx.infra.delete_s3(&key).await {
+            tracing::warn!("Failed to delete {key}: {e}");
+        }
+        return Err(e.into());
+    }
+    progress.emit(FetchEvent::DownloadFinished {
+        pmc_id,
+        artifact,
+        bytes: digest.size_bytes,
+    });
+
+    Ok(StoredArtifact {
+        artifact,
+        key,
+        source_url,
+        digest,
+    })
+}
+
+/// Value of `paper_artifacts.kind`
+fn artifact_kind(artifact: Artifact) -> &'static str {
+    match artifact {
+        Artifact::Pdf => "pdf",
+        Artifact::Xml => "xml",
+    }
+}
+
+fn content_type(artifact: Artifact) -> ContentType {
+    match artifact {
+        Artifact::Pdf => ContentType::Pdf,
+        Artifact::Xml => ContentType::Xml,
+    }
+}
+
+/// Every paper gets its own "directory", e.g. `prefix/PMC123`
+pub(crate) fn paper_prefix(pmcid: &str, blueprint: &Blueprint) -> String {
     let prefix = sterilize_prefix(&blueprint.fetcher.upload_path_prefix);
     format!("{prefix}/{pmcid}")
 }
 
+pub(crate) fn determine_key(pmcid: &str, artifact: Artifact, blueprint: &Blueprint) -> String {
+    let prefix = paper_prefix(pmcid, blueprint);
+    let extension = artifact_kind(artifact);
+    format!("{prefix}/{pmcid}.{extension}")
+}
+
 // Always returns a valid path
 // WITHOUT tailing slash (`/`)
 fn sterilize_prefix<T: ToString>(prefix: T) -> String {
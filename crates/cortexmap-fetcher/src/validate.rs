@@ -0,0 +1,398 @@
+use crate::FetchError;
+use crate::fetch::{ArtifactStream, invalid_source};
+use cortexmap_core::blueprint::Artifact;
+use cortexmap_infra::{ContentStream, InfraError};
+use futures::StreamExt;
+use std::sync::{Arc, Mutex};
+
+/// Bytes a complete file of some kind starts and ends with.
+struct Signature {
+    /// The file starts with one of these
+    magics: &'static [&'static [u8]],
+    /// Whether a byte order mark and whitespace may come before the magic bytes
+    skip_leading: bool,
+    trailer: &'static [u8],
+}
+
+const PDF_SIGNATURE: Signature = Signature {
+    magics: &[b"%PDF-"],
+    skip_leading: false,
+    trailer: b"%%EOF",
+};
+
+/// JATS documents always have `<article>` as root element, either
+/// right away or after the XML declaration. HTML error pages start
+/// with `<` as well, so that alone isn't enough.
+const XML_SIGNATURE: Signature = Signature {
+    magics: &[b"<?xml", b"<article"],
+    skip_leading: true,
+    trailer: b"</article>",
+};
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Readers look for `%%EOF` within the last 1024 bytes,
+/// since some producers append whitespace or junk after it.
+const TRAILER_WINDOW: usize = 1024;
+
+/// Leading bytes looked at for the magic bytes before giving up.
+const HEAD_WINDOW: usize = 1024;
+
+fn signature(artifact: Artifact) -> &'static Signature {
+    match artifact {
+        Artifact::Pdf => &PDF_SIGNATURE,
+        Artifact::Xml => &XML_SIGNATURE,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Head {
+    Matched,
+    /// Too short to tell yet
+    Undecided,
+    Mismatched,
+}
+
+impl Signature {
+    /// Whether the first bytes of a file (as many as were received)
+    /// start with one of the magics.
+    fn check_head(&self, head: &[u8]) -> Head {
+        let mut rest = head;
+        if self.skip_leading {
+            if BOM.starts_with(rest) {
+                return Head::Undecided;
+            }
+            rest = rest.strip_prefix(BOM).unwrap_or(rest);
+            rest = rest.trim_ascii_start();
+        }
+        if self.magics.iter().any(|v| rest.starts_with(v)) {
+            Head::Matched
+        } else if head.len() < HEAD_WINDOW && self.magics.iter().any(|v| v.starts_with(rest)) {
+            Head::Undecided
+        } else {
+            Head::Mismatched
+        }
+    }
+
+    /// The magics as `a` or `b`, for messages.
+    fn describe_magics(&self) -> String {
+        self.magics
+            .iter()
+            .map(|v| format!("`{}`", String::from_utf8_lossy(v)))
+            .collect::<Vec<_>>()
+            .join(" or ")
+    }
+}
+
+/// Keeps track of what went through the stream so far
+/// without holding more than the head and the tail of it.
+struct Inspector {
+    signature: &'static Signature,
+    /// The first bytes, until the magic bytes were found among them
+    head: Vec<u8>,
+    head_matched: bool,
+    tail: Vec<u8>,
+    len: u64,
+}
+
+impl Inspector {
+    fn new(signature: &'static Signature) -> Self {
+        Self {
+            signature,
+            head: Vec::new(),
+            head_matched: false,
+            tail: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> Result<(), String> {
+        self.len += chunk.len() as u64;
+
+        if !self.head_matched {
+            let missing = HEAD_WINDOW - self.head.len();
+            self.head
+                .extend_from_slice(&chunk[..missing.min(chunk.len())]);
+            match self.signature.check_head(&self.head) {
+                Head::Matched => {
+                    self.head_matched = true;
+                    self.head = Vec::new();
+                }
+                Head::Undecided => {}
+                Head::Mismatched => return Err(self.missing_magic()),
+            }
+        }
+
+        self.tail.extend_from_slice(chunk);
+        if self.tail.len() > TRAILER_WINDOW {
+            self.tail.drain(..self.tail.len() - TRAILER_WINDOW);
+        }
+        Ok(())
+    }
+
+    fn finish(&self, content_length: Option<u64>) -> Result<(), String> {
+        if !self.head_matched {
+            return Err(self.missing_magic());
+        }
+        if let Some(expected) = content_length
+            && expected != self.len
+        {
+            return Err(format!(
+                "truncated: received {} of {expected} bytes",
+                self.len
+            ));
+        }
+        let trailer = self.signature.trailer;
+        if !self.tail.windows(trailer.len()).any(|w| w == trailer) {
+            return Err(format!(
+                "missing `{}` trailer",
+                String::from_utf8_lossy(trailer)
+            ));
+        }
+        Ok(())
+    }
+
+    fn missing_magic(&self) -> String {
+        format!("missing {} header", self.signature.describe_magics())
+    }
+}
+
+/// Peeks at the start of the stream and rejects it unless it begins with
+/// the magic bytes of the artifact. The peeked bytes are put back, so the
+/// returned stream is complete.
+pub(crate) async fn check_head(mut artifact: ArtifactStream) -> Result<ArtifactStream, FetchError> {
+    let signature = signature(artifact.artifact);
+    let mut peeked = Vec::new();
+    let mut head = Vec::new();
+    let mut verdict = signature.check_head(&head);
+    while verdict == Head::Undecided {
+        match artifact.stream.next().await {
+            Some(Ok(chunk)) => {
+                let missing = HEAD_WINDOW - head.len();
+                head.extend_from_slice(&chunk[..missing.min(chunk.len())]);
+                peeked.push(Ok(chunk));
+                verdict = signature.check_head(&head);
+            }
+            Some(Err(e)) => return Err(e.into()),
+            None => break,
+        }
+    }
+
+    if verdict != Head::Matched {
+        return Err(invalid_source(
+            artifact.artifact,
+            format!(
+                "{}: {} doesn't start with {}",
+                artifact.pmc_id,
+                artifact.source_url,
+                signature.describe_magics()
+            ),
+        ));
+    }
+
+    artifact.stream = Box::pin(futures::stream::iter(peeked).chain(artifact.stream));
+    Ok(artifact)
+}
+
+/// Remembers why a [`validate`] stream was aborted.
+pub struct Verdict {
+    pmc_id: String,
+    artifact: Artifact,
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl Verdict {
+    /// Returns the validation failure, if the stream was rejected.
+    /// Transport errors are not reported here, they surface from
+    /// whoever consumed the stream.
+    pub fn failure(&self) -> Option<FetchError> {
+        self.failure
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|reason| invalid_source(self.artifact, format!("{}: {reason}", self.pmc_id)))
+    }
+}
+
+struct ValidationState {
+    inner: ContentStream,
+    inspector: Inspector,
+    content_length: Option<u64>,
+    failure: Arc<Mutex<Option<String>>>,
+    done: bool,
+}
+
+impl ValidationState {
+    fn reject(&mut self, reason: String) -> InfraError {
+        self.done = true;
+        *self.failure.lock().unwrap() = Some(reason.clone());
+        InfraError::StreamAborted(reason)
+    }
+}
+
+/// Wraps the artifact stream so that it ends with an error instead of
+/// completing whenever the download fails or the payload isn't a
+/// complete file of the expected kind. Consumers are expected to
+/// discard everything they received if the stream yields an error.
+pub fn validate(artifact: ArtifactStream) -> (ContentStream, Verdict) {
+    let failure = Arc::new(Mutex::new(None));
+    let state = ValidationState {
+        inner: artifact.stream,
+        inspector: Inspector::new(signature(artifact.artifact)),
+        content_length: artifact.content_length,
+        failure: failure.clone(),
+        done: false,
+    };
+
+    let stream = futures::stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+        match state.inner.next().await {
+            Some(Ok(chunk)) => match state.inspector.feed(&chunk) {
+                Ok(()) => Some((Ok(chunk), state)),
+                Err(reason) => Some((Err(state.reject(reason)), state)),
+            },
+            Some(Err(e)) => {
+                state.done = true;
+                Some((Err(e), state))
+            }
+            None => {
+                state.done = true;
+                match state.inspector.finish(state.content_length) {
+                    Ok(()) => None,
+                    Err(reason) => Some((Err(state.reject(reason)), state)),
+                }
+            }
+        }
+    });
+
+    (
+        Box::pin(stream),
+        Verdict {
+            pmc_id: artifact.pmc_id,
+            artifact: artifact.artifact,
+            failure,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn inspect(chunks: &[&[u8]], content_length: Option<u64>) -> Result<(), String> {
+        inspect_as(&PDF_SIGNATURE, chunks, content_length)
+    }
+
+    fn inspect_as(
+        signature: &'static Signature,
+        chunks: &[&[u8]],
+        content_length: Option<u64>,
+    ) -> Result<(), String> {
+        let mut inspector = Inspector::new(signature);
+        for chunk in chunks {
+            inspector.feed(chunk)?;
+        }
+        inspector.finish(content_length)
+    }
+
+    #[test]
+    fn test_valid_pdf() {
+        assert!(inspect(&[b"%PDF-1.7\n...\n%%EOF\n"], None).is_ok());
+        assert!(inspect(&[b"%P", b"DF", b"-1.4 body ", b"%%E", b"OF"], Some(19)).is_ok());
+    }
+
+    #[test]
+    fn test_html_payload() {
+        let err = inspect(&[b"<!DOCTYPE html><html></html>"], None).unwrap_err();
+        assert!(err.contains("%PDF-"));
+    }
+
+    #[test]
+    fn test_missing_trailer() {
+        let err = inspect(&[b"%PDF-1.7\nhalf a document"], None).unwrap_err();
+        assert!(err.contains("%%EOF"));
+    }
+
+    #[test]
+    fn test_content_length_mismatch() {
+        let err = inspect(&[b"%PDF-1.7 %%EOF"], Some(100)).unwrap_err();
+        assert!(err.contains("truncated"));
+    }
+
+    #[test]
+    fn test_trailer_window() {
+        let mut body = b"%PDF-1.7 %%EOF".to_vec();
+        body.extend(std::iter::repeat_n(b' ', TRAILER_WINDOW));
+        assert!(inspect(&[&body], None).is_err());
+    }
+
+    #[test]
+    fn test_empty_payload() {
+        assert!(inspect(&[], None).is_err());
+    }
+
+    #[test]
+    fn test_jats_xml() {
+        let xml: &[u8] = b"<?xml version=\"1.0\"?><article><body/></article>\n";
+        assert!(inspect_as(&XML_SIGNATURE, &[xml], None).is_ok());
+
+        let err = inspect_as(&XML_SIGNATURE, &[b"<html><body/></html>"], None).unwrap_err();
+        assert!(err.contains("<article"));
+        assert!(inspect_as(&XML_SIGNATURE, &[b"{\"error\": 1}"], None).is_err());
+    }
+
+    #[test]
+    fn test_xml_head() {
+        let article: &[u8] = b"<article><body/></article>";
+        assert!(inspect_as(&XML_SIGNATURE, &[article], None).is_ok());
+        // A byte order mark and whitespace may come first, in any chunks.
+        let chunks: &[&[u8]] = &[b"\xEF\xBB", b"\xBF\n  <?x", b"ml?><article/></article>"];
+        assert!(inspect_as(&XML_SIGNATURE, chunks, None).is_ok());
+
+        for page in [
+            &b"<!DOCTYPE html><html></html></article>"[..],
+            b"\n<html><body></body></html></article>",
+        ] {
+            let err = inspect_as(&XML_SIGNATURE, &[page], None).unwrap_err();
+            assert!(err.contains("`<?xml` or `<article`"), "{err}");
+        }
+        // Endless whitespace is given up on.
+        let blank = vec![b' '; HEAD_WINDOW + 1];
+        assert!(inspect_as(&XML_SIGNATURE, &[&blank], None).is_err());
+    }
+
+    fn artifact_stream(chunks: &[&'static [u8]]) -> ArtifactStream {
+        ArtifactStream {
+            stream: Box::pin(futures::stream::iter(
+                chunks
+                    .iter()
+                    .map(|v| Ok(Bytes::from_static(v)))
+                    .collect::<Vec<_>>(),
+            )),
+            pmc_id: "PMC1".to_string(),
+            artifact: Artifact::Pdf,
+            source_url: "https://example.com/PMC1.pdf".to_string(),
+            content_length: None,
+            fallback: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_head() {
+        let stream = check_head(artifact_stream(&[b"%P", b"DF-1.7", b" %%EOF"]))
+            .await
+            .unwrap();
+        let body = stream.stream.map(|v| v.unwrap().to_vec()).concat().await;
+        assert_eq!(&body[..], b"%PDF-1.7 %%EOF");
+
+        let err = check_head(artifact_stream(&[b"<html>"]))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, FetchError::InvalidPdfSource(_)));
+        assert!(check_head(artifact_stream(&[])).await.is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::FetchError;
+use crate::checksum::{Checksum, checksum};
+use cortexmap_infra::{DatabaseInfra, InfraContext, InfraError, Paper, S3Infra};
+use futures::TryStreamExt;
+use std::collections::HashSet;
+
+/// Outcome of comparing a stored object with the digest recorded for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Integrity {
+    Intact,
+    Corrupted {
+        expected: Checksum,
+        actual: Checksum,
+    },
+    /// The object was stored before checksums were recorded.
+    Unrecorded,
+}
+
+/// Integrity of one of the objects recorded for a paper.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectIntegrity {
+    pub s3_key: String,
+    pub integrity: Integrity,
+}
+
+/// Re-reads every object recorded for the paper, its artifacts and
+/// supplementary files, and compares each with its stored digest.
+pub async fn verify<I: DatabaseInfra + S3Infra + Send + Sync + 'static>(
+    pmc_id: &str,
+    ctx: InfraContext<I>,
+) -> Result<Vec<ObjectIntegrity>, FetchError> {
+    let paper = ctx
+        .infra
+        .get_paper_by_pmcid(pmc_id)
+        .await?
+        .filter(|v| v.status == Paper::STORED)
+        .ok_or_else(|| FetchError::PaperNotFound(pmc_id.to_string()))?;
+
+    // The primary artifact comes first, papers stored before artifacts
+    // were recorded have it on their row only.
+    let mut recorded = vec![(paper.s3_key, paper.sha256, paper.size_bytes)];
+    for artifact in ctx.infra.get_artifacts(paper.id).await? {
+        recorded.push((artifact.s3_key, artifact.sha256, artifact.size_bytes));
+    }
+    for file in ctx.infra.get_supplementary_files(paper.id).await? {
+        recorded.push((file.s3_key, Some(file.sha256), Some(file.size_bytes)));
+    }
+
+    let mut seen = HashSet::new();
+    let mut verified = Vec::new();
+    for (s3_key, sha256, size_bytes) in recorded {
+        if !seen.insert(s3_key.clone()) {
+            continue;
+        }
+        let integrity = match (sha256, size_bytes) {
+            (Some(sha256), Some(size_bytes)) => {
+                let size_bytes = u64::try_from(size_bytes).map_err(|_| {
+                    FetchError::InvalidRecord(format!("{s3_key}: size of {size_bytes} bytes"))
+                })?;
+                let expected = Checksum { sha256, size_bytes };
+                verify_object(&s3_key, expected, ctx.clone()).await?
+            }
+            _ => Integrity::Unrecorded,
+        };
+        verified.push(ObjectIntegrity { s3_key, integrity });
+    }
+    Ok(verified)
+}
+
+async fn verify_object<I: S3Infra + Send + Sync + 'static>(
+    key: &str,
+    expected: Checksum,
+    ctx: InfraContext<I>,
+) -> Result<Integrity, FetchError> {
+    let (stream, handle) = checksum(ctx.infra.get_s3(key).await?);
+    stream.try_for_each(|_| async { Ok(()) }).await?;
+    let actual = handle.get().ok_or_else(|| {
+        InfraError::StreamAborted(format!("{key}: read finished before the stream ended"))
+    })?;
+
+    if actual == expected {
+        Ok(Integrity::Intact)
+    } else {
+        Ok(Integrity::Corrupted { expected, actual })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cortexmap_infra::{
+        ContentType, NewPaperArtifact, NewPendingPaper, NewSupplementaryFile, PaperUpdate,
+    };
+    use mock_infra::MockInfra;
+    use sha2::{Digest, Sha256};
+
+    fn sha256(content: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(content))
+    }
+
+    #[tokio::test]
+    async fn test_verify_every_object() {
+        let infra = MockInfra::new();
+        let pending = infra
+            .db
+            .insert_pending_paper(NewPendingPaper {
+                pmc_id: "PMC1".to_string(),
+                s3_key: "papers/PMC1/PMC1.pdf".to_string(),
+                uid: "uid-PMC1".to_string(),
+                query: "cortex".to_string(),
+                reached_via: "query".to_string(),
+                reached_from: None,
+                snowball_depth: 0,
+            })
+            .await
+            .unwrap();
+        let objects: [(&str, &[u8]); 3] = [
+            ("papers/PMC1/PMC1.pdf", b"%PDF-1.7\n%%EOF\n"),
+            ("papers/PMC1/PMC1.xml", b"<article/>"),
+            ("papers/PMC1/supplementary/table1.csv", b"a,b\n"),
+        ];
+        for (key, content) in objects {
+            infra.s3.insert(key, ContentType::Text, content);
+        }
+        let artifact = |kind: &str, (key, content): (&str, &[u8])| NewPaperArtifact {
+            paper_id: pending.id,
+            kind: kind.to_string(),
+            s3_key: key.to_string(),
+            sha256: sha256(content),
+            size_bytes: content.len() as i64,
+        };
+        let update = PaperUpdate {
+            sha256: Some(sha256(objects[0].1)),
+            size_bytes: Some(objects[0].1.len() as i64),
+            ..Default::default()
+        };
+        infra
+            .db
+            .finalize_paper(
+                pending.id,
+                update,
+                vec![artifact("pdf", objects[0]), artifact("xml", objects[1])],
+            )
+            .await
+            .unwrap();
+        let (key, content) = objects[2];
+        infra
+            .db
+            .insert_supplementary_file(NewSupplementaryFile {
+                paper_id: pending.id,
+                filename: "table1.csv".to_string(),
+                s3_key: key.to_string(),
+                content_type: ContentType::Text.to_string(),
+                sha256: sha256(content),
+                size_bytes: content.len() as i64,
+            })
+            .await
+            .unwrap();
+
+        let verified = verify("PMC1", infra.context()).await.unwrap();
+        assert_eq!(
+            verified
+                .iter()
+                .map(|v| v.s3_key.as_str())
+                .collect::<Vec<_>>(),
+            objects.map(|(key, _)| key)
+        );
+        assert!(verified.iter().all(|v| v.integrity == Integrity::Intact));
+
+        // A corrupted supplementary file shows, the artifacts stay intact.
+        infra.s3.insert(key, ContentType::Text, "a,c\n");
+        let verified = verify("PMC1", infra.context()).await.unwrap();
+        assert!(matches!(verified[2].integrity, Integrity::Corrupted { .. }));
+        assert_eq!(verified[0].integrity, Integrity::Intact);
+
+        // A missing object fails instead of passing for intact.
+        infra.s3.delete_s3(objects[1].0).await.unwrap();
+        assert!(verify("PMC1", infra.context()).await.is_err());
+        assert!(matches!(
+            verify("PMC2", infra.context()).await,
+            Err(FetchError::PaperNotFound(_))
+        ));
+    }
+}
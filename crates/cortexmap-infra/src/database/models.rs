@@ -1,4 +1,7 @@
-use super::papers;
+use super::{
+    citations, fetch_items, fetch_runs, mesh_headings, paper_artifacts, paper_authors,
+    paper_keywords, paper_metadata, papers, supplementary_files,
+};
 use diesel::prelude::*;
 
 /// Represents a new paper to be inserted into the database.
@@ -10,10 +13,40 @@ pub struct NewPaper {
     pub s3_key: String,
     pub uid: String,
     pub query: String,
+    /// Hex encoded SHA-256 digest of the stored object
+    pub sha256: String,
+    /// Size of the stored object in bytes
+    pub size_bytes: i64,
+    /// URL the PDF was downloaded from, if one was stored
+    pub pdf_source_url: Option<String>,
+    /// `query`, `reference` or `citation`
+    pub reached_via: String,
+    /// PMCID of the paper whose references or citations led here
+    pub reached_from: Option<String>,
+    /// Citation hops away from the query's papers
+    pub snowball_depth: i32,
+}
+
+/// Claims a PMCID for a paper whose objects are about to be uploaded.
+/// The paper is [`Paper::PENDING`] until it is finalized with its
+/// digests and artifacts.
+#[derive(Insertable, Debug)]
+#[diesel(table_name = papers)]
+pub struct NewPendingPaper {
+    pub pmc_id: String,
+    /// Key the primary artifact is going to be stored under
+    pub s3_key: String,
+    pub uid: String,
+    pub query: String,
+    pub reached_via: String,
+    pub reached_from: Option<String>,
+    pub snowball_depth: i32,
 }
 
 /// Represents a paper record retrieved from the database.
 /// Includes all fields including the auto-generated id and timestamp.
+/// `s3_key`, `sha256` and `size_bytes` describe the primary artifact,
+/// every stored artifact is listed in `paper_artifacts`.
 #[derive(Queryable, Selectable, Debug, Clone)]
 #[diesel(table_name = papers)]
 pub struct Paper {
@@ -23,4 +56,320 @@ pub struct Paper {
     pub uid: String,
     pub query: String,
     pub created_at: chrono::NaiveDateTime,
+    /// `None` for papers stored before checksums were recorded
+    pub sha256: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub pdf_source_url: Option<String>,
+    pub reached_via: String,
+    pub reached_from: Option<String>,
+    pub snowball_depth: i32,
+    /// One of [`Paper::PENDING`], [`Paper::STORED`] or [`Paper::ABANDONED`]
+    pub status: String,
+}
+
+impl Paper {
+    /// Claimed, its objects are being uploaded
+    pub const PENDING: &str = "pending";
+    /// Every object is uploaded and recorded
+    pub const STORED: &str = "stored";
+    /// Left pending by a crashed attempt, its objects
+    /// and record are about to be removed
+    pub const ABANDONED: &str = "abandoned";
+}
+
+/// Changes to a stored paper, `None` fields are left as they are.
+/// The PMCID and the uid identify the paper and can't be changed.
+#[derive(AsChangeset, Debug, Clone, Default)]
+#[diesel(table_name = papers)]
+pub struct PaperUpdate {
+    pub s3_key: Option<String>,
+    pub query: Option<String>,
+    pub sha256: Option<String>,
+    pub size_bytes: Option<i64>,
+    /// `Some(None)` clears it
+    pub pdf_source_url: Option<Option<String>>,
+}
+
+impl PaperUpdate {
+    /// Whether nothing would be changed
+    pub fn is_empty(&self) -> bool {
+        self.s3_key.is_none()
+            && self.query.is_none()
+            && self.sha256.is_none()
+            && self.size_bytes.is_none()
+            && self.pdf_source_url.is_none()
+    }
+}
+
+/// Which papers to list, `None` fields match every paper.
+#[derive(Debug, Clone, Default)]
+pub struct PaperFilter {
+    /// Papers stored for this query
+    pub query: Option<String>,
+    /// Papers stored at or after this time
+    pub created_from: Option<chrono::NaiveDateTime>,
+    /// Papers stored before this time
+    pub created_until: Option<chrono::NaiveDateTime>,
+}
+
+impl PaperFilter {
+    pub fn matches(&self, paper: &Paper) -> bool {
+        self.query.as_ref().is_none_or(|v| *v == paper.query)
+            && self.created_from.is_none_or(|v| paper.created_at >= v)
+            && self.created_until.is_none_or(|v| paper.created_at < v)
+    }
+}
+
+/// A page of papers, which are listed by id.
+/// The next page starts after the last paper of this one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRequest {
+    /// Id of the last paper of the previous page, `None` for the first page
+    pub after_id: Option<i64>,
+    pub limit: i64,
+}
+
+impl PageRequest {
+    pub fn first(limit: i64) -> Self {
+        Self {
+            after_id: None,
+            limit,
+        }
+    }
+
+    /// The page after `papers`, or `None` if it was the last one.
+    pub fn next(&self, papers: &[Paper]) -> Option<Self> {
+        match papers.last() {
+            Some(last) if papers.len() as i64 >= self.limit => Some(Self {
+                after_id: Some(last.id),
+                limit: self.limit,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Represents a new artifact (PDF, XML, ...) stored for a paper.
+#[derive(Insertable, Debug)]
+#[diesel(table_name = paper_artifacts)]
+pub struct NewPaperArtifact {
+    pub paper_id: i64,
+    /// Kind of the artifact, e.g. `pdf` or `xml`
+    pub kind: String,
+    pub s3_key: String,
+    pub sha256: String,
+    pub size_bytes: i64,
+}
+
+/// Represents an artifact record retrieved from the database.
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = paper_artifacts)]
+pub struct PaperArtifact {
+    pub id: i64,
+    pub paper_id: i64,
+    pub kind: String,
+    pub s3_key: String,
+    /// `None` for artifacts stored before checksums were recorded
+    pub sha256: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// Represents a new supplementary file stored for a paper.
+#[derive(Insertable, Debug)]
+#[diesel(table_name = supplementary_files)]
+pub struct NewSupplementaryFile {
+    pub paper_id: i64,
+    /// Path of the file inside the supplementary archive
+    pub filename: String,
+    pub s3_key: String,
+    pub content_type: String,
+    pub sha256: String,
+    pub size_bytes: i64,
+}
+
+/// Represents a supplementary file record retrieved from the database.
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = supplementary_files)]
+pub struct SupplementaryFile {
+    pub id: i64,
+    pub paper_id: i64,
+    pub filename: String,
+    pub s3_key: String,
+    pub content_type: String,
+    pub sha256: String,
+    pub size_bytes: i64,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// Bibliographic metadata of a paper as returned by Europe PMC.
+/// There are no generated columns, so the same type is used for
+/// inserting and reading records.
+#[derive(Insertable, Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = paper_metadata)]
+pub struct PaperMetadata {
+    pub paper_id: i64,
+    pub title: Option<String>,
+    pub abstract_text: Option<String>,
+    pub journal_title: Option<String>,
+    pub journal_issn: Option<String>,
+    pub publication_date: Option<chrono::NaiveDate>,
+    pub doi: Option<String>,
+    pub pmid: Option<String>,
+    pub license: Option<String>,
+    pub is_open_access: Option<bool>,
+}
+
+/// An author of a paper, `position` is the (zero based) place in the author list.
+#[derive(Insertable, Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = paper_authors)]
+pub struct PaperAuthor {
+    pub paper_id: i64,
+    pub position: i32,
+    pub full_name: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub orcid: Option<String>,
+}
+
+/// A MeSH descriptor the paper was indexed with.
+#[derive(Insertable, Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = mesh_headings)]
+pub struct MeshHeading {
+    pub paper_id: i64,
+    pub descriptor_name: String,
+    pub major_topic: bool,
+}
+
+/// A keyword given by the authors of a paper.
+#[derive(Insertable, Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = paper_keywords)]
+pub struct PaperKeyword {
+    pub paper_id: i64,
+    pub keyword: String,
+}
+
+/// Everything known about a paper besides its files,
+/// spread over the normalized metadata tables.
+#[derive(Debug, Clone)]
+pub struct Bibliography {
+    pub metadata: PaperMetadata,
+    pub authors: Vec<PaperAuthor>,
+    pub mesh_headings: Vec<MeshHeading>,
+    pub keywords: Vec<PaperKeyword>,
+}
+
+/// An edge of the citation graph: `citing` lists `cited` among its references.
+/// Works are identified as `SOURCE:ID`, e.g. `MED:12345` or `PMC:PMC67890`.
+#[derive(Insertable, Queryable, Selectable, Debug, Clone, PartialEq, Eq, Hash)]
+#[diesel(table_name = citations)]
+pub struct Citation {
+    pub citing: String,
+    pub cited: String,
+}
+
+impl Citation {
+    /// The edge with work `from` replaced by `to`.
+    pub fn renamed(self, from: &str, to: &str) -> Self {
+        let rename = |v: String| if v == from { to.to_string() } else { v };
+        Self {
+            citing: rename(self.citing),
+            cited: rename(self.cited),
+        }
+    }
+}
+
+/// Represents a new harvest run of a query.
+#[derive(Insertable, Debug)]
+#[diesel(table_name = fetch_runs)]
+pub struct NewFetchRun {
+    pub query: String,
+    pub page_size: i64,
+}
+
+/// Represents a harvest run retrieved from the database.
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = fetch_runs)]
+pub struct FetchRun {
+    pub id: i64,
+    pub query: String,
+    pub page_size: i64,
+    /// Europe PMC `cursorMark` of the next page to fetch
+    pub cursor_mark: String,
+    /// One of [`FetchRun::RUNNING`] or [`FetchRun::COMPLETED`]
+    pub status: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl FetchRun {
+    /// Cursor of the first page of the results
+    pub const FIRST_CURSOR: &str = "*";
+    /// Pages or items are left, also the state of interrupted runs
+    pub const RUNNING: &str = "running";
+    /// Every page was fetched and every item attempted
+    pub const COMPLETED: &str = "completed";
+}
+
+/// Represents a search hit recorded for a run.
+#[derive(Insertable, Debug)]
+#[diesel(table_name = fetch_items)]
+pub struct NewFetchItem {
+    pub run_id: i64,
+    pub pmc_id: String,
+    /// The search hit as JSON
+    pub search_record: Option<String>,
+}
+
+/// Represents a run's item retrieved from the database.
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = fetch_items)]
+pub struct FetchItem {
+    pub id: i64,
+    pub run_id: i64,
+    pub pmc_id: String,
+    pub search_record: Option<String>,
+    /// One of [`FetchItem::PENDING`], [`FetchItem::DONE`] or [`FetchItem::FAILED`]
+    pub state: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    /// The stored paper, once the item is done
+    pub paper_id: Option<i64>,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl FetchItem {
+    /// Recorded but not attempted yet
+    pub const PENDING: &str = "pending";
+    /// The paper is stored
+    pub const DONE: &str = "done";
+    /// The last attempt failed, see `last_error`
+    pub const FAILED: &str = "failed";
+}
+
+/// Outcome of an attempt at a run's item.
+#[derive(AsChangeset, Debug)]
+#[diesel(table_name = fetch_items, treat_none_as_null = true)]
+pub struct FetchItemOutcome {
+    pub state: String,
+    pub last_error: Option<String>,
+    pub paper_id: Option<i64>,
+}
+
+impl FetchItemOutcome {
+    pub fn done(paper_id: i64) -> Self {
+        Self {
+            state: FetchItem::DONE.to_string(),
+            last_error: None,
+            paper_id: Some(paper_id),
+        }
+    }
+
+    pub fn failed(error: String) -> Self {
+        Self {
+            state: FetchItem::FAILED.to_string(),
+            last_error: Some(error),
+            paper_id: None,
+        }
+    }
 }
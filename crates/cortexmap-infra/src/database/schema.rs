@@ -1,5 +1,91 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    citations (citing, cited) {
+        citing -> Text,
+        cited -> Text,
+    }
+}
+
+diesel::table! {
+    fetch_items (id) {
+        id -> Int8,
+        run_id -> Int8,
+        pmc_id -> Text,
+        search_record -> Nullable<Text>,
+        state -> Text,
+        attempts -> Int4,
+        last_error -> Nullable<Text>,
+        paper_id -> Nullable<Int8>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    fetch_runs (id) {
+        id -> Int8,
+        query -> Text,
+        page_size -> Int8,
+        cursor_mark -> Text,
+        status -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    mesh_headings (paper_id, descriptor_name) {
+        paper_id -> Int8,
+        descriptor_name -> Text,
+        major_topic -> Bool,
+    }
+}
+
+diesel::table! {
+    paper_artifacts (id) {
+        id -> Int8,
+        paper_id -> Int8,
+        kind -> Text,
+        s3_key -> Text,
+        sha256 -> Nullable<Text>,
+        size_bytes -> Nullable<Int8>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    paper_authors (paper_id, position) {
+        paper_id -> Int8,
+        position -> Int4,
+        full_name -> Text,
+        first_name -> Nullable<Text>,
+        last_name -> Nullable<Text>,
+        orcid -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    paper_keywords (paper_id, keyword) {
+        paper_id -> Int8,
+        keyword -> Text,
+    }
+}
+
+diesel::table! {
+    paper_metadata (paper_id) {
+        paper_id -> Int8,
+        title -> Nullable<Text>,
+        abstract_text -> Nullable<Text>,
+        journal_title -> Nullable<Text>,
+        journal_issn -> Nullable<Text>,
+        publication_date -> Nullable<Date>,
+        doi -> Nullable<Text>,
+        pmid -> Nullable<Text>,
+        license -> Nullable<Text>,
+        is_open_access -> Nullable<Bool>,
+    }
+}
+
 diesel::table! {
     papers (id) {
         id -> Int8,
@@ -8,5 +94,47 @@ diesel::table! {
         uid -> Text,
         query -> Text,
         created_at -> Timestamp,
+        sha256 -> Nullable<Text>,
+        size_bytes -> Nullable<Int8>,
+        pdf_source_url -> Nullable<Text>,
+        reached_via -> Text,
+        reached_from -> Nullable<Text>,
+        snowball_depth -> Int4,
+        status -> Text,
+    }
+}
+
+diesel::table! {
+    supplementary_files (id) {
+        id -> Int8,
+        paper_id -> Int8,
+        filename -> Text,
+        s3_key -> Text,
+        content_type -> Text,
+        sha256 -> Text,
+        size_bytes -> Int8,
+        created_at -> Timestamp,
     }
 }
+
+diesel::joinable!(fetch_items -> fetch_runs (run_id));
+diesel::joinable!(fetch_items -> papers (paper_id));
+diesel::joinable!(mesh_headings -> papers (paper_id));
+diesel::joinable!(paper_artifacts -> papers (paper_id));
+diesel::joinable!(paper_authors -> papers (paper_id));
+diesel::joinable!(paper_keywords -> papers (paper_id));
+diesel::joinable!(paper_metadata -> papers (paper_id));
+diesel::joinable!(supplementary_files -> papers (paper_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    citations,
+    fetch_items,
+    fetch_runs,
+    mesh_headings,
+    paper_artifacts,
+    paper_authors,
+    paper_keywords,
+    paper_metadata,
+    papers,
+    supplementary_files,
+);
@@ -1,12 +1,29 @@
 use aws_sdk_s3::config::http::HttpResponse;
 use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::operation::abort_multipart_upload::AbortMultipartUploadError;
+use aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadError;
+use aws_sdk_s3::operation::copy_object::CopyObjectError;
+use aws_sdk_s3::operation::create_multipart_upload::CreateMultipartUploadError;
+use aws_sdk_s3::operation::delete_object::DeleteObjectError;
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::head_object::HeadObjectError;
+use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
 use aws_sdk_s3::operation::put_object::PutObjectError;
+use aws_sdk_s3::operation::upload_part::UploadPartError;
+use aws_sdk_s3::primitives::ByteStreamError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum InfraError {
+    /// The request couldn't be sent or its body couldn't be read.
     #[error("Http error: {0}")]
-    HttpError(#[from] reqwest::Error),
+    HttpError(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("Http status {status} for {url}")]
+    HttpStatus {
+        status: http::StatusCode,
+        url: String,
+    },
 
     #[error("Database error: {0}")]
     Database(#[from] diesel::result::Error),
@@ -17,6 +34,116 @@ pub enum InfraError {
     #[error("Pool error: {0}")]
     R2D2PoolError(#[from] diesel::r2d2::PoolError),
 
+    /// No connection of the async pool could be had in time.
+    #[error("Pool error: {0}")]
+    AsyncPoolError(Box<dyn std::error::Error + Send + Sync>),
+
+    /// A migration couldn't be read or applied.
+    #[error("Migration error: {0}")]
+    Migration(Box<dyn std::error::Error + Send + Sync>),
+
+    /// The database schema is behind or ahead of the migrations built in.
+    #[error("Schema mismatch: {0}")]
+    SchemaMismatch(String),
+
+    // SDK errors are boxed since they are several times larger than the other variants.
     #[error("Put object error: {0}")]
-    PutObjectError(#[from] SdkError<PutObjectError, HttpResponse>),
+    PutObjectError(Box<SdkError<PutObjectError, HttpResponse>>),
+
+    #[error("Get object error: {0}")]
+    GetObjectError(Box<SdkError<GetObjectError, HttpResponse>>),
+
+    #[error("Copy object error: {0}")]
+    CopyObjectError(Box<SdkError<CopyObjectError, HttpResponse>>),
+
+    #[error("Head object error: {0}")]
+    HeadObjectError(Box<SdkError<HeadObjectError, HttpResponse>>),
+
+    #[error("List objects error: {0}")]
+    ListObjectsError(Box<SdkError<ListObjectsV2Error, HttpResponse>>),
+
+    #[error("Delete object error: {0}")]
+    DeleteObjectError(Box<SdkError<DeleteObjectError, HttpResponse>>),
+
+    /// One of the requests of a multipart upload failed.
+    #[error("Multipart upload error: {0}")]
+    MultipartUpload(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("Byte stream error: {0}")]
+    ByteStreamError(#[from] ByteStreamError),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// A setting the infra was built from is out of its range.
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    /// The key can't be mapped to a location in the storage.
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
+    /// The content stream handed to an infra was aborted by its producer.
+    #[error("Stream aborted: {0}")]
+    StreamAborted(String),
+}
+
+impl From<SdkError<PutObjectError, HttpResponse>> for InfraError {
+    fn from(value: SdkError<PutObjectError, HttpResponse>) -> Self {
+        InfraError::PutObjectError(Box::new(value))
+    }
+}
+
+impl From<SdkError<GetObjectError, HttpResponse>> for InfraError {
+    fn from(value: SdkError<GetObjectError, HttpResponse>) -> Self {
+        InfraError::GetObjectError(Box::new(value))
+    }
+}
+
+impl From<SdkError<CopyObjectError, HttpResponse>> for InfraError {
+    fn from(value: SdkError<CopyObjectError, HttpResponse>) -> Self {
+        InfraError::CopyObjectError(Box::new(value))
+    }
+}
+
+impl From<SdkError<HeadObjectError, HttpResponse>> for InfraError {
+    fn from(value: SdkError<HeadObjectError, HttpResponse>) -> Self {
+        InfraError::HeadObjectError(Box::new(value))
+    }
+}
+
+impl From<SdkError<ListObjectsV2Error, HttpResponse>> for InfraError {
+    fn from(value: SdkError<ListObjectsV2Error, HttpResponse>) -> Self {
+        InfraError::ListObjectsError(Box::new(value))
+    }
+}
+
+impl From<SdkError<DeleteObjectError, HttpResponse>> for InfraError {
+    fn from(value: SdkError<DeleteObjectError, HttpResponse>) -> Self {
+        InfraError::DeleteObjectError(Box::new(value))
+    }
+}
+
+impl From<SdkError<CreateMultipartUploadError, HttpResponse>> for InfraError {
+    fn from(value: SdkError<CreateMultipartUploadError, HttpResponse>) -> Self {
+        InfraError::MultipartUpload(Box::new(value))
+    }
+}
+
+impl From<SdkError<UploadPartError, HttpResponse>> for InfraError {
+    fn from(value: SdkError<UploadPartError, HttpResponse>) -> Self {
+        InfraError::MultipartUpload(Box::new(value))
+    }
+}
+
+impl From<SdkError<CompleteMultipartUploadError, HttpResponse>> for InfraError {
+    fn from(value: SdkError<CompleteMultipartUploadError, HttpResponse>) -> Self {
+        InfraError::MultipartUpload(Box::new(value))
+    }
+}
+
+impl From<SdkError<AbortMultipartUploadError, HttpResponse>> for InfraError {
+    fn from(value: SdkError<AbortMultipartUploadError, HttpResponse>) -> Self {
+        InfraError::MultipartUpload(Box::new(value))
+    }
 }
@@ -1,4 +1,4 @@
-use reqwest::Method;
+use std::fmt::{Display, Formatter};
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum MethodCM {
@@ -13,18 +13,24 @@ pub enum MethodCM {
     TRACE,
 }
 
-impl From<MethodCM> for Method {
-    fn from(value: MethodCM) -> Self {
-        match value {
-            MethodCM::GET => Method::GET,
-            MethodCM::POST => Method::POST,
-            MethodCM::PUT => Method::PUT,
-            MethodCM::PATCH => Method::PATCH,
-            MethodCM::DELETE => Method::DELETE,
-            MethodCM::HEAD => Method::HEAD,
-            MethodCM::OPTIONS => Method::OPTIONS,
-            MethodCM::CONNECT => Method::CONNECT,
-            MethodCM::TRACE => Method::TRACE,
+impl MethodCM {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MethodCM::GET => "GET",
+            MethodCM::POST => "POST",
+            MethodCM::PUT => "PUT",
+            MethodCM::PATCH => "PATCH",
+            MethodCM::DELETE => "DELETE",
+            MethodCM::HEAD => "HEAD",
+            MethodCM::OPTIONS => "OPTIONS",
+            MethodCM::CONNECT => "CONNECT",
+            MethodCM::TRACE => "TRACE",
         }
     }
 }
+
+impl Display for MethodCM {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -1,7 +1,7 @@
 mod method;
-// mod request;
-// mod response;
+mod request;
+mod response;
 
 pub use method::*;
-// pub use request::*;
-// pub use response::*;
+pub use request::*;
+pub use response::*;
@@ -1,24 +1,68 @@
-use crate::http::{HttpError, MethodCM};
+use crate::http::MethodCM;
 use bytes::Bytes;
-use reqwest::header::HeaderMap;
-use reqwest::{Request, RequestBuilder};
-use url::Url;
+use http::HeaderMap;
+use http::header::{HeaderName, HeaderValue};
+use std::time::Duration;
 
+/// A request for an [`HttpInfra`](crate::HttpInfra), independent of the client sending it.
+#[derive(Debug, Clone)]
 pub struct RequestCM {
-    pub url: Url,
     pub method: MethodCM,
+    pub url: String,
     pub headers: HeaderMap,
+    /// Appended to the query string of the URL, encoded by the client
+    pub query: Vec<(String, String)>,
     pub body: Option<Bytes>,
+    /// Time the whole request may take, the client's default applies if not set
+    pub timeout: Option<Duration>,
+    /// Whether the client follows redirects, otherwise a `3xx`
+    /// response is returned as it is
+    pub follow_redirects: bool,
 }
 
 impl RequestCM {
-    pub fn into_reqwest(self) -> Result<Request, HttpError> {
-        let request = Request::new(self.method.into(), self.url);
-        let mut builder =
-            RequestBuilder::from_parts(Default::default(), request).headers(self.headers);
-        if let Some(body) = self.body {
-            builder = builder.body(body);
+    pub fn new(method: MethodCM, url: impl Into<String>) -> Self {
+        Self {
+            method,
+            url: url.into(),
+            headers: HeaderMap::new(),
+            query: Vec::new(),
+            body: None,
+            timeout: None,
+            follow_redirects: true,
         }
-        Ok(builder.build()?)
+    }
+
+    pub fn get(url: impl Into<String>) -> Self {
+        Self::new(MethodCM::GET, url)
+    }
+
+    pub fn post(url: impl Into<String>) -> Self {
+        Self::new(MethodCM::POST, url)
+    }
+
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn query(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn follow_redirects(mut self, follow: bool) -> Self {
+        self.follow_redirects = follow;
+        self
     }
 }
@@ -1,13 +1,137 @@
-use bytes::Bytes;
-use derive_builder::Builder;
-use reqwest::StatusCode;
-use reqwest::header::HeaderMap;
+use crate::{ContentStream, InfraError};
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, StatusCode};
 
-#[derive(Builder)]
-#[builder(setter(into))]
+/// The response of an [`HttpInfra`](crate::HttpInfra), its body
+/// is streamed as it arrives.
 pub struct ResponseCM {
     pub status: StatusCode,
-    #[builder(default)]
     pub headers: HeaderMap,
-    pub body: Bytes,
+    /// URL the response came from, after redirects
+    pub url: String,
+    body: ContentStream,
+}
+
+/// The body is left out, it can only be read once.
+impl std::fmt::Debug for ResponseCM {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseCM")
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResponseCM {
+    pub fn new(status: StatusCode, url: impl Into<String>, body: ContentStream) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            url: url.into(),
+            body,
+        }
+    }
+
+    /// A response with a body that is already complete.
+    pub fn from_bytes(status: StatusCode, url: impl Into<String>, body: impl Into<Bytes>) -> Self {
+        let body = body.into();
+        let stream = futures::stream::iter((!body.is_empty()).then_some(Ok(body)));
+        Self::new(status, url, Box::pin(stream))
+    }
+
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Value of the `Content-Type` header, if it's there and readable.
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get(CONTENT_TYPE)?.to_str().ok()
+    }
+
+    /// Value of the `Content-Length` header, if it's there and valid.
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers
+            .get(CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    /// Turns client and server error statuses into [`InfraError::HttpStatus`].
+    pub fn error_for_status(self) -> Result<Self, InfraError> {
+        if self.status.is_client_error() || self.status.is_server_error() {
+            return Err(InfraError::HttpStatus {
+                status: self.status,
+                url: self.url,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Reads the whole body.
+    pub async fn bytes(mut self) -> Result<Bytes, InfraError> {
+        let mut body = BytesMut::new();
+        while let Some(chunk) = self.body.next().await {
+            body.extend_from_slice(&chunk?);
+        }
+        Ok(body.freeze())
+    }
+
+    pub fn into_stream(self) -> ContentStream {
+        self.body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[tokio::test]
+    async fn test_body() {
+        let chunks = vec![
+            Ok(Bytes::from_static(b"%PDF-")),
+            Ok(Bytes::from_static(b"1.7")),
+        ];
+        let response = ResponseCM::new(
+            StatusCode::OK,
+            "http://localhost/a.pdf",
+            Box::pin(futures::stream::iter(chunks)),
+        );
+        assert_eq!(response.bytes().await.unwrap(), "%PDF-1.7");
+
+        let response = ResponseCM::from_bytes(StatusCode::OK, "http://localhost", "");
+        assert_eq!(response.into_stream().count().await, 0);
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/pdf"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("8"));
+        let response = ResponseCM::from_bytes(StatusCode::OK, "http://localhost", "%PDF-1.7")
+            .with_headers(headers);
+        assert_eq!(response.content_type(), Some("application/pdf"));
+        assert_eq!(response.content_length(), Some(8));
+
+        let response = ResponseCM::from_bytes(StatusCode::OK, "http://localhost", "");
+        assert_eq!(response.content_type(), None);
+        assert_eq!(response.content_length(), None);
+    }
+
+    #[test]
+    fn test_error_for_status() {
+        let response = ResponseCM::from_bytes(StatusCode::NOT_FOUND, "http://localhost/x", "");
+        assert!(matches!(
+            response.error_for_status(),
+            Err(InfraError::HttpStatus { status: StatusCode::NOT_FOUND, url }) if url == "http://localhost/x"
+        ));
+        let response = ResponseCM::from_bytes(StatusCode::NO_CONTENT, "http://localhost/x", "");
+        assert!(response.error_for_status().is_ok());
+    }
 }
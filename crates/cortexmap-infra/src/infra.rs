@@ -1,14 +1,25 @@
 use std::fmt::{Display, Formatter};
 use crate::error::InfraError;
-use crate::{NewPaper, Paper};
+use crate::{
+    Bibliography, Citation, FetchItem, FetchItemOutcome, FetchRun, NewFetchItem, NewFetchRun,
+    NewPaper, NewPaperArtifact, NewPendingPaper, NewSupplementaryFile, PageRequest, Paper,
+    PaperArtifact, PaperFilter, PaperUpdate, RequestCM, ResponseCM, SupplementaryFile,
+};
 use bytes::Bytes;
 use futures::Stream;
-use reqwest::Response;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
 
+/// Byte stream of an object's content, read from or written to an infra.
+pub type ContentStream = Pin<Box<dyn Stream<Item = Result<Bytes, InfraError>> + Send + Sync>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ContentType {
     Text,
     Pdf,
+    Xml,
+    /// Any other media type, e.g. `text/csv`
+    Other(String),
 }
 
 impl Display for ContentType {
@@ -20,29 +31,236 @@ impl Display for ContentType {
             ContentType::Pdf => {
                 write!(f, "application/pdf")
             }
+            ContentType::Xml => {
+                write!(f, "application/xml")
+            }
+            ContentType::Other(media_type) => {
+                write!(f, "{media_type}")
+            }
         }
     }
 }
 
+/// Size, content type and user metadata of a stored object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectHead {
+    pub size: u64,
+    pub content_type: String,
+    pub metadata: HashMap<String, String>,
+}
+
+/// A page of the keys under a prefix, in lexicographic order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectList {
+    pub keys: Vec<String>,
+    /// Continues the listing with the next page, `None` on the last page
+    pub continuation: Option<String>,
+}
+
 #[async_trait::async_trait]
-pub trait HttpInfra {
-    // TODO: add some wrapper instead of using reqwest::Response
-    async fn get(&self, url: &str) -> Result<Response, InfraError>;
-    async fn post(&self, url: &str, body: Option<Bytes>) -> Result<Response, InfraError>;
+pub trait HttpInfra: Sync {
+    /// Sends the request, whatever the status of the response is
+    async fn send(&self, request: RequestCM) -> Result<ResponseCM, InfraError>;
+
+    /// `GET`s the URL, error statuses are returned as [`InfraError::HttpStatus`]
+    async fn get(&self, url: &str) -> Result<ResponseCM, InfraError> {
+        self.send(RequestCM::get(url)).await?.error_for_status()
+    }
+
+    /// `POST`s the body to the URL, error statuses are returned as [`InfraError::HttpStatus`]
+    async fn post(&self, url: &str, body: Option<Bytes>) -> Result<ResponseCM, InfraError> {
+        let mut request = RequestCM::post(url);
+        request.body = body;
+        self.send(request).await?.error_for_status()
+    }
 }
 
 #[async_trait::async_trait]
 pub trait DatabaseInfra {
     /// Insert a new paper into the database
     async fn insert_paper(&self, new_paper: NewPaper) -> Result<Paper, InfraError>;
+
+    /// Claim a PMCID for a paper before uploading its objects, fails
+    /// like [`DatabaseInfra::insert_paper`] if it is taken
+    async fn insert_pending_paper(&self, paper: NewPendingPaper) -> Result<Paper, InfraError>;
+
+    /// Apply `update` to a pending paper, record its artifacts and mark it
+    /// [`Paper::STORED`], atomically. Fails with `NotFound` once the paper
+    /// isn't pending anymore, e.g. since a recovery sweep abandoned it.
+    async fn finalize_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Paper, InfraError>;
+
+    /// Mark the papers pending for at least `older_than` abandoned, so
+    /// they can't be finalized anymore, and list every abandoned paper,
+    /// including the ones of an earlier sweep that didn't finish. Their
+    /// age is taken by the database's clock, the one `created_at` is
+    /// set by.
+    async fn abandon_pending_papers(
+        &self,
+        older_than: std::time::Duration,
+    ) -> Result<Vec<Paper>, InfraError>;
+
+    /// Look up a paper by its PMCID
+    async fn get_paper_by_pmcid(&self, pmc_id: &str) -> Result<Option<Paper>, InfraError>;
+
+    /// Look up a paper by its uid
+    async fn get_paper_by_uid(&self, uid: &str) -> Result<Option<Paper>, InfraError>;
+
+    /// Which of the PMCIDs belong to stored papers
+    async fn get_existing_pmcids(&self, pmc_ids: &[String]) -> Result<HashSet<String>, InfraError>;
+
+    /// List the stored papers matching `filter`, a page at a time, by id
+    async fn list_papers(
+        &self,
+        filter: PaperFilter,
+        page: PageRequest,
+    ) -> Result<Vec<Paper>, InfraError>;
+
+    /// Change a stored paper, returns `None` if there is no such paper
+    async fn update_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+    ) -> Result<Option<Paper>, InfraError>;
+
+    /// Delete a paper along with its artifacts, supplementary files and
+    /// bibliography records, returns whether there was such a paper.
+    /// The stored objects are left in the bucket.
+    async fn delete_paper(&self, paper_id: i64) -> Result<bool, InfraError>;
+
+    /// Record the artifacts stored for a paper
+    async fn insert_artifacts(
+        &self,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Vec<PaperArtifact>, InfraError>;
+
+    /// List the artifacts stored for a paper
+    async fn get_artifacts(&self, paper_id: i64) -> Result<Vec<PaperArtifact>, InfraError>;
+
+    /// Record a supplementary file stored for a paper
+    async fn insert_supplementary_file(
+        &self,
+        file: NewSupplementaryFile,
+    ) -> Result<SupplementaryFile, InfraError>;
+
+    /// List the supplementary files stored for a paper
+    async fn get_supplementary_files(
+        &self,
+        paper_id: i64,
+    ) -> Result<Vec<SupplementaryFile>, InfraError>;
+
+    /// Store the bibliographic metadata of a paper
+    async fn insert_bibliography(&self, bibliography: Bibliography) -> Result<(), InfraError>;
+
+    /// Read back the bibliographic metadata of a paper
+    async fn get_bibliography(&self, paper_id: i64) -> Result<Option<Bibliography>, InfraError>;
+
+    /// Record edges of the citation graph, returns how many were new
+    async fn insert_citations(&self, citations: Vec<Citation>) -> Result<usize, InfraError>;
+
+    /// List the works `work` cites (its out-neighbours)
+    async fn get_cited(&self, work: &str) -> Result<Vec<Citation>, InfraError>;
+
+    /// List the works citing `work` (its in-neighbours)
+    async fn get_citing(&self, work: &str) -> Result<Vec<Citation>, InfraError>;
+
+    /// List every recorded edge of the citation graph
+    async fn get_citations(&self) -> Result<Vec<Citation>, InfraError>;
+
+    /// Map PubMed ids to the PMCIDs of the stored papers with them
+    async fn get_pmcids_by_pmids(
+        &self,
+        pmids: &[String],
+    ) -> Result<HashMap<String, String>, InfraError>;
+
+    /// Move the edges of work `from` over to work `to`, merging
+    /// the two nodes. Returns how many edges `to` gained.
+    async fn rename_work(&self, from: &str, to: &str) -> Result<usize, InfraError>;
+
+    /// Start a new harvest run
+    async fn insert_fetch_run(&self, run: NewFetchRun) -> Result<FetchRun, InfraError>;
+
+    /// Look up a harvest run by its id
+    async fn get_fetch_run(&self, run_id: i64) -> Result<Option<FetchRun>, InfraError>;
+
+    /// List the runs that haven't completed, oldest first
+    async fn get_unfinished_fetch_runs(&self) -> Result<Vec<FetchRun>, InfraError>;
+
+    /// Record the items of a fetched page and move the run's cursor past it,
+    /// atomically, so a page is never skipped nor recorded twice.
+    /// Items already recorded for the run are left as they are,
+    /// returns how many were new.
+    async fn insert_fetch_page(
+        &self,
+        run_id: i64,
+        items: Vec<NewFetchItem>,
+        next_cursor_mark: &str,
+    ) -> Result<usize, InfraError>;
+
+    /// List all items of a run
+    async fn get_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError>;
+
+    /// List the items of a run that are pending or failed
+    async fn get_unfinished_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError>;
+
+    /// Record the outcome of an attempt at an item
+    async fn update_fetch_item(
+        &self,
+        item_id: i64,
+        outcome: FetchItemOutcome,
+    ) -> Result<FetchItem, InfraError>;
+
+    /// Set the status of a run
+    async fn update_fetch_run_status(&self, run_id: i64, status: &str) -> Result<(), InfraError>;
 }
 
 #[async_trait::async_trait]
 pub trait S3Infra {
+    /// Upload `content` under `key`.
+    /// An `Err` item in the stream aborts the upload,
+    /// so no (partial) object is left behind.
     async fn put_s3(
         &self,
         key: &str,
         content_type: ContentType,
-        content: Pin<Box<dyn Stream<Item = Bytes> + Send + Sync>>,
+        content: ContentStream,
     ) -> Result<(), InfraError>;
+
+    /// Read back the content stored under `key`.
+    async fn get_s3(&self, key: &str) -> Result<ContentStream, InfraError>;
+
+    /// Replace the user metadata of an existing object.
+    /// Metadata can't be added while streaming, since it is sent
+    /// before the content, so values derived from the content
+    /// (like checksums) are attached afterwards with this.
+    async fn set_metadata_s3(
+        &self,
+        key: &str,
+        content_type: ContentType,
+        metadata: HashMap<String, String>,
+    ) -> Result<(), InfraError>;
+
+    /// Size, content type and metadata of the object under `key`,
+    /// `None` if there is no such object.
+    async fn head_s3(&self, key: &str) -> Result<Option<ObjectHead>, InfraError>;
+
+    /// List the keys starting with `prefix`, a page at a time. Pass the
+    /// previous page's `continuation` to get the next one.
+    async fn list_s3(
+        &self,
+        prefix: &str,
+        continuation: Option<String>,
+    ) -> Result<ObjectList, InfraError>;
+
+    /// Delete the object under `key`. Like S3, deleting
+    /// a missing object is not an error.
+    async fn delete_s3(&self, key: &str) -> Result<(), InfraError>;
+
+    /// Copy the object under `from` to `to`, along with
+    /// its content type and metadata.
+    async fn copy_s3(&self, from: &str, to: &str) -> Result<(), InfraError>;
 }
@@ -0,0 +1,306 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use bytes::Bytes;
+use cortexmap_infra::{HttpInfra, InfraError, RequestCM, ResponseCM};
+use http::header::{HeaderName, HeaderValue};
+use http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Environment variable that switches cassettes to [`CassetteMode::Record`]
+/// when set to `record`.
+pub const CASSETTE_MODE_VAR: &str = "CORTEXMAP_CASSETTES";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Requests go to the inner client, and the interactions
+    /// are written to the cassette
+    Record,
+    /// Requests are answered from the cassette, requests
+    /// that aren't on it fail
+    Replay,
+}
+
+impl CassetteMode {
+    /// [`CassetteMode::Record`] if [`CASSETTE_MODE_VAR`] says so,
+    /// [`CassetteMode::Replay`] otherwise.
+    pub fn from_env() -> Self {
+        match std::env::var(CASSETTE_MODE_VAR).as_deref() {
+            Ok("record") => CassetteMode::Record,
+            _ => CassetteMode::Replay,
+        }
+    }
+}
+
+/// A request and the response it got, as stored on a cassette.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    query: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<RecordedBody>,
+}
+
+impl From<&RequestCM> for RecordedRequest {
+    fn from(request: &RequestCM) -> Self {
+        Self {
+            method: request.method.to_string(),
+            url: request.url.clone(),
+            query: request.query.clone(),
+            body: request.body.as_ref().map(RecordedBody::new),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: RecordedBody,
+}
+
+/// Text bodies are kept readable, anything else (like PDFs) is base64 encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RecordedBody {
+    Text(String),
+    Base64(String),
+}
+
+impl RecordedBody {
+    fn new(body: &Bytes) -> Self {
+        match std::str::from_utf8(body) {
+            Ok(text) => RecordedBody::Text(text.to_string()),
+            Err(_) => RecordedBody::Base64(BASE64.encode(body)),
+        }
+    }
+
+    fn to_bytes(&self) -> Result<Bytes, InfraError> {
+        match self {
+            RecordedBody::Text(text) => Ok(Bytes::from(text.clone())),
+            RecordedBody::Base64(encoded) => Ok(BASE64
+                .decode(encoded)
+                .map_err(std::io::Error::other)?
+                .into()),
+        }
+    }
+}
+
+impl RecordedResponse {
+    async fn record(response: ResponseCM) -> Result<Self, InfraError> {
+        let status = response.status.as_u16();
+        let url = response.url.clone();
+        let headers = response
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = RecordedBody::new(&response.bytes().await?);
+
+        Ok(Self {
+            status,
+            url,
+            headers,
+            body,
+        })
+    }
+
+    fn to_response(&self) -> Result<ResponseCM, InfraError> {
+        let status = StatusCode::from_u16(self.status).map_err(std::io::Error::other)?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            headers.append(
+                HeaderName::try_from(name.as_str()).map_err(std::io::Error::other)?,
+                HeaderValue::try_from(value.as_str()).map_err(std::io::Error::other)?,
+            );
+        }
+
+        Ok(ResponseCM::from_bytes(status, &self.url, self.body.to_bytes()?).with_headers(headers))
+    }
+}
+
+#[derive(Debug)]
+struct Tape {
+    interactions: Vec<Interaction>,
+    /// Which interactions were replayed already
+    replayed: Vec<bool>,
+}
+
+/// Records the interactions of the inner client to a cassette file,
+/// or replays them from it, so tests based on real Europe PMC
+/// responses run offline and deterministically.
+///
+/// Replayed requests are matched on method, URL, query and body.
+/// Identical requests get the recorded responses in order, the last
+/// one over and over once they are used up.
+pub struct CassetteHttpInfra<H> {
+    inner: H,
+    path: PathBuf,
+    mode: CassetteMode,
+    tape: Mutex<Tape>,
+}
+
+impl<H> CassetteHttpInfra<H> {
+    /// Opens the cassette at `path`. Recording starts a new cassette,
+    /// replaying fails if there is none.
+    pub fn new(inner: H, path: impl Into<PathBuf>, mode: CassetteMode) -> Result<Self, InfraError> {
+        let path = path.into();
+        let interactions = match mode {
+            CassetteMode::Record => Vec::new(),
+            CassetteMode::Replay => {
+                serde_json::from_slice(&std::fs::read(&path)?).map_err(std::io::Error::other)?
+            }
+        };
+
+        Ok(Self {
+            inner,
+            path,
+            mode,
+            tape: Mutex::new(Tape {
+                replayed: vec![false; interactions.len()],
+                interactions,
+            }),
+        })
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    /// Writes the recorded interactions to the cassette file.
+    fn save(&self, interactions: &[Interaction]) -> Result<(), InfraError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_vec_pretty(interactions).map_err(std::io::Error::other)?;
+        std::fs::write(&self.path, content)?;
+        Ok(())
+    }
+
+    fn replay(&self, request: &RecordedRequest) -> Result<ResponseCM, InfraError> {
+        let mut tape = self.tape.lock().unwrap();
+        let matching = (0..tape.interactions.len())
+            .filter(|&i| &tape.interactions[i].request == request)
+            .collect::<Vec<_>>();
+        let Some(&last) = matching.last() else {
+            return Err(InfraError::HttpError(
+                format!(
+                    "{} {} is not on cassette {}",
+                    request.method,
+                    request.url,
+                    self.path.display()
+                )
+                .into(),
+            ));
+        };
+        let index = matching
+            .into_iter()
+            .find(|&i| !tape.replayed[i])
+            .unwrap_or(last);
+        tape.replayed[index] = true;
+
+        tape.interactions[index].response.to_response()
+    }
+}
+
+#[async_trait::async_trait]
+impl<H: HttpInfra + Send + Sync> HttpInfra for CassetteHttpInfra<H> {
+    async fn send(&self, request: RequestCM) -> Result<ResponseCM, InfraError> {
+        let recorded_request = RecordedRequest::from(&request);
+        match self.mode {
+            CassetteMode::Replay => self.replay(&recorded_request),
+            CassetteMode::Record => {
+                let response = self.inner.send(request).await?;
+                let recorded_response = RecordedResponse::record(response).await?;
+                let response = recorded_response.to_response()?;
+
+                let mut tape = self.tape.lock().unwrap();
+                tape.interactions.push(Interaction {
+                    request: recorded_request,
+                    response: recorded_response,
+                });
+                tape.replayed.push(true);
+                self.save(&tape.interactions)?;
+                Ok(response)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockHttpInfra, MockResponse};
+
+    const PDF: &[u8] = b"%PDF-1.7\n\xe2\xe3\xcf\xd3\n%%EOF\n";
+
+    fn temp_cassette() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "cortexmap-cassette-{}-{}.json",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let path = temp_cassette();
+        let http = MockHttpInfra::default();
+        http.route(
+            "http://test/search",
+            [MockResponse::json("page 1"), MockResponse::json("page 2")],
+        );
+        http.route("http://test/a.pdf", [MockResponse::pdf(PDF)]);
+
+        let recorder = CassetteHttpInfra::new(http, &path, CassetteMode::Record).unwrap();
+        for url in [
+            "http://test/search",
+            "http://test/search",
+            "http://test/a.pdf",
+        ] {
+            recorder.get(url).await.unwrap();
+        }
+        assert_eq!(recorder.inner.requested_urls().len(), 3);
+
+        let player =
+            CassetteHttpInfra::new(MockHttpInfra::default(), &path, CassetteMode::Replay).unwrap();
+        let body = |url: &'static str| {
+            let player = &player;
+            async move { player.get(url).await.unwrap().bytes().await.unwrap() }
+        };
+        assert_eq!(body("http://test/search").await, "page 1");
+        assert_eq!(body("http://test/search").await, "page 2");
+        assert_eq!(body("http://test/search").await, "page 2");
+        let pdf = player.get("http://test/a.pdf").await.unwrap();
+        assert_eq!(pdf.content_type(), Some("application/pdf"));
+        assert_eq!(pdf.bytes().await.unwrap(), PDF);
+        assert!(player.inner.requested_urls().is_empty());
+
+        let err = player.get("http://test/b.pdf").await.unwrap_err();
+        assert!(err.to_string().contains("is not on cassette"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_cassette() {
+        assert!(
+            CassetteHttpInfra::new(
+                MockHttpInfra::default(),
+                temp_cassette(),
+                CassetteMode::Replay
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,755 @@
+use crate::faults::Faults;
+use cortexmap_infra::{
+    Bibliography, Citation, DatabaseInfra, FetchItem, FetchItemOutcome, FetchRun, InfraError,
+    NewFetchItem, NewFetchRun, NewPaper, NewPaperArtifact, NewPendingPaper, NewSupplementaryFile,
+    PageRequest, Paper, PaperArtifact, PaperFilter, PaperUpdate, SupplementaryFile,
+};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+struct Tables {
+    last_id: i64,
+    papers: Vec<Paper>,
+    artifacts: Vec<PaperArtifact>,
+    supplementary_files: Vec<SupplementaryFile>,
+    bibliographies: HashMap<i64, Bibliography>,
+    citations: Vec<Citation>,
+    fetch_runs: Vec<FetchRun>,
+    fetch_items: Vec<FetchItem>,
+}
+
+impl Tables {
+    /// Ids are unique across tables, which is enough for tests.
+    fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    /// Fails like the unique keys of `papers` would.
+    fn check_paper_keys(&self, pmc_id: &str, uid: &str) -> Result<(), InfraError> {
+        if self.papers.iter().any(|v| v.pmc_id == pmc_id) {
+            return Err(unique_violation("papers_pmc_id_key"));
+        }
+        if self.papers.iter().any(|v| v.uid == uid) {
+            return Err(unique_violation("papers_uid_key"));
+        }
+        Ok(())
+    }
+
+    fn insert_artifacts(
+        &mut self,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Vec<PaperArtifact>, InfraError> {
+        for (i, artifact) in artifacts.iter().enumerate() {
+            let duplicate = self
+                .artifacts
+                .iter()
+                .any(|v| v.paper_id == artifact.paper_id && v.kind == artifact.kind)
+                || artifacts[..i]
+                    .iter()
+                    .any(|v| v.paper_id == artifact.paper_id && v.kind == artifact.kind);
+            if duplicate {
+                return Err(unique_violation("paper_artifacts_paper_id_kind_key"));
+            }
+        }
+
+        let mut inserted = Vec::with_capacity(artifacts.len());
+        for artifact in artifacts {
+            inserted.push(PaperArtifact {
+                id: self.next_id(),
+                paper_id: artifact.paper_id,
+                kind: artifact.kind,
+                s3_key: artifact.s3_key,
+                sha256: Some(artifact.sha256),
+                size_bytes: Some(artifact.size_bytes),
+                created_at: now(),
+            });
+        }
+        self.artifacts.extend(inserted.iter().cloned());
+        Ok(inserted)
+    }
+}
+
+fn apply_update(paper: &mut Paper, update: PaperUpdate) {
+    if let Some(s3_key) = update.s3_key {
+        paper.s3_key = s3_key;
+    }
+    if let Some(query) = update.query {
+        paper.query = query;
+    }
+    if let Some(sha256) = update.sha256 {
+        paper.sha256 = Some(sha256);
+    }
+    if let Some(size_bytes) = update.size_bytes {
+        paper.size_bytes = Some(size_bytes);
+    }
+    if let Some(pdf_source_url) = update.pdf_source_url {
+        paper.pdf_source_url = pdf_source_url;
+    }
+}
+
+fn now() -> chrono::NaiveDateTime {
+    chrono::Utc::now().naive_utc()
+}
+
+/// The error Postgres reports for a violated unique constraint.
+fn unique_violation(constraint: &str) -> InfraError {
+    InfraError::Database(DieselError::DatabaseError(
+        DatabaseErrorKind::UniqueViolation,
+        Box::new(format!(
+            "duplicate key value violates unique constraint {constraint}"
+        )),
+    ))
+}
+
+/// Keeps the rows in vectors, enforcing the unique constraints
+/// of the schema. Faults are injected by method name.
+#[derive(Debug, Default)]
+pub struct MockDatabaseInfra {
+    tables: Mutex<Tables>,
+    pub faults: Faults,
+}
+
+impl MockDatabaseInfra {
+    pub fn papers(&self) -> Vec<Paper> {
+        self.tables.lock().unwrap().papers.clone()
+    }
+
+    pub fn artifacts(&self) -> Vec<PaperArtifact> {
+        self.tables.lock().unwrap().artifacts.clone()
+    }
+
+    pub fn supplementary_files(&self) -> Vec<SupplementaryFile> {
+        self.tables.lock().unwrap().supplementary_files.clone()
+    }
+
+    pub fn citations(&self) -> Vec<Citation> {
+        self.tables.lock().unwrap().citations.clone()
+    }
+
+    pub fn fetch_runs(&self) -> Vec<FetchRun> {
+        self.tables.lock().unwrap().fetch_runs.clone()
+    }
+
+    pub fn fetch_items(&self) -> Vec<FetchItem> {
+        self.tables.lock().unwrap().fetch_items.clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl DatabaseInfra for MockDatabaseInfra {
+    async fn insert_paper(&self, new_paper: NewPaper) -> Result<Paper, InfraError> {
+        self.faults.check("insert_paper")?;
+        let mut tables = self.tables.lock().unwrap();
+        tables.check_paper_keys(&new_paper.pmc_id, &new_paper.uid)?;
+
+        let paper = Paper {
+            id: tables.next_id(),
+            pmc_id: new_paper.pmc_id,
+            s3_key: new_paper.s3_key,
+            uid: new_paper.uid,
+            query: new_paper.query,
+            created_at: now(),
+            sha256: Some(new_paper.sha256),
+            size_bytes: Some(new_paper.size_bytes),
+            pdf_source_url: new_paper.pdf_source_url,
+            reached_via: new_paper.reached_via,
+            reached_from: new_paper.reached_from,
+            snowball_depth: new_paper.snowball_depth,
+            status: Paper::STORED.to_string(),
+        };
+        tables.papers.push(paper.clone());
+        Ok(paper)
+    }
+
+    async fn insert_pending_paper(&self, paper: NewPendingPaper) -> Result<Paper, InfraError> {
+        self.faults.check("insert_pending_paper")?;
+        let mut tables = self.tables.lock().unwrap();
+        tables.check_paper_keys(&paper.pmc_id, &paper.uid)?;
+
+        let paper = Paper {
+            id: tables.next_id(),
+            pmc_id: paper.pmc_id,
+            s3_key: paper.s3_key,
+            uid: paper.uid,
+            query: paper.query,
+            created_at: now(),
+            sha256: None,
+            size_bytes: None,
+            pdf_source_url: None,
+            reached_via: paper.reached_via,
+            reached_from: paper.reached_from,
+            snowball_depth: paper.snowball_depth,
+            status: Paper::PENDING.to_string(),
+        };
+        tables.papers.push(paper.clone());
+        Ok(paper)
+    }
+
+    async fn finalize_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Paper, InfraError> {
+        self.faults.check("finalize_paper")?;
+        let mut tables = self.tables.lock().unwrap();
+        if !tables
+            .papers
+            .iter()
+            .any(|v| v.id == paper_id && v.status == Paper::PENDING)
+        {
+            return Err(InfraError::Database(DieselError::NotFound));
+        }
+        // Nothing is changed if an artifact collides.
+        tables.insert_artifacts(artifacts)?;
+
+        let paper = tables.papers.iter_mut().find(|v| v.id == paper_id).unwrap();
+        apply_update(paper, update);
+        paper.status = Paper::STORED.to_string();
+        Ok(paper.clone())
+    }
+
+    async fn abandon_pending_papers(
+        &self,
+        older_than: std::time::Duration,
+    ) -> Result<Vec<Paper>, InfraError> {
+        self.faults.check("abandon_pending_papers")?;
+        let cutoff = chrono::Duration::from_std(older_than)
+            .ok()
+            .and_then(|v| now().checked_sub_signed(v))
+            .unwrap_or(chrono::NaiveDateTime::MIN);
+        let mut tables = self.tables.lock().unwrap();
+        for paper in &mut tables.papers {
+            if paper.status == Paper::PENDING && paper.created_at <= cutoff {
+                paper.status = Paper::ABANDONED.to_string();
+            }
+        }
+        Ok(tables
+            .papers
+            .iter()
+            .filter(|v| v.status == Paper::ABANDONED)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_paper_by_pmcid(&self, pmc_id: &str) -> Result<Option<Paper>, InfraError> {
+        self.faults.check("get_paper_by_pmcid")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.papers.iter().find(|v| v.pmc_id == pmc_id).cloned())
+    }
+
+    async fn get_paper_by_uid(&self, uid: &str) -> Result<Option<Paper>, InfraError> {
+        self.faults.check("get_paper_by_uid")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.papers.iter().find(|v| v.uid == uid).cloned())
+    }
+
+    async fn get_existing_pmcids(&self, pmc_ids: &[String]) -> Result<HashSet<String>, InfraError> {
+        self.faults.check("get_existing_pmcids")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .papers
+            .iter()
+            .filter(|v| pmc_ids.contains(&v.pmc_id) && v.status == Paper::STORED)
+            .map(|v| v.pmc_id.clone())
+            .collect())
+    }
+
+    async fn list_papers(
+        &self,
+        filter: PaperFilter,
+        page: PageRequest,
+    ) -> Result<Vec<Paper>, InfraError> {
+        self.faults.check("list_papers")?;
+        let tables = self.tables.lock().unwrap();
+        // Ids are increasing, so the papers are in id order already.
+        Ok(tables
+            .papers
+            .iter()
+            .filter(|v| page.after_id.is_none_or(|after_id| v.id > after_id))
+            .filter(|v| v.status == Paper::STORED && filter.matches(v))
+            .take(page.limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+    ) -> Result<Option<Paper>, InfraError> {
+        self.faults.check("update_paper")?;
+        let mut tables = self.tables.lock().unwrap();
+        let Some(paper) = tables.papers.iter_mut().find(|v| v.id == paper_id) else {
+            return Ok(None);
+        };
+        apply_update(paper, update);
+        Ok(Some(paper.clone()))
+    }
+
+    async fn delete_paper(&self, paper_id: i64) -> Result<bool, InfraError> {
+        self.faults.check("delete_paper")?;
+        let mut tables = self.tables.lock().unwrap();
+        let count = tables.papers.len();
+        tables.papers.retain(|v| v.id != paper_id);
+        if tables.papers.len() == count {
+            return Ok(false);
+        }
+
+        // Like the schema's `ON DELETE` clauses.
+        tables.artifacts.retain(|v| v.paper_id != paper_id);
+        tables
+            .supplementary_files
+            .retain(|v| v.paper_id != paper_id);
+        tables.bibliographies.remove(&paper_id);
+        for item in &mut tables.fetch_items {
+            if item.paper_id == Some(paper_id) {
+                item.paper_id = None;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn insert_artifacts(
+        &self,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Vec<PaperArtifact>, InfraError> {
+        self.faults.check("insert_artifacts")?;
+        self.tables.lock().unwrap().insert_artifacts(artifacts)
+    }
+
+    async fn get_artifacts(&self, paper_id: i64) -> Result<Vec<PaperArtifact>, InfraError> {
+        self.faults.check("get_artifacts")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .artifacts
+            .iter()
+            .filter(|v| v.paper_id == paper_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_supplementary_file(
+        &self,
+        file: NewSupplementaryFile,
+    ) -> Result<SupplementaryFile, InfraError> {
+        self.faults.check("insert_supplementary_file")?;
+        let mut tables = self.tables.lock().unwrap();
+        if tables
+            .supplementary_files
+            .iter()
+            .any(|v| v.paper_id == file.paper_id && v.filename == file.filename)
+        {
+            return Err(unique_violation(
+                "supplementary_files_paper_id_filename_key",
+            ));
+        }
+
+        let file = SupplementaryFile {
+            id: tables.next_id(),
+            paper_id: file.paper_id,
+            filename: file.filename,
+            s3_key: file.s3_key,
+            content_type: file.content_type,
+            sha256: file.sha256,
+            size_bytes: file.size_bytes,
+            created_at: now(),
+        };
+        tables.supplementary_files.push(file.clone());
+        Ok(file)
+    }
+
+    async fn get_supplementary_files(
+        &self,
+        paper_id: i64,
+    ) -> Result<Vec<SupplementaryFile>, InfraError> {
+        self.faults.check("get_supplementary_files")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .supplementary_files
+            .iter()
+            .filter(|v| v.paper_id == paper_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_bibliography(&self, bibliography: Bibliography) -> Result<(), InfraError> {
+        self.faults.check("insert_bibliography")?;
+        let mut tables = self.tables.lock().unwrap();
+        let paper_id = bibliography.metadata.paper_id;
+        if tables.bibliographies.contains_key(&paper_id) {
+            return Err(unique_violation("paper_metadata_pkey"));
+        }
+        tables.bibliographies.insert(paper_id, bibliography);
+        Ok(())
+    }
+
+    async fn get_bibliography(&self, paper_id: i64) -> Result<Option<Bibliography>, InfraError> {
+        self.faults.check("get_bibliography")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.bibliographies.get(&paper_id).cloned())
+    }
+
+    async fn insert_citations(&self, citations: Vec<Citation>) -> Result<usize, InfraError> {
+        self.faults.check("insert_citations")?;
+        let mut tables = self.tables.lock().unwrap();
+        let mut inserted = 0;
+        for citation in citations {
+            if !tables.citations.contains(&citation) {
+                tables.citations.push(citation);
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
+
+    async fn get_cited(&self, work: &str) -> Result<Vec<Citation>, InfraError> {
+        self.faults.check("get_cited")?;
+        let tables = self.tables.lock().unwrap();
+        let mut cited = tables
+            .citations
+            .iter()
+            .filter(|v| v.citing == work)
+            .cloned()
+            .collect::<Vec<_>>();
+        cited.sort_by(|a, b| a.cited.cmp(&b.cited));
+        Ok(cited)
+    }
+
+    async fn get_citing(&self, work: &str) -> Result<Vec<Citation>, InfraError> {
+        self.faults.check("get_citing")?;
+        let tables = self.tables.lock().unwrap();
+        let mut citing = tables
+            .citations
+            .iter()
+            .filter(|v| v.cited == work)
+            .cloned()
+            .collect::<Vec<_>>();
+        citing.sort_by(|a, b| a.citing.cmp(&b.citing));
+        Ok(citing)
+    }
+
+    async fn get_citations(&self) -> Result<Vec<Citation>, InfraError> {
+        self.faults.check("get_citations")?;
+        let mut citations = self.citations();
+        citations.sort_by(|a, b| (&a.citing, &a.cited).cmp(&(&b.citing, &b.cited)));
+        Ok(citations)
+    }
+
+    async fn get_pmcids_by_pmids(
+        &self,
+        pmids: &[String],
+    ) -> Result<HashMap<String, String>, InfraError> {
+        self.faults.check("get_pmcids_by_pmids")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .papers
+            .iter()
+            .filter(|v| v.status == Paper::STORED)
+            .filter_map(|paper| {
+                let pmid = tables
+                    .bibliographies
+                    .get(&paper.id)?
+                    .metadata
+                    .pmid
+                    .clone()?;
+                pmids.contains(&pmid).then(|| (pmid, paper.pmc_id.clone()))
+            })
+            .collect())
+    }
+
+    async fn rename_work(&self, from: &str, to: &str) -> Result<usize, InfraError> {
+        self.faults.check("rename_work")?;
+        let mut tables = self.tables.lock().unwrap();
+        let (touching, mut kept): (Vec<_>, Vec<_>) = std::mem::take(&mut tables.citations)
+            .into_iter()
+            .partition(|v| v.citing == from || v.cited == from);
+        let mut inserted = 0;
+        for citation in touching {
+            let citation = citation.renamed(from, to);
+            if !kept.contains(&citation) {
+                kept.push(citation);
+                inserted += 1;
+            }
+        }
+        tables.citations = kept;
+        Ok(inserted)
+    }
+
+    async fn insert_fetch_run(&self, run: NewFetchRun) -> Result<FetchRun, InfraError> {
+        self.faults.check("insert_fetch_run")?;
+        let mut tables = self.tables.lock().unwrap();
+        let run = FetchRun {
+            id: tables.next_id(),
+            query: run.query,
+            page_size: run.page_size,
+            cursor_mark: FetchRun::FIRST_CURSOR.to_string(),
+            status: FetchRun::RUNNING.to_string(),
+            created_at: now(),
+            updated_at: now(),
+        };
+        tables.fetch_runs.push(run.clone());
+        Ok(run)
+    }
+
+    async fn get_fetch_run(&self, run_id: i64) -> Result<Option<FetchRun>, InfraError> {
+        self.faults.check("get_fetch_run")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.fetch_runs.iter().find(|v| v.id == run_id).cloned())
+    }
+
+    async fn get_unfinished_fetch_runs(&self) -> Result<Vec<FetchRun>, InfraError> {
+        self.faults.check("get_unfinished_fetch_runs")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .fetch_runs
+            .iter()
+            .filter(|v| v.status != FetchRun::COMPLETED)
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_fetch_page(
+        &self,
+        run_id: i64,
+        items: Vec<NewFetchItem>,
+        next_cursor_mark: &str,
+    ) -> Result<usize, InfraError> {
+        self.faults.check("insert_fetch_page")?;
+        let mut tables = self.tables.lock().unwrap();
+        let Some(index) = tables.fetch_runs.iter().position(|v| v.id == run_id) else {
+            return Err(InfraError::Database(DieselError::NotFound));
+        };
+
+        let mut inserted = 0;
+        for item in items {
+            let recorded = tables
+                .fetch_items
+                .iter()
+                .any(|v| v.run_id == item.run_id && v.pmc_id == item.pmc_id);
+            if recorded {
+                continue;
+            }
+            let id = tables.next_id();
+            tables.fetch_items.push(FetchItem {
+                id,
+                run_id: item.run_id,
+                pmc_id: item.pmc_id,
+                search_record: item.search_record,
+                state: FetchItem::PENDING.to_string(),
+                attempts: 0,
+                last_error: None,
+                paper_id: None,
+                updated_at: now(),
+            });
+            inserted += 1;
+        }
+        let run = &mut tables.fetch_runs[index];
+        run.cursor_mark = next_cursor_mark.to_string();
+        run.updated_at = now();
+        Ok(inserted)
+    }
+
+    async fn get_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError> {
+        self.faults.check("get_fetch_items")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .fetch_items
+            .iter()
+            .filter(|v| v.run_id == run_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_unfinished_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError> {
+        self.faults.check("get_unfinished_fetch_items")?;
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .fetch_items
+            .iter()
+            .filter(|v| v.run_id == run_id && v.state != FetchItem::DONE)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_fetch_item(
+        &self,
+        item_id: i64,
+        outcome: FetchItemOutcome,
+    ) -> Result<FetchItem, InfraError> {
+        self.faults.check("update_fetch_item")?;
+        let mut tables = self.tables.lock().unwrap();
+        let item = tables
+            .fetch_items
+            .iter_mut()
+            .find(|v| v.id == item_id)
+            .ok_or(InfraError::Database(DieselError::NotFound))?;
+        item.state = outcome.state;
+        item.last_error = outcome.last_error;
+        item.paper_id = outcome.paper_id;
+        item.attempts += 1;
+        item.updated_at = now();
+        Ok(item.clone())
+    }
+
+    async fn update_fetch_run_status(&self, run_id: i64, status: &str) -> Result<(), InfraError> {
+        self.faults.check("update_fetch_run_status")?;
+        let mut tables = self.tables.lock().unwrap();
+        let run = tables
+            .fetch_runs
+            .iter_mut()
+            .find(|v| v.id == run_id)
+            .ok_or(InfraError::Database(DieselError::NotFound))?;
+        run.status = status.to_string();
+        run.updated_at = now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_paper(pmc_id: &str) -> NewPaper {
+        NewPaper {
+            pmc_id: pmc_id.to_string(),
+            s3_key: format!("papers/{pmc_id}/{pmc_id}.pdf"),
+            uid: format!("uid-{pmc_id}"),
+            query: "cortex".to_string(),
+            sha256: "abc".to_string(),
+            size_bytes: 3,
+            pdf_source_url: None,
+            reached_via: "query".to_string(),
+            reached_from: None,
+            snowball_depth: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unique_pmc_id() {
+        let db = MockDatabaseInfra::default();
+        let paper = db.insert_paper(new_paper("PMC1")).await.unwrap();
+        assert_eq!(
+            db.get_paper_by_pmcid("PMC1").await.unwrap().unwrap().id,
+            paper.id
+        );
+        assert!(matches!(
+            db.insert_paper(new_paper("PMC1")).await,
+            Err(InfraError::Database(DieselError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                _
+            )))
+        ));
+        assert_eq!(db.papers().len(), 1);
+
+        db.faults.inject("insert_paper", 1);
+        assert!(db.insert_paper(new_paper("PMC2")).await.is_err());
+        assert!(db.insert_paper(new_paper("PMC2")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_page() {
+        let db = MockDatabaseInfra::default();
+        let run = db
+            .insert_fetch_run(NewFetchRun {
+                query: "cortex".to_string(),
+                page_size: 2,
+            })
+            .await
+            .unwrap();
+        let item = |pmc_id: &str| NewFetchItem {
+            run_id: run.id,
+            pmc_id: pmc_id.to_string(),
+            search_record: None,
+        };
+        let inserted = db
+            .insert_fetch_page(run.id, vec![item("PMC1"), item("PMC2")], "next")
+            .await
+            .unwrap();
+        assert_eq!(inserted, 2);
+        let inserted = db
+            .insert_fetch_page(run.id, vec![item("PMC2"), item("PMC3")], "last")
+            .await
+            .unwrap();
+        assert_eq!(inserted, 1);
+
+        let items = db.get_fetch_items(run.id).await.unwrap();
+        assert_eq!(
+            items.iter().map(|v| v.pmc_id.as_str()).collect::<Vec<_>>(),
+            ["PMC1", "PMC2", "PMC3"]
+        );
+        assert_eq!(
+            db.get_fetch_run(run.id).await.unwrap().unwrap().cursor_mark,
+            "last"
+        );
+
+        db.update_fetch_item(items[0].id, FetchItemOutcome::failed("x".to_string()))
+            .await
+            .unwrap();
+        db.update_fetch_item(items[1].id, FetchItemOutcome::done(1))
+            .await
+            .unwrap();
+        let unfinished = db.get_unfinished_fetch_items(run.id).await.unwrap();
+        assert_eq!(unfinished.len(), 2);
+        assert_eq!(unfinished[0].attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_pending_paper_unlisted() {
+        let db = MockDatabaseInfra::default();
+        let stored = db.insert_paper(new_paper("PMC1")).await.unwrap();
+        db.insert_pending_paper(NewPendingPaper {
+            pmc_id: "PMC2".to_string(),
+            s3_key: "papers/PMC2/PMC2.pdf".to_string(),
+            uid: "uid-PMC2".to_string(),
+            query: "cortex".to_string(),
+            reached_via: "query".to_string(),
+            reached_from: None,
+            snowball_depth: 0,
+        })
+        .await
+        .unwrap();
+
+        let pmc_ids = ["PMC1".to_string(), "PMC2".to_string()];
+        assert_eq!(
+            db.get_existing_pmcids(&pmc_ids).await.unwrap(),
+            HashSet::from(["PMC1".to_string()])
+        );
+        let papers = db
+            .list_papers(PaperFilter::default(), PageRequest::first(10))
+            .await
+            .unwrap();
+        assert_eq!(papers.iter().map(|v| v.id).collect::<Vec<_>>(), [stored.id]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_paper() {
+        let db = MockDatabaseInfra::default();
+        let paper = db.insert_paper(new_paper("PMC1")).await.unwrap();
+        db.insert_artifacts(vec![NewPaperArtifact {
+            paper_id: paper.id,
+            kind: "pdf".to_string(),
+            s3_key: paper.s3_key.clone(),
+            sha256: "abc".to_string(),
+            size_bytes: 3,
+        }])
+        .await
+        .unwrap();
+        db.insert_paper(new_paper("PMC2")).await.unwrap();
+
+        assert!(db.delete_paper(paper.id).await.unwrap());
+        assert!(!db.delete_paper(paper.id).await.unwrap());
+        assert!(db.artifacts().is_empty());
+        let papers = db
+            .list_papers(PaperFilter::default(), PageRequest::first(10))
+            .await
+            .unwrap();
+        assert_eq!(
+            papers.iter().map(|v| v.pmc_id.as_str()).collect::<Vec<_>>(),
+            ["PMC2"]
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use cortexmap_infra::InfraError;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Failures injected into operations, keyed by the name of
+/// the trait method, e.g. `put_s3` or `insert_paper`.
+#[derive(Debug, Default)]
+pub struct Faults {
+    remaining: Mutex<HashMap<String, usize>>,
+}
+
+impl Faults {
+    /// Makes the next `times` calls of `operation` fail.
+    pub fn inject(&self, operation: &str, times: usize) {
+        *self
+            .remaining
+            .lock()
+            .unwrap()
+            .entry(operation.to_string())
+            .or_default() += times;
+    }
+
+    /// Fails if a fault of `operation` is pending, consuming it.
+    pub fn check(&self, operation: &str) -> Result<(), InfraError> {
+        let mut remaining = self.remaining.lock().unwrap();
+        match remaining.get_mut(operation) {
+            Some(times) if *times > 0 => {
+                *times -= 1;
+                Err(InfraError::Io(std::io::Error::other(format!(
+                    "injected fault in {operation}"
+                ))))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faults() {
+        let faults = Faults::default();
+        assert!(faults.check("put_s3").is_ok());
+
+        faults.inject("put_s3", 2);
+        assert!(faults.check("insert_paper").is_ok());
+        assert!(faults.check("put_s3").is_err());
+        assert!(faults.check("put_s3").is_err());
+        assert!(faults.check("put_s3").is_ok());
+    }
+}
@@ -0,0 +1,289 @@
+use crate::faults::Faults;
+use bytes::Bytes;
+use cortexmap_infra::{HttpInfra, InfraError, RequestCM, ResponseCM};
+use futures::StreamExt;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE, HeaderName, HeaderValue, LOCATION};
+use http::{HeaderMap, StatusCode};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A scripted answer to a request.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    chunks: Vec<Bytes>,
+    /// The body fails with this after the chunks
+    body_error: Option<String>,
+    /// Sending fails with this, e.g. for a refused connection
+    send_error: Option<String>,
+    /// Chunks of the body after the first one arrive this long apart
+    chunk_delay: Option<Duration>,
+}
+
+impl MockResponse {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            chunks: Vec::new(),
+            body_error: None,
+            send_error: None,
+            chunk_delay: None,
+        }
+    }
+
+    /// `200 OK` with the body and the content type.
+    pub fn ok(content_type: &'static str, body: impl Into<Bytes>) -> Self {
+        Self::new(StatusCode::OK)
+            .header(CONTENT_TYPE, HeaderValue::from_static(content_type))
+            .chunks([body.into()])
+    }
+
+    pub fn json(body: impl Into<Bytes>) -> Self {
+        Self::ok("application/json", body)
+    }
+
+    pub fn pdf(body: impl Into<Bytes>) -> Self {
+        Self::ok("application/pdf", body)
+    }
+
+    /// `302 Found` pointing to `location`, which clients follow
+    /// unless the request asks them not to.
+    pub fn redirect(location: &str) -> Self {
+        Self::new(StatusCode::FOUND).header(
+            LOCATION,
+            HeaderValue::from_str(location).expect("a valid location"),
+        )
+    }
+
+    /// A request that couldn't be sent at all.
+    pub fn send_error(reason: impl Into<String>) -> Self {
+        Self {
+            send_error: Some(reason.into()),
+            ..Self::new(StatusCode::OK)
+        }
+    }
+
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Replaces the body with `chunks`, streamed one by one.
+    pub fn chunks(mut self, chunks: impl IntoIterator<Item = Bytes>) -> Self {
+        self.chunks = chunks.into_iter().collect();
+        self
+    }
+
+    /// Announces a `Content-Length`, which doesn't have to match the body.
+    pub fn content_length(self, length: u64) -> Self {
+        self.header(CONTENT_LENGTH, HeaderValue::from(length))
+    }
+
+    /// Makes the body fail after its chunks, like a dropped connection.
+    pub fn body_error(mut self, reason: impl Into<String>) -> Self {
+        self.body_error = Some(reason.into());
+        self
+    }
+
+    /// Makes every chunk of the body but the first arrive `delay` after
+    /// the previous one, like a download that stalls once it got going.
+    pub fn chunk_delay(mut self, delay: Duration) -> Self {
+        self.chunk_delay = Some(delay);
+        self
+    }
+
+    fn into_response(self, url: &str) -> Result<ResponseCM, InfraError> {
+        if let Some(reason) = self.send_error {
+            return Err(InfraError::HttpError(reason.into()));
+        }
+        let mut items = self.chunks.into_iter().map(Ok).collect::<Vec<_>>();
+        if let Some(reason) = self.body_error {
+            items.push(Err(InfraError::HttpError(reason.into())));
+        }
+        let delay = self.chunk_delay.unwrap_or_default();
+        let body = Box::pin(futures::stream::iter(items.into_iter().enumerate()).then(
+            move |(i, v)| async move {
+                if i > 0 && !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                v
+            },
+        ));
+
+        Ok(ResponseCM::new(self.status, url, body).with_headers(self.headers))
+    }
+}
+
+/// Redirects followed at most, like reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+/// Where a route applies.
+#[derive(Debug)]
+enum Matcher {
+    Exact(String),
+    Prefix(String),
+}
+
+impl Matcher {
+    fn matches(&self, url: &str) -> bool {
+        match self {
+            Matcher::Exact(v) => url == v,
+            Matcher::Prefix(v) => url.starts_with(v.as_str()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Route {
+    matcher: Matcher,
+    /// Answered in order, the last one over and over
+    responses: Vec<MockResponse>,
+}
+
+/// Answers requests from scripted routes, `404 Not Found` if none matches.
+/// Routes are tried in the order they were added.
+#[derive(Debug, Default)]
+pub struct MockHttpInfra {
+    routes: Mutex<Vec<Route>>,
+    requests: Mutex<Vec<RequestCM>>,
+    pub faults: Faults,
+}
+
+impl MockHttpInfra {
+    /// Answers requests to exactly `url` (query included) with `responses`,
+    /// in order, repeating the last one.
+    pub fn route(&self, url: &str, responses: impl IntoIterator<Item = MockResponse>) {
+        self.add_route(Matcher::Exact(url.to_string()), responses);
+    }
+
+    /// Like [`route`](Self::route), for every URL starting with `prefix`.
+    pub fn route_prefix(&self, prefix: &str, responses: impl IntoIterator<Item = MockResponse>) {
+        self.add_route(Matcher::Prefix(prefix.to_string()), responses);
+    }
+
+    fn add_route(&self, matcher: Matcher, responses: impl IntoIterator<Item = MockResponse>) {
+        let responses = responses.into_iter().collect::<Vec<_>>();
+        assert!(!responses.is_empty(), "a route needs a response");
+        self.routes
+            .lock()
+            .unwrap()
+            .push(Route { matcher, responses });
+    }
+
+    /// The requests sent so far, in order.
+    pub fn requests(&self) -> Vec<RequestCM> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The URLs requested so far, in order.
+    pub fn requested_urls(&self) -> Vec<String> {
+        self.requests().into_iter().map(|v| v.url).collect()
+    }
+
+    fn next_response(&self, url: &str) -> MockResponse {
+        let mut routes = self.routes.lock().unwrap();
+        let Some(route) = routes.iter_mut().find(|v| v.matcher.matches(url)) else {
+            return MockResponse::new(StatusCode::NOT_FOUND);
+        };
+        if route.responses.len() > 1 {
+            route.responses.remove(0)
+        } else {
+            route.responses[0].clone()
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpInfra for MockHttpInfra {
+    async fn send(&self, request: RequestCM) -> Result<ResponseCM, InfraError> {
+        let mut url = request.url.clone();
+        let follow_redirects = request.follow_redirects;
+        self.requests.lock().unwrap().push(request);
+        self.faults.check("send")?;
+
+        let mut response = self.next_response(&url);
+        // Like a client, each hop is a request of its own.
+        for _ in 0..MAX_REDIRECTS {
+            let location = response.headers.get(LOCATION).and_then(|v| v.to_str().ok());
+            let redirected = follow_redirects && response.status.is_redirection();
+            let Some(location) = location.filter(|_| redirected) else {
+                break;
+            };
+            url = location.to_string();
+            self.requests.lock().unwrap().push(RequestCM::get(&url));
+            response = self.next_response(&url);
+        }
+        response.into_response(&url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_routes() {
+        let http = MockHttpInfra::default();
+        http.route(
+            "http://test/a",
+            [MockResponse::json("1"), MockResponse::json("2")],
+        );
+        http.route_prefix("http://test/", [MockResponse::new(StatusCode::IM_A_TEAPOT)]);
+
+        let body = |v: ResponseCM| async move { v.bytes().await.unwrap() };
+        assert_eq!(body(http.get("http://test/a").await.unwrap()).await, "1");
+        assert_eq!(body(http.get("http://test/a").await.unwrap()).await, "2");
+        assert_eq!(body(http.get("http://test/a").await.unwrap()).await, "2");
+        assert!(matches!(
+            http.get("http://test/b").await,
+            Err(InfraError::HttpStatus {
+                status: StatusCode::IM_A_TEAPOT,
+                ..
+            })
+        ));
+        assert!(matches!(
+            http.get("http://other/").await,
+            Err(InfraError::HttpStatus {
+                status: StatusCode::NOT_FOUND,
+                ..
+            })
+        ));
+        assert_eq!(http.requested_urls().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_redirects() {
+        let http = MockHttpInfra::default();
+        http.route("http://test/a", [MockResponse::redirect("http://test/b")]);
+        http.route("http://test/b", [MockResponse::json("b")]);
+
+        let response = http.get("http://test/a").await.unwrap();
+        assert_eq!(response.url, "http://test/b");
+        assert_eq!(response.bytes().await.unwrap(), "b");
+        assert_eq!(http.requested_urls(), ["http://test/a", "http://test/b"]);
+
+        let request = RequestCM::get("http://test/a").follow_redirects(false);
+        let response = http.send(request).await.unwrap();
+        assert_eq!(response.status, StatusCode::FOUND);
+        assert_eq!(response.url, "http://test/a");
+    }
+
+    #[tokio::test]
+    async fn test_failures() {
+        let http = MockHttpInfra::default();
+        http.route("http://test/refused", [MockResponse::send_error("refused")]);
+        http.route(
+            "http://test/dropped",
+            [MockResponse::pdf("%PDF-").body_error("connection reset")],
+        );
+
+        assert!(http.get("http://test/refused").await.is_err());
+        let response = http.get("http://test/dropped").await.unwrap();
+        assert!(response.bytes().await.is_err());
+
+        http.faults.inject("send", 1);
+        assert!(http.get("http://test/dropped").await.is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{MockDatabaseInfra, MockHttpInfra, MockS3Infra};
+use cortexmap_infra::{
+    Bibliography, Citation, ContentStream, ContentType, DatabaseInfra, FetchItem, FetchItemOutcome,
+    FetchRun, HttpInfra, InfraContext, InfraError, NewFetchItem, NewFetchRun, NewPaper,
+    NewPaperArtifact, NewPendingPaper, NewSupplementaryFile, ObjectHead, ObjectList, PageRequest,
+    Paper, PaperArtifact, PaperFilter, PaperUpdate, RequestCM, ResponseCM, S3Infra,
+    SupplementaryFile,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// All mock infras in one, the parts are public so tests
+/// can script and inspect them while the code under test
+/// holds the context.
+#[derive(Debug, Default)]
+pub struct MockInfra {
+    pub http: MockHttpInfra,
+    pub db: MockDatabaseInfra,
+    pub s3: MockS3Infra,
+}
+
+impl MockInfra {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn context(self: &Arc<Self>) -> InfraContext<Self> {
+        InfraContext {
+            infra: self.clone(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpInfra for MockInfra {
+    async fn send(&self, request: RequestCM) -> Result<ResponseCM, InfraError> {
+        self.http.send(request).await
+    }
+}
+
+#[async_trait::async_trait]
+impl DatabaseInfra for MockInfra {
+    async fn insert_paper(&self, new_paper: NewPaper) -> Result<Paper, InfraError> {
+        self.db.insert_paper(new_paper).await
+    }
+
+    async fn insert_pending_paper(&self, paper: NewPendingPaper) -> Result<Paper, InfraError> {
+        self.db.insert_pending_paper(paper).await
+    }
+
+    async fn finalize_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Paper, InfraError> {
+        self.db.finalize_paper(paper_id, update, artifacts).await
+    }
+
+    async fn abandon_pending_papers(
+        &self,
+        older_than: std::time::Duration,
+    ) -> Result<Vec<Paper>, InfraError> {
+        self.db.abandon_pending_papers(older_than).await
+    }
+
+    async fn get_paper_by_pmcid(&self, pmc_id: &str) -> Result<Option<Paper>, InfraError> {
+        self.db.get_paper_by_pmcid(pmc_id).await
+    }
+
+    async fn get_paper_by_uid(&self, uid: &str) -> Result<Option<Paper>, InfraError> {
+        self.db.get_paper_by_uid(uid).await
+    }
+
+    async fn get_existing_pmcids(&self, pmc_ids: &[String]) -> Result<HashSet<String>, InfraError> {
+        self.db.get_existing_pmcids(pmc_ids).await
+    }
+
+    async fn list_papers(
+        &self,
+        filter: PaperFilter,
+        page: PageRequest,
+    ) -> Result<Vec<Paper>, InfraError> {
+        self.db.list_papers(filter, page).await
+    }
+
+    async fn update_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+    ) -> Result<Option<Paper>, InfraError> {
+        self.db.update_paper(paper_id, update).await
+    }
+
+    async fn delete_paper(&self, paper_id: i64) -> Result<bool, InfraError> {
+        self.db.delete_paper(paper_id).await
+    }
+
+    async fn insert_artifacts(
+        &self,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Vec<PaperArtifact>, InfraError> {
+        self.db.insert_artifacts(artifacts).await
+    }
+
+    async fn get_artifacts(&self, paper_id: i64) -> Result<Vec<PaperArtifact>, InfraError> {
+        self.db.get_artifacts(paper_id).await
+    }
+
+    async fn insert_supplementary_file(
+        &self,
+        file: NewSupplementaryFile,
+    ) -> Result<SupplementaryFile, InfraError> {
+        self.db.insert_supplementary_file(file).await
+    }
+
+    async fn get_supplementary_files(
+        &self,
+        paper_id: i64,
+    ) -> Result<Vec<SupplementaryFile>, InfraError> {
+        self.db.get_supplementary_files(paper_id).await
+    }
+
+    async fn insert_bibliography(&self, bibliography: Bibliography) -> Result<(), InfraError> {
+        self.db.insert_bibliography(bibliography).await
+    }
+
+    async fn get_bibliography(&self, paper_id: i64) -> Result<Option<Bibliography>, InfraError> {
+        self.db.get_bibliography(paper_id).await
+    }
+
+    async fn insert_citations(&self, citations: Vec<Citation>) -> Result<usize, InfraError> {
+        self.db.insert_citations(citations).await
+    }
+
+    async fn get_cited(&self, work: &str) -> Result<Vec<Citation>, InfraError> {
+        self.db.get_cited(work).await
+    }
+
+    async fn get_citing(&self, work: &str) -> Result<Vec<Citation>, InfraError> {
+        self.db.get_citing(work).await
+    }
+
+    async fn get_citations(&self) -> Result<Vec<Citation>, InfraError> {
+        self.db.get_citations().await
+    }
+
+    async fn get_pmcids_by_pmids(
+        &self,
+        pmids: &[String],
+    ) -> Result<HashMap<String, String>, InfraError> {
+        self.db.get_pmcids_by_pmids(pmids).await
+    }
+
+    async fn rename_work(&self, from: &str, to: &str) -> Result<usize, InfraError> {
+        self.db.rename_work(from, to).await
+    }
+
+    async fn insert_fetch_run(&self, run: NewFetchRun) -> Result<FetchRun, InfraError> {
+        self.db.insert_fetch_run(run).await
+    }
+
+    async fn get_fetch_run(&self, run_id: i64) -> Result<Option<FetchRun>, InfraError> {
+        self.db.get_fetch_run(run_id).await
+    }
+
+    async fn get_unfinished_fetch_runs(&self) -> Result<Vec<FetchRun>, InfraError> {
+        self.db.get_unfinished_fetch_runs().await
+    }
+
+    async fn insert_fetch_page(
+        &self,
+        run_id: i64,
+        items: Vec<NewFetchItem>,
+        next_cursor_mark: &str,
+    ) -> Result<usize, InfraError> {
+        self.db
+            .insert_fetch_page(run_id, items, next_cursor_mark)
+            .await
+    }
+
+    async fn get_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError> {
+        self.db.get_fetch_items(run_id).await
+    }
+
+    async fn get_unfinished_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError> {
+        self.db.get_unfinished_fetch_items(run_id).await
+    }
+
+    async fn update_fetch_item(
+        &self,
+        item_id: i64,
+        outcome: FetchItemOutcome,
+    ) -> Result<FetchItem, InfraError> {
+        self.db.update_fetch_item(item_id, outcome).await
+    }
+
+    async fn update_fetch_run_status(&self, run_id: i64, status: &str) -> Result<(), InfraError> {
+        self.db.update_fetch_run_status(run_id, status).await
+    }
+}
+
+#[async_trait::async_trait]
+impl S3Infra for MockInfra {
+    async fn put_s3(
+        &self,
+        key: &str,
+        content_type: ContentType,
+        content: ContentStream,
+    ) -> Result<(), InfraError> {
+        self.s3.put_s3(key, content_type, content).await
+    }
+
+    async fn get_s3(&self, key: &str) -> Result<ContentStream, InfraError> {
+        self.s3.get_s3(key).await
+    }
+
+    async fn set_metadata_s3(
+        &self,
+        key: &str,
+        content_type: ContentType,
+        metadata: HashMap<String, String>,
+    ) -> Result<(), InfraError> {
+        self.s3.set_metadata_s3(key, content_type, metadata).await
+    }
+
+    async fn head_s3(&self, key: &str) -> Result<Option<ObjectHead>, InfraError> {
+        self.s3.head_s3(key).await
+    }
+
+    async fn list_s3(
+        &self,
+        prefix: &str,
+        continuation: Option<String>,
+    ) -> Result<ObjectList, InfraError> {
+        self.s3.list_s3(prefix, continuation).await
+    }
+
+    async fn delete_s3(&self, key: &str) -> Result<(), InfraError> {
+        self.s3.delete_s3(key).await
+    }
+
+    async fn copy_s3(&self, from: &str, to: &str) -> Result<(), InfraError> {
+        self.s3.copy_s3(from, to).await
+    }
+}
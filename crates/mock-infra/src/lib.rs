@@ -0,0 +1,19 @@
+//! In-memory implementations of the infra traits for tests.
+//! Everything is kept in memory behind mutexes, so tests can
+//! script what the outside world answers and inspect what was
+//! written, and inject faults into single operations.
+//! [`CassetteHttpInfra`] replays recorded Europe PMC responses.
+
+mod cassette;
+mod database;
+mod faults;
+mod http;
+mod infra;
+mod s3;
+
+pub use cassette::{CASSETTE_MODE_VAR, CassetteHttpInfra, CassetteMode};
+pub use database::MockDatabaseInfra;
+pub use faults::Faults;
+pub use http::{MockHttpInfra, MockResponse};
+pub use infra::MockInfra;
+pub use s3::{MockObject, MockS3Infra};
@@ -0,0 +1,207 @@
+use crate::faults::Faults;
+use bytes::{Bytes, BytesMut};
+use cortexmap_infra::{ContentStream, ContentType, InfraError, ObjectHead, ObjectList, S3Infra};
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// Keys listed per page, as many as S3 lists.
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// The error for a missing object, like the filesystem backend's.
+fn not_found(key: &str) -> InfraError {
+    InfraError::Io(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("no object {key}"),
+    ))
+}
+
+/// An object as it was stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockObject {
+    pub content: Bytes,
+    pub content_type: String,
+    pub metadata: HashMap<String, String>,
+}
+
+/// Keeps objects in a map. Like S3, an upload whose stream
+/// fails leaves nothing behind.
+#[derive(Debug, Default)]
+pub struct MockS3Infra {
+    objects: Mutex<BTreeMap<String, MockObject>>,
+    /// Faults of `put_s3` are raised once the content was read
+    pub faults: Faults,
+}
+
+impl MockS3Infra {
+    /// All stored objects by key.
+    pub fn objects(&self) -> BTreeMap<String, MockObject> {
+        self.objects.lock().unwrap().clone()
+    }
+
+    pub fn object(&self, key: &str) -> Option<MockObject> {
+        self.objects.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.objects.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Stores an object directly, e.g. one left by an earlier run.
+    pub fn insert(&self, key: &str, content_type: ContentType, content: impl Into<Bytes>) {
+        self.objects.lock().unwrap().insert(
+            key.to_string(),
+            MockObject {
+                content: content.into(),
+                content_type: content_type.to_string(),
+                metadata: HashMap::new(),
+            },
+        );
+    }
+}
+
+#[async_trait::async_trait]
+impl S3Infra for MockS3Infra {
+    async fn put_s3(
+        &self,
+        key: &str,
+        content_type: ContentType,
+        mut content: ContentStream,
+    ) -> Result<(), InfraError> {
+        let mut buf = BytesMut::new();
+        while let Some(chunk) = content.next().await {
+            buf.extend_from_slice(&chunk?);
+        }
+        self.faults.check("put_s3")?;
+
+        self.insert(key, content_type, buf.freeze());
+        Ok(())
+    }
+
+    async fn get_s3(&self, key: &str) -> Result<ContentStream, InfraError> {
+        self.faults.check("get_s3")?;
+        let object = self.object(key).ok_or_else(|| not_found(key))?;
+
+        Ok(Box::pin(futures::stream::iter([Ok(object.content)])))
+    }
+
+    async fn set_metadata_s3(
+        &self,
+        key: &str,
+        content_type: ContentType,
+        metadata: HashMap<String, String>,
+    ) -> Result<(), InfraError> {
+        self.faults.check("set_metadata_s3")?;
+        let mut objects = self.objects.lock().unwrap();
+        let object = objects.get_mut(key).ok_or_else(|| not_found(key))?;
+        object.content_type = content_type.to_string();
+        object.metadata = metadata;
+        Ok(())
+    }
+
+    async fn head_s3(&self, key: &str) -> Result<Option<ObjectHead>, InfraError> {
+        self.faults.check("head_s3")?;
+        Ok(self.object(key).map(|v| ObjectHead {
+            size: v.content.len() as u64,
+            content_type: v.content_type,
+            metadata: v.metadata,
+        }))
+    }
+
+    async fn list_s3(
+        &self,
+        prefix: &str,
+        continuation: Option<String>,
+    ) -> Result<ObjectList, InfraError> {
+        self.faults.check("list_s3")?;
+        // The continuation is the last key of the previous page.
+        let mut keys = self
+            .keys()
+            .into_iter()
+            .filter(|v| v.starts_with(prefix))
+            .filter(|v| continuation.as_ref().is_none_or(|after| v > after))
+            .collect::<Vec<_>>();
+        let continuation = if keys.len() > LIST_PAGE_SIZE {
+            keys.truncate(LIST_PAGE_SIZE);
+            keys.last().cloned()
+        } else {
+            None
+        };
+
+        Ok(ObjectList { keys, continuation })
+    }
+
+    async fn delete_s3(&self, key: &str) -> Result<(), InfraError> {
+        self.faults.check("delete_s3")?;
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn copy_s3(&self, from: &str, to: &str) -> Result<(), InfraError> {
+        self.faults.check("copy_s3")?;
+        let mut objects = self.objects.lock().unwrap();
+        let object = objects.get(from).cloned().ok_or_else(|| not_found(from))?;
+        objects.insert(to.to_string(), object);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(chunks: Vec<Result<&'static [u8], &'static str>>) -> ContentStream {
+        Box::pin(futures::stream::iter(chunks.into_iter().map(|v| {
+            v.map(Bytes::from_static)
+                .map_err(|e| InfraError::StreamAborted(e.to_string()))
+        })))
+    }
+
+    #[tokio::test]
+    async fn test_put() {
+        let s3 = MockS3Infra::default();
+        s3.put_s3(
+            "a.pdf",
+            ContentType::Pdf,
+            content(vec![Ok(b"%PDF-"), Ok(b"1.7")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(s3.object("a.pdf").unwrap().content, "%PDF-1.7");
+
+        let res = s3
+            .put_s3(
+                "b.pdf",
+                ContentType::Pdf,
+                content(vec![Ok(b"%PDF-"), Err("cut")]),
+            )
+            .await;
+        assert!(res.is_err());
+        assert_eq!(s3.keys(), vec!["a.pdf"]);
+
+        s3.faults.inject("put_s3", 1);
+        let res = s3
+            .put_s3("c.pdf", ContentType::Pdf, content(vec![Ok(b"%PDF-")]))
+            .await;
+        assert!(res.is_err());
+        assert_eq!(s3.keys(), vec!["a.pdf"]);
+    }
+
+    #[tokio::test]
+    async fn test_copy_and_delete() {
+        let s3 = MockS3Infra::default();
+        s3.insert("papers/a.pdf", ContentType::Pdf, "%PDF-");
+        s3.insert("other.txt", ContentType::Text, "text");
+
+        s3.copy_s3("papers/a.pdf", "papers/b.pdf").await.unwrap();
+        assert!(s3.copy_s3("papers/c.pdf", "papers/d.pdf").await.is_err());
+        let head = s3.head_s3("papers/b.pdf").await.unwrap().unwrap();
+        assert_eq!(head.size, 5);
+        assert_eq!(head.content_type, "application/pdf");
+
+        s3.delete_s3("papers/a.pdf").await.unwrap();
+        let list = s3.list_s3("papers/", None).await.unwrap();
+        assert_eq!(list.keys, ["papers/b.pdf"]);
+        assert_eq!(list.continuation, None);
+    }
+}
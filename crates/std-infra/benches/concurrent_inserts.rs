@@ -0,0 +1,129 @@
+//! Inserts papers from many tasks at once, through the r2d2 and the async
+//! implementation of `DatabaseInfra`, and prints how long that took.
+//!
+//! Needs a Postgres at `CORTEXMAP_BENCH_DATABASE_URL`, pending migrations
+//! are applied and the papers deleted again after each round:
+//!
+//! ```sh
+//! CORTEXMAP_BENCH_DATABASE_URL=postgres://localhost/cortexmap_bench \
+//!     cargo bench -p std-infra --bench concurrent_inserts
+//! ```
+
+use cortexmap_core::blueprint::{Migrations, PoolOptions, Postgresql};
+use cortexmap_infra::{DatabaseInfra, NewPaper};
+use futures::{StreamExt, TryStreamExt};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std_infra::{AsyncDatabaseInfra, StdDatabaseInfra};
+
+const INSERTS: usize = 2000;
+/// Tasks inserting at the same time, the first fits the pool
+const CONCURRENCY: [usize; 4] = [10, 50, 200, 1000];
+
+type Infra = Arc<dyn DatabaseInfra + Send + Sync>;
+
+#[tokio::main]
+async fn main() {
+    let Ok(url) = std::env::var("CORTEXMAP_BENCH_DATABASE_URL") else {
+        eprintln!("CORTEXMAP_BENCH_DATABASE_URL is not set, skipping");
+        return;
+    };
+    let postgresql = Postgresql {
+        url,
+        pool: PoolOptions {
+            max_size: 10,
+            connection_timeout: Duration::from_secs(5),
+        },
+    };
+    let async_infra = AsyncDatabaseInfra::new(&postgresql);
+    async_infra.check_migrations(Migrations::Apply).unwrap();
+    let infras: [(&str, Infra); 2] = [
+        (
+            "r2d2",
+            Arc::new(StdDatabaseInfra::new(&postgresql.url, &postgresql.pool).unwrap()),
+        ),
+        ("async", Arc::new(async_infra)),
+    ];
+
+    // Opens the connections, so the first round doesn't pay for it.
+    for (_, infra) in &infras {
+        run(infra, postgresql.pool.max_size as usize).await;
+    }
+
+    println!(
+        "{:<6} {:>11} {:>10} {:>10} {:>7}",
+        "infra", "concurrency", "elapsed", "inserts/s", "failed"
+    );
+    for concurrency in CONCURRENCY {
+        for (name, infra) in &infras {
+            let round = run(infra, concurrency).await;
+            println!(
+                "{name:<6} {concurrency:>11} {:>9.0?} {:>10.0} {:>7}",
+                round.elapsed,
+                round.inserted as f64 / round.elapsed.as_secs_f64(),
+                round.failed,
+            );
+        }
+    }
+}
+
+struct Round {
+    elapsed: Duration,
+    inserted: usize,
+    failed: usize,
+}
+
+/// Inserts `INSERTS` papers, each from a task of its own with at most
+/// `concurrency` running, then deletes them.
+async fn run(infra: &Infra, concurrency: usize) -> Round {
+    let prefix = uuid::Uuid::new_v4();
+    let start = Instant::now();
+    let results = futures::stream::iter(0..INSERTS)
+        .map(|i| {
+            let infra = infra.clone();
+            tokio::spawn(async move {
+                infra
+                    .insert_paper(new_paper(&format!("{prefix}-{i}")))
+                    .await
+            })
+        })
+        .buffer_unordered(concurrency)
+        .map(|v| v.unwrap())
+        .collect::<Vec<_>>()
+        .await;
+    let elapsed = start.elapsed();
+
+    let ids = results
+        .iter()
+        .filter_map(|v| v.as_ref().ok().map(|v| v.id))
+        .collect::<Vec<_>>();
+    if let Some(Err(e)) = results.iter().find(|v| v.is_err()) {
+        eprintln!("First failed insert: {e}");
+    }
+    futures::stream::iter(ids.iter().map(|&id| infra.delete_paper(id)))
+        .buffer_unordered(10)
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+
+    Round {
+        elapsed,
+        inserted: ids.len(),
+        failed: results.len() - ids.len(),
+    }
+}
+
+fn new_paper(id: &str) -> NewPaper {
+    NewPaper {
+        pmc_id: format!("PMC-bench-{id}"),
+        s3_key: format!("bench/{id}.pdf"),
+        uid: format!("bench-{id}"),
+        query: "bench".to_string(),
+        sha256: "0".repeat(64),
+        size_bytes: 0,
+        pdf_source_url: None,
+        reached_via: "query".to_string(),
+        reached_from: None,
+        snowball_depth: 0,
+    }
+}
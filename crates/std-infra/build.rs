@@ -0,0 +1,5 @@
+fn main() {
+    // The migrations are embedded, so new ones have to trigger a rebuild.
+    println!("cargo:rerun-if-changed=../../migrations");
+    println!("cargo:rerun-if-changed=../../migrations-sqlite");
+}
@@ -0,0 +1,515 @@
+use crate::database::pg_interval;
+use cortexmap_core::blueprint::{PoolOptions, Postgresql};
+use cortexmap_infra::{
+    Bibliography, Citation, DatabaseInfra, FetchItem, FetchItemOutcome, FetchRun, InfraError,
+    MeshHeading, NewFetchItem, NewFetchRun, NewPaper, NewPaperArtifact, NewPendingPaper,
+    NewSupplementaryFile, PageRequest, Paper, PaperArtifact, PaperAuthor, PaperFilter,
+    PaperKeyword, PaperMetadata, PaperUpdate, SupplementaryFile,
+};
+use cortexmap_infra::{
+    citations, fetch_items, fetch_runs, mesh_headings, paper_artifacts, paper_authors,
+    paper_keywords, paper_metadata, papers, supplementary_files,
+};
+use diesel::prelude::*;
+use diesel_async::pooled_connection::bb8::{Pool, PooledConnection};
+use diesel_async::pooled_connection::{AsyncDieselConnectionManager, ManagerConfig};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use std::collections::{HashMap, HashSet};
+
+pub type AsyncPgPool = Pool<AsyncPgConnection>;
+
+/// Postgres over a pool of async connections. Unlike [`crate::StdDatabaseInfra`]
+/// no query holds a blocking thread, so waiting for the database costs
+/// a task instead of a thread.
+pub struct AsyncDatabaseInfra {
+    url: String,
+    pool: AsyncPgPool,
+}
+
+impl AsyncDatabaseInfra {
+    /// Connections are opened on first use, so a wrong URL
+    /// shows in the first query rather than here.
+    pub fn new(postgresql: &Postgresql) -> Self {
+        Self::with_config(postgresql, ManagerConfig::default())
+    }
+
+    pub(crate) fn with_config(
+        postgresql: &Postgresql,
+        config: ManagerConfig<AsyncPgConnection>,
+    ) -> Self {
+        let PoolOptions {
+            max_size,
+            connection_timeout,
+        } = postgresql.pool;
+        let manager = AsyncDieselConnectionManager::new_with_config(&postgresql.url, config);
+        let pool = Pool::builder()
+            .max_size(max_size)
+            .connection_timeout(connection_timeout)
+            .build_unchecked(manager);
+
+        Self {
+            url: postgresql.url.clone(),
+            pool,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn pool(&self) -> &AsyncPgPool {
+        &self.pool
+    }
+
+    async fn conn(&self) -> Result<PooledConnection<'_, AsyncPgConnection>, InfraError> {
+        self.pool
+            .get()
+            .await
+            .map_err(|e| InfraError::AsyncPoolError(Box::new(e)))
+    }
+}
+
+#[async_trait::async_trait]
+impl DatabaseInfra for AsyncDatabaseInfra {
+    async fn insert_paper(&self, new_paper: NewPaper) -> Result<Paper, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(diesel::insert_into(papers::table)
+            .values(&new_paper)
+            .get_result(conn)
+            .await?)
+    }
+
+    async fn insert_pending_paper(&self, paper: NewPendingPaper) -> Result<Paper, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(diesel::insert_into(papers::table)
+            .values((&paper, papers::status.eq(Paper::PENDING)))
+            .get_result(conn)
+            .await?)
+    }
+
+    async fn finalize_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Paper, InfraError> {
+        let conn = &mut self.conn().await?;
+        conn.transaction(async |conn| {
+            let paper = diesel::update(
+                papers::table
+                    .find(paper_id)
+                    .filter(papers::status.eq(Paper::PENDING)),
+            )
+            .set((&update, papers::status.eq(Paper::STORED)))
+            .returning(Paper::as_returning())
+            .get_result(conn)
+            .await?;
+            diesel::insert_into(paper_artifacts::table)
+                .values(&artifacts)
+                .execute(conn)
+                .await?;
+            Ok::<_, InfraError>(paper)
+        })
+        .await
+    }
+
+    async fn abandon_pending_papers(
+        &self,
+        older_than: std::time::Duration,
+    ) -> Result<Vec<Paper>, InfraError> {
+        let conn = &mut self.conn().await?;
+        diesel::update(
+            papers::table
+                .filter(papers::status.eq(Paper::PENDING))
+                .filter(papers::created_at.le(diesel::dsl::now - pg_interval(older_than))),
+        )
+        .set(papers::status.eq(Paper::ABANDONED))
+        .execute(conn)
+        .await?;
+        Ok(papers::table
+            .filter(papers::status.eq(Paper::ABANDONED))
+            .order(papers::id)
+            .select(Paper::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    async fn get_paper_by_pmcid(&self, pmc_id: &str) -> Result<Option<Paper>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(papers::table
+            .filter(papers::pmc_id.eq(pmc_id))
+            .select(Paper::as_select())
+            .first(conn)
+            .await
+            .optional()?)
+    }
+
+    async fn get_paper_by_uid(&self, uid: &str) -> Result<Option<Paper>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(papers::table
+            .filter(papers::uid.eq(uid))
+            .select(Paper::as_select())
+            .first(conn)
+            .await
+            .optional()?)
+    }
+
+    async fn get_existing_pmcids(&self, pmc_ids: &[String]) -> Result<HashSet<String>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(papers::table
+            .filter(papers::pmc_id.eq_any(pmc_ids))
+            .filter(papers::status.eq(Paper::STORED))
+            .select(papers::pmc_id)
+            .load::<String>(conn)
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    async fn list_papers(
+        &self,
+        filter: PaperFilter,
+        page: PageRequest,
+    ) -> Result<Vec<Paper>, InfraError> {
+        let mut query = papers::table
+            .filter(papers::status.eq(Paper::STORED))
+            .select(Paper::as_select())
+            .into_boxed();
+        if let Some(after_id) = page.after_id {
+            query = query.filter(papers::id.gt(after_id));
+        }
+        if let Some(search_query) = filter.query {
+            query = query.filter(papers::query.eq(search_query));
+        }
+        if let Some(created_from) = filter.created_from {
+            query = query.filter(papers::created_at.ge(created_from));
+        }
+        if let Some(created_until) = filter.created_until {
+            query = query.filter(papers::created_at.lt(created_until));
+        }
+
+        let conn = &mut self.conn().await?;
+        Ok(query.order(papers::id).limit(page.limit).load(conn).await?)
+    }
+
+    async fn update_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+    ) -> Result<Option<Paper>, InfraError> {
+        let conn = &mut self.conn().await?;
+        // Diesel refuses an update without any column to set.
+        if update.is_empty() {
+            return Ok(papers::table
+                .find(paper_id)
+                .select(Paper::as_select())
+                .first(conn)
+                .await
+                .optional()?);
+        }
+        Ok(diesel::update(papers::table.find(paper_id))
+            .set(&update)
+            .returning(Paper::as_returning())
+            .get_result(conn)
+            .await
+            .optional()?)
+    }
+
+    async fn delete_paper(&self, paper_id: i64) -> Result<bool, InfraError> {
+        let conn = &mut self.conn().await?;
+        // The paper's other records are removed by `ON DELETE CASCADE`.
+        let deleted = diesel::delete(papers::table.find(paper_id))
+            .execute(conn)
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    async fn insert_artifacts(
+        &self,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Vec<PaperArtifact>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(diesel::insert_into(paper_artifacts::table)
+            .values(&artifacts)
+            .get_results(conn)
+            .await?)
+    }
+
+    async fn get_artifacts(&self, paper_id: i64) -> Result<Vec<PaperArtifact>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(paper_artifacts::table
+            .filter(paper_artifacts::paper_id.eq(paper_id))
+            .order(paper_artifacts::id)
+            .select(PaperArtifact::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    async fn insert_supplementary_file(
+        &self,
+        file: NewSupplementaryFile,
+    ) -> Result<SupplementaryFile, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(diesel::insert_into(supplementary_files::table)
+            .values(&file)
+            .get_result(conn)
+            .await?)
+    }
+
+    async fn get_supplementary_files(
+        &self,
+        paper_id: i64,
+    ) -> Result<Vec<SupplementaryFile>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(supplementary_files::table
+            .filter(supplementary_files::paper_id.eq(paper_id))
+            .order(supplementary_files::id)
+            .select(SupplementaryFile::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    async fn insert_bibliography(&self, bibliography: Bibliography) -> Result<(), InfraError> {
+        let conn = &mut self.conn().await?;
+        conn.transaction(async |conn| {
+            diesel::insert_into(paper_metadata::table)
+                .values(&bibliography.metadata)
+                .execute(conn)
+                .await?;
+            diesel::insert_into(paper_authors::table)
+                .values(&bibliography.authors)
+                .execute(conn)
+                .await?;
+            // Europe PMC occasionally lists the same heading or keyword twice.
+            diesel::insert_into(mesh_headings::table)
+                .values(&bibliography.mesh_headings)
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .await?;
+            diesel::insert_into(paper_keywords::table)
+                .values(&bibliography.keywords)
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .await?;
+            Ok::<_, InfraError>(())
+        })
+        .await
+    }
+
+    async fn get_bibliography(&self, paper_id: i64) -> Result<Option<Bibliography>, InfraError> {
+        let conn = &mut self.conn().await?;
+        let Some(metadata) = paper_metadata::table
+            .find(paper_id)
+            .select(PaperMetadata::as_select())
+            .first(conn)
+            .await
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(Bibliography {
+            metadata,
+            authors: paper_authors::table
+                .filter(paper_authors::paper_id.eq(paper_id))
+                .order(paper_authors::position)
+                .select(PaperAuthor::as_select())
+                .load(conn)
+                .await?,
+            mesh_headings: mesh_headings::table
+                .filter(mesh_headings::paper_id.eq(paper_id))
+                .select(MeshHeading::as_select())
+                .load(conn)
+                .await?,
+            keywords: paper_keywords::table
+                .filter(paper_keywords::paper_id.eq(paper_id))
+                .select(PaperKeyword::as_select())
+                .load(conn)
+                .await?,
+        }))
+    }
+
+    async fn insert_citations(&self, citations: Vec<Citation>) -> Result<usize, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(diesel::insert_into(citations::table)
+            .values(&citations)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?)
+    }
+
+    async fn get_cited(&self, work: &str) -> Result<Vec<Citation>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(citations::table
+            .filter(citations::citing.eq(work))
+            .order(citations::cited)
+            .select(Citation::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    async fn get_citing(&self, work: &str) -> Result<Vec<Citation>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(citations::table
+            .filter(citations::cited.eq(work))
+            .order(citations::citing)
+            .select(Citation::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    async fn get_citations(&self) -> Result<Vec<Citation>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(citations::table
+            .order((citations::citing, citations::cited))
+            .select(Citation::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    async fn get_pmcids_by_pmids(
+        &self,
+        pmids: &[String],
+    ) -> Result<HashMap<String, String>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(paper_metadata::table
+            .inner_join(papers::table)
+            .filter(paper_metadata::pmid.eq_any(pmids))
+            .filter(papers::status.eq(Paper::STORED))
+            .select((paper_metadata::pmid.assume_not_null(), papers::pmc_id))
+            .load::<(String, String)>(conn)
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    async fn rename_work(&self, from: &str, to: &str) -> Result<usize, InfraError> {
+        let conn = &mut self.conn().await?;
+        conn.transaction(async |conn| {
+            let touching = citations::citing.eq(from).or(citations::cited.eq(from));
+            let renamed = citations::table
+                .filter(touching)
+                .select(Citation::as_select())
+                .load(conn)
+                .await?
+                .into_iter()
+                .map(|v| v.renamed(from, to))
+                .collect::<Vec<_>>();
+            diesel::delete(citations::table.filter(touching))
+                .execute(conn)
+                .await?;
+            Ok::<_, InfraError>(
+                diesel::insert_into(citations::table)
+                    .values(&renamed)
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .await?,
+            )
+        })
+        .await
+    }
+
+    async fn insert_fetch_run(&self, run: NewFetchRun) -> Result<FetchRun, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(diesel::insert_into(fetch_runs::table)
+            .values(&run)
+            .get_result(conn)
+            .await?)
+    }
+
+    async fn get_fetch_run(&self, run_id: i64) -> Result<Option<FetchRun>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(fetch_runs::table
+            .find(run_id)
+            .select(FetchRun::as_select())
+            .first(conn)
+            .await
+            .optional()?)
+    }
+
+    async fn get_unfinished_fetch_runs(&self) -> Result<Vec<FetchRun>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(fetch_runs::table
+            .filter(fetch_runs::status.ne(FetchRun::COMPLETED))
+            .order(fetch_runs::id)
+            .select(FetchRun::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    async fn insert_fetch_page(
+        &self,
+        run_id: i64,
+        items: Vec<NewFetchItem>,
+        next_cursor_mark: &str,
+    ) -> Result<usize, InfraError> {
+        let conn = &mut self.conn().await?;
+        conn.transaction(async |conn| {
+            // Pages can overlap when results are added while paging.
+            let inserted = diesel::insert_into(fetch_items::table)
+                .values(&items)
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .await?;
+            diesel::update(fetch_runs::table.find(run_id))
+                .set((
+                    fetch_runs::cursor_mark.eq(next_cursor_mark),
+                    fetch_runs::updated_at.eq(diesel::dsl::now),
+                ))
+                .execute(conn)
+                .await?;
+            Ok::<_, InfraError>(inserted)
+        })
+        .await
+    }
+
+    async fn get_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(fetch_items::table
+            .filter(fetch_items::run_id.eq(run_id))
+            .order(fetch_items::id)
+            .select(FetchItem::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    async fn get_unfinished_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(fetch_items::table
+            .filter(fetch_items::run_id.eq(run_id))
+            .filter(fetch_items::state.ne(FetchItem::DONE))
+            .order(fetch_items::id)
+            .select(FetchItem::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    async fn update_fetch_item(
+        &self,
+        item_id: i64,
+        outcome: FetchItemOutcome,
+    ) -> Result<FetchItem, InfraError> {
+        let conn = &mut self.conn().await?;
+        Ok(diesel::update(fetch_items::table.find(item_id))
+            .set((
+                &outcome,
+                fetch_items::attempts.eq(fetch_items::attempts + 1),
+                fetch_items::updated_at.eq(diesel::dsl::now),
+            ))
+            .returning(FetchItem::as_returning())
+            .get_result(conn)
+            .await?)
+    }
+
+    async fn update_fetch_run_status(&self, run_id: i64, status: &str) -> Result<(), InfraError> {
+        let conn = &mut self.conn().await?;
+        diesel::update(fetch_runs::table.find(run_id))
+            .set((
+                fetch_runs::status.eq(status),
+                fetch_runs::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+}
@@ -1,23 +1,156 @@
-use cortexmap_infra::papers;
-use cortexmap_infra::{DatabaseInfra, InfraError, NewPaper, Paper};
-use diesel::PgConnection;
+use cortexmap_core::blueprint::{Database, PoolOptions};
+use cortexmap_infra::{
+    Bibliography, Citation, DatabaseInfra, FetchItem, FetchItemOutcome, FetchRun, InfraError,
+    MeshHeading, NewFetchItem, NewFetchRun, NewPaper, NewPaperArtifact, NewPendingPaper,
+    NewSupplementaryFile, PageRequest, Paper, PaperArtifact, PaperAuthor, PaperFilter,
+    PaperKeyword, PaperMetadata, PaperUpdate, SupplementaryFile,
+};
+use cortexmap_infra::{
+    citations, fetch_items, fetch_runs, mesh_headings, paper_artifacts, paper_authors,
+    paper_keywords, paper_metadata, papers, supplementary_files,
+};
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
-use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
+use diesel::{PgConnection, SqliteConnection};
+use std::collections::{HashMap, HashSet};
 
-pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+pub type PgPool = Pool<ConnectionManager<PgConnection>>;
+pub type SqlitePool = Pool<ConnectionManager<SqliteConnection>>;
+
+/// Connections of the configured backend.
+#[derive(Clone)]
+pub enum DbPool {
+    Postgresql(PgPool),
+    Sqlite(SqlitePool),
+}
+
+/// Runs the block with a pooled connection on the blocking thread
+/// pool. The block is compiled once per backend, so the same
+/// diesel query serves Postgres and SQLite. Within the block,
+/// `insert_ignoring_conflicts!(table, rows)` inserts the rows
+/// that don't collide with a unique key, and `ago!(duration)` is
+/// the database's current time less the duration.
+macro_rules! with_conn {
+    ($infra:expr, |$conn:ident| $body:block) => {
+        match $infra.pool.clone() {
+            DbPool::Postgresql(pool) => {
+                #[allow(unused_macros)]
+                macro_rules! insert_ignoring_conflicts {
+                    ($table:expr, $rows:expr) => {
+                        diesel::insert_into($table)
+                            .values($rows)
+                            .on_conflict_do_nothing()
+                    };
+                }
+                #[allow(unused_macros)]
+                macro_rules! ago {
+                    ($duration:expr) => {
+                        diesel::dsl::now - pg_interval($duration)
+                    };
+                }
+                tokio::task::spawn_blocking(move || {
+                    let mut pooled = pool.get()?;
+                    let $conn: &mut PgConnection = &mut pooled;
+                    $body
+                })
+                .await?
+            }
+            DbPool::Sqlite(pool) => {
+                // Diesel can't batch `ON CONFLICT` on SQLite. `OR IGNORE` also
+                // skips rows breaking `NOT NULL` or `CHECK`, which the callers
+                // never insert.
+                #[allow(unused_macros)]
+                macro_rules! insert_ignoring_conflicts {
+                    ($table:expr, $rows:expr) => {
+                        diesel::insert_or_ignore_into($table).values($rows)
+                    };
+                }
+                // With the milliseconds `CURRENT_TIMESTAMP` lacks, so
+                // a zero duration still takes in the current second.
+                #[allow(unused_macros)]
+                macro_rules! ago {
+                    ($duration:expr) => {
+                        diesel::dsl::sql::<diesel::sql_types::Timestamp>(
+                            "strftime('%Y-%m-%d %H:%M:%f', 'now', ",
+                        )
+                        .bind::<diesel::sql_types::Text, _>(format!(
+                            "-{:.3} seconds",
+                            $duration.as_secs_f64()
+                        ))
+                        .sql(")")
+                    };
+                }
+                tokio::task::spawn_blocking(move || {
+                    let mut pooled = pool.get()?;
+                    let $conn: &mut SqliteConnection = &mut pooled;
+                    $body
+                })
+                .await?
+            }
+        }
+    };
+}
+
+/// Postgres can't go back further than 4713 BC, so longer
+/// durations are cut to a span no pending paper gets near.
+pub(crate) fn pg_interval(duration: std::time::Duration) -> diesel::pg::data_types::PgInterval {
+    const MAX: std::time::Duration = std::time::Duration::from_secs(1000 * 366 * 24 * 3600);
+    let micros = duration.min(MAX).as_micros();
+    diesel::pg::data_types::PgInterval::from_microseconds(micros as i64)
+}
+
+/// SQLite leaves foreign keys (and so `ON DELETE CASCADE`) unenforced
+/// unless asked to, and fails at once on a locked database instead
+/// of waiting for the lock.
+#[derive(Debug)]
+struct SqlitePragmas;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for SqlitePragmas {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute(
+            "PRAGMA foreign_keys = ON; PRAGMA busy_timeout = 5000; PRAGMA journal_mode = WAL;",
+        )
+        .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
 
 pub struct StdDatabaseInfra {
     pool: DbPool,
 }
 
 impl StdDatabaseInfra {
-    pub fn new(database_url: &str) -> Result<Self, InfraError> {
+    pub fn new(database_url: &str, options: &PoolOptions) -> Result<Self, InfraError> {
         let manager = ConnectionManager::<PgConnection>::new(database_url);
         let pool = Pool::builder()
-            .max_size(10)
+            .max_size(options.max_size)
+            .connection_timeout(options.connection_timeout)
             .build(manager)?;
 
-        Ok(Self { pool })
+        Ok(Self {
+            pool: DbPool::Postgresql(pool),
+        })
+    }
+
+    /// Opens the SQLite database at `path`, creating the file if needed.
+    pub fn sqlite(path: &str) -> Result<Self, InfraError> {
+        let manager = ConnectionManager::<SqliteConnection>::new(path);
+        let pool = Pool::builder()
+            .max_size(4)
+            .connection_customizer(Box::new(SqlitePragmas))
+            .build(manager)?;
+
+        Ok(Self {
+            pool: DbPool::Sqlite(pool),
+        })
+    }
+
+    /// Connects to the database the blueprint's connections name.
+    pub fn from_database(database: &Database) -> Result<Self, InfraError> {
+        match database {
+            Database::Postgresql(db) => Self::new(&db.url, &db.pool),
+            Database::Sqlite(db) => Self::sqlite(&db.path),
+        }
     }
 
     pub fn pool(&self) -> &DbPool {
@@ -28,17 +161,1053 @@ impl StdDatabaseInfra {
 #[async_trait::async_trait]
 impl DatabaseInfra for StdDatabaseInfra {
     async fn insert_paper(&self, new_paper: NewPaper) -> Result<Paper, InfraError> {
-        let pool = self.pool.clone();
-
-        Ok(tokio::task::spawn_blocking(move || {
-            let mut conn = pool.get()?;
-
+        with_conn!(self, |conn| {
             Ok::<_, InfraError>(
                 diesel::insert_into(papers::table)
                     .values(&new_paper)
-                    .get_result(&mut conn)?,
+                    .get_result(conn)?,
+            )
+        })
+    }
+
+    async fn insert_pending_paper(&self, paper: NewPendingPaper) -> Result<Paper, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                diesel::insert_into(papers::table)
+                    .values((&paper, papers::status.eq(Paper::PENDING)))
+                    .get_result(conn)?,
+            )
+        })
+    }
+
+    async fn finalize_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Paper, InfraError> {
+        with_conn!(self, |conn| {
+            conn.transaction::<_, InfraError, _>(|conn| {
+                let paper = diesel::update(
+                    papers::table
+                        .find(paper_id)
+                        .filter(papers::status.eq(Paper::PENDING)),
+                )
+                .set((&update, papers::status.eq(Paper::STORED)))
+                .returning(Paper::as_returning())
+                .get_result(conn)?;
+                diesel::insert_into(paper_artifacts::table)
+                    .values(&artifacts)
+                    .execute(conn)?;
+                Ok(paper)
+            })
+        })
+    }
+
+    async fn abandon_pending_papers(
+        &self,
+        older_than: std::time::Duration,
+    ) -> Result<Vec<Paper>, InfraError> {
+        with_conn!(self, |conn| {
+            diesel::update(
+                papers::table
+                    .filter(papers::status.eq(Paper::PENDING))
+                    .filter(papers::created_at.le(ago!(older_than))),
+            )
+            .set(papers::status.eq(Paper::ABANDONED))
+            .execute(conn)?;
+            Ok::<_, InfraError>(
+                papers::table
+                    .filter(papers::status.eq(Paper::ABANDONED))
+                    .order(papers::id)
+                    .select(Paper::as_select())
+                    .load(conn)?,
+            )
+        })
+    }
+
+    async fn get_paper_by_pmcid(&self, pmc_id: &str) -> Result<Option<Paper>, InfraError> {
+        let pmc_id = pmc_id.to_owned();
+
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                papers::table
+                    .filter(papers::pmc_id.eq(pmc_id))
+                    .select(Paper::as_select())
+                    .first(conn)
+                    .optional()?,
+            )
+        })
+    }
+
+    async fn get_paper_by_uid(&self, uid: &str) -> Result<Option<Paper>, InfraError> {
+        let uid = uid.to_owned();
+
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                papers::table
+                    .filter(papers::uid.eq(uid))
+                    .select(Paper::as_select())
+                    .first(conn)
+                    .optional()?,
+            )
+        })
+    }
+
+    async fn get_existing_pmcids(&self, pmc_ids: &[String]) -> Result<HashSet<String>, InfraError> {
+        let pmc_ids = pmc_ids.to_vec();
+
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                papers::table
+                    .filter(papers::pmc_id.eq_any(pmc_ids))
+                    .filter(papers::status.eq(Paper::STORED))
+                    .select(papers::pmc_id)
+                    .load::<String>(conn)?
+                    .into_iter()
+                    .collect(),
+            )
+        })
+    }
+
+    async fn list_papers(
+        &self,
+        filter: PaperFilter,
+        page: PageRequest,
+    ) -> Result<Vec<Paper>, InfraError> {
+        with_conn!(self, |conn| {
+            let mut query = papers::table
+                .filter(papers::status.eq(Paper::STORED))
+                .select(Paper::as_select())
+                .into_boxed();
+            if let Some(after_id) = page.after_id {
+                query = query.filter(papers::id.gt(after_id));
+            }
+            if let Some(search_query) = filter.query {
+                query = query.filter(papers::query.eq(search_query));
+            }
+            if let Some(created_from) = filter.created_from {
+                query = query.filter(papers::created_at.ge(created_from));
+            }
+            if let Some(created_until) = filter.created_until {
+                query = query.filter(papers::created_at.lt(created_until));
+            }
+
+            Ok::<_, InfraError>(query.order(papers::id).limit(page.limit).load(conn)?)
+        })
+    }
+
+    async fn update_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+    ) -> Result<Option<Paper>, InfraError> {
+        with_conn!(self, |conn| {
+            // Diesel refuses an update without any column to set.
+            if update.is_empty() {
+                return Ok::<_, InfraError>(
+                    papers::table
+                        .find(paper_id)
+                        .select(Paper::as_select())
+                        .first(conn)
+                        .optional()?,
+                );
+            }
+            Ok(diesel::update(papers::table.find(paper_id))
+                .set(&update)
+                .returning(Paper::as_returning())
+                .get_result(conn)
+                .optional()?)
+        })
+    }
+
+    async fn delete_paper(&self, paper_id: i64) -> Result<bool, InfraError> {
+        with_conn!(self, |conn| {
+            // The paper's other records are removed by `ON DELETE CASCADE`.
+            let deleted = diesel::delete(papers::table.find(paper_id)).execute(conn)?;
+            Ok::<_, InfraError>(deleted > 0)
+        })
+    }
+
+    async fn insert_artifacts(
+        &self,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Vec<PaperArtifact>, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                diesel::insert_into(paper_artifacts::table)
+                    .values(&artifacts)
+                    .get_results(conn)?,
+            )
+        })
+    }
+
+    async fn get_artifacts(&self, paper_id: i64) -> Result<Vec<PaperArtifact>, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                paper_artifacts::table
+                    .filter(paper_artifacts::paper_id.eq(paper_id))
+                    .order(paper_artifacts::id)
+                    .select(PaperArtifact::as_select())
+                    .load(conn)?,
+            )
+        })
+    }
+
+    async fn insert_supplementary_file(
+        &self,
+        file: NewSupplementaryFile,
+    ) -> Result<SupplementaryFile, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                diesel::insert_into(supplementary_files::table)
+                    .values(&file)
+                    .get_result(conn)?,
+            )
+        })
+    }
+
+    async fn get_supplementary_files(
+        &self,
+        paper_id: i64,
+    ) -> Result<Vec<SupplementaryFile>, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                supplementary_files::table
+                    .filter(supplementary_files::paper_id.eq(paper_id))
+                    .order(supplementary_files::id)
+                    .select(SupplementaryFile::as_select())
+                    .load(conn)?,
+            )
+        })
+    }
+
+    async fn insert_bibliography(&self, bibliography: Bibliography) -> Result<(), InfraError> {
+        with_conn!(self, |conn| {
+            conn.transaction::<_, InfraError, _>(|conn| {
+                diesel::insert_into(paper_metadata::table)
+                    .values(&bibliography.metadata)
+                    .execute(conn)?;
+                diesel::insert_into(paper_authors::table)
+                    .values(&bibliography.authors)
+                    .execute(conn)?;
+                // Europe PMC occasionally lists the same heading or keyword twice.
+                insert_ignoring_conflicts!(mesh_headings::table, &bibliography.mesh_headings)
+                    .execute(conn)?;
+                insert_ignoring_conflicts!(paper_keywords::table, &bibliography.keywords)
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    async fn get_bibliography(&self, paper_id: i64) -> Result<Option<Bibliography>, InfraError> {
+        with_conn!(self, |conn| {
+            let Some(metadata) = paper_metadata::table
+                .find(paper_id)
+                .select(PaperMetadata::as_select())
+                .first(conn)
+                .optional()?
+            else {
+                return Ok::<_, InfraError>(None);
+            };
+
+            Ok(Some(Bibliography {
+                metadata,
+                authors: paper_authors::table
+                    .filter(paper_authors::paper_id.eq(paper_id))
+                    .order(paper_authors::position)
+                    .select(PaperAuthor::as_select())
+                    .load(conn)?,
+                mesh_headings: mesh_headings::table
+                    .filter(mesh_headings::paper_id.eq(paper_id))
+                    .select(MeshHeading::as_select())
+                    .load(conn)?,
+                keywords: paper_keywords::table
+                    .filter(paper_keywords::paper_id.eq(paper_id))
+                    .select(PaperKeyword::as_select())
+                    .load(conn)?,
+            }))
+        })
+    }
+
+    async fn insert_citations(&self, citations: Vec<Citation>) -> Result<usize, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                insert_ignoring_conflicts!(citations::table, &citations).execute(conn)?,
+            )
+        })
+    }
+
+    async fn get_cited(&self, work: &str) -> Result<Vec<Citation>, InfraError> {
+        let work = work.to_owned();
+
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                citations::table
+                    .filter(citations::citing.eq(work))
+                    .order(citations::cited)
+                    .select(Citation::as_select())
+                    .load(conn)?,
+            )
+        })
+    }
+
+    async fn get_citing(&self, work: &str) -> Result<Vec<Citation>, InfraError> {
+        let work = work.to_owned();
+
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                citations::table
+                    .filter(citations::cited.eq(work))
+                    .order(citations::citing)
+                    .select(Citation::as_select())
+                    .load(conn)?,
+            )
+        })
+    }
+
+    async fn get_citations(&self) -> Result<Vec<Citation>, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                citations::table
+                    .order((citations::citing, citations::cited))
+                    .select(Citation::as_select())
+                    .load(conn)?,
+            )
+        })
+    }
+
+    async fn get_pmcids_by_pmids(
+        &self,
+        pmids: &[String],
+    ) -> Result<HashMap<String, String>, InfraError> {
+        let pmids = pmids.to_vec();
+
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                paper_metadata::table
+                    .inner_join(papers::table)
+                    .filter(paper_metadata::pmid.eq_any(pmids))
+                    .filter(papers::status.eq(Paper::STORED))
+                    .select((paper_metadata::pmid.assume_not_null(), papers::pmc_id))
+                    .load::<(String, String)>(conn)?
+                    .into_iter()
+                    .collect(),
+            )
+        })
+    }
+
+    async fn rename_work(&self, from: &str, to: &str) -> Result<usize, InfraError> {
+        let (from, to) = (from.to_owned(), to.to_owned());
+
+        with_conn!(self, |conn| {
+            conn.transaction::<_, InfraError, _>(|conn| {
+                let touching = citations::citing.eq(&from).or(citations::cited.eq(&from));
+                let renamed = citations::table
+                    .filter(touching)
+                    .select(Citation::as_select())
+                    .load(conn)?
+                    .into_iter()
+                    .map(|v| v.renamed(&from, &to))
+                    .collect::<Vec<_>>();
+                diesel::delete(citations::table.filter(touching)).execute(conn)?;
+                Ok(insert_ignoring_conflicts!(citations::table, &renamed).execute(conn)?)
+            })
+        })
+    }
+
+    async fn insert_fetch_run(&self, run: NewFetchRun) -> Result<FetchRun, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                diesel::insert_into(fetch_runs::table)
+                    .values(&run)
+                    .get_result(conn)?,
+            )
+        })
+    }
+
+    async fn get_fetch_run(&self, run_id: i64) -> Result<Option<FetchRun>, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                fetch_runs::table
+                    .find(run_id)
+                    .select(FetchRun::as_select())
+                    .first(conn)
+                    .optional()?,
+            )
+        })
+    }
+
+    async fn get_unfinished_fetch_runs(&self) -> Result<Vec<FetchRun>, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                fetch_runs::table
+                    .filter(fetch_runs::status.ne(FetchRun::COMPLETED))
+                    .order(fetch_runs::id)
+                    .select(FetchRun::as_select())
+                    .load(conn)?,
+            )
+        })
+    }
+
+    async fn insert_fetch_page(
+        &self,
+        run_id: i64,
+        items: Vec<NewFetchItem>,
+        next_cursor_mark: &str,
+    ) -> Result<usize, InfraError> {
+        let next_cursor_mark = next_cursor_mark.to_owned();
+
+        with_conn!(self, |conn| {
+            conn.transaction::<_, InfraError, _>(|conn| {
+                // Pages can overlap when results are added while paging.
+                let inserted =
+                    insert_ignoring_conflicts!(fetch_items::table, &items).execute(conn)?;
+                diesel::update(fetch_runs::table.find(run_id))
+                    .set((
+                        fetch_runs::cursor_mark.eq(next_cursor_mark),
+                        fetch_runs::updated_at.eq(diesel::dsl::now),
+                    ))
+                    .execute(conn)?;
+                Ok(inserted)
+            })
+        })
+    }
+
+    async fn get_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                fetch_items::table
+                    .filter(fetch_items::run_id.eq(run_id))
+                    .order(fetch_items::id)
+                    .select(FetchItem::as_select())
+                    .load(conn)?,
+            )
+        })
+    }
+
+    async fn get_unfinished_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                fetch_items::table
+                    .filter(fetch_items::run_id.eq(run_id))
+                    .filter(fetch_items::state.ne(FetchItem::DONE))
+                    .order(fetch_items::id)
+                    .select(FetchItem::as_select())
+                    .load(conn)?,
             )
         })
-        .await??)
+    }
+
+    async fn update_fetch_item(
+        &self,
+        item_id: i64,
+        outcome: FetchItemOutcome,
+    ) -> Result<FetchItem, InfraError> {
+        with_conn!(self, |conn| {
+            Ok::<_, InfraError>(
+                diesel::update(fetch_items::table.find(item_id))
+                    .set((
+                        &outcome,
+                        fetch_items::attempts.eq(fetch_items::attempts + 1),
+                        fetch_items::updated_at.eq(diesel::dsl::now),
+                    ))
+                    .returning(FetchItem::as_returning())
+                    .get_result(conn)?,
+            )
+        })
+    }
+
+    async fn update_fetch_run_status(&self, run_id: i64, status: &str) -> Result<(), InfraError> {
+        let status = status.to_owned();
+
+        with_conn!(self, |conn| {
+            diesel::update(fetch_runs::table.find(run_id))
+                .set((
+                    fetch_runs::status.eq(status),
+                    fetch_runs::updated_at.eq(diesel::dsl::now),
+                ))
+                .execute(conn)?;
+            Ok::<_, InfraError>(())
+        })
+    }
+}
+
+/// These run against a fresh SQLite file, and with the `_postgres`
+/// suffix against a Postgres with the migrations applied, named by
+/// `CORTEXMAP_TEST_DATABASE_URL`, through this and the async
+/// implementation. There everything happens in a transaction that is
+/// never committed, so the database is left as it was.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AsyncDatabaseInfra;
+    use cortexmap_core::blueprint::Postgresql;
+    use diesel::ConnectionError;
+    use diesel::r2d2::TestCustomizer;
+    use diesel_async::pooled_connection::ManagerConfig;
+    use diesel_async::{AsyncConnection, AsyncPgConnection};
+    use futures::FutureExt;
+
+    fn postgres_infra() -> StdDatabaseInfra {
+        let url = std::env::var("CORTEXMAP_TEST_DATABASE_URL")
+            .expect("CORTEXMAP_TEST_DATABASE_URL is not set");
+        // A single connection, so every query sees the test transaction.
+        let pool = Pool::builder()
+            .max_size(1)
+            .connection_customizer(Box::new(TestCustomizer))
+            .build(ConnectionManager::<PgConnection>::new(url))
+            .unwrap();
+        StdDatabaseInfra {
+            pool: DbPool::Postgresql(pool),
+        }
+    }
+
+    fn async_postgres_infra() -> AsyncDatabaseInfra {
+        let url = std::env::var("CORTEXMAP_TEST_DATABASE_URL")
+            .expect("CORTEXMAP_TEST_DATABASE_URL is not set");
+        let postgresql = Postgresql {
+            url,
+            pool: PoolOptions {
+                max_size: 1,
+                ..Default::default()
+            },
+        };
+        // Like above, the one connection stays in a test transaction.
+        let mut config = ManagerConfig::default();
+        config.custom_setup = Box::new(|url| {
+            let url = url.to_owned();
+            async move {
+                let mut conn = AsyncPgConnection::establish(&url).await?;
+                conn.begin_test_transaction()
+                    .await
+                    .map_err(ConnectionError::CouldntSetupConfiguration)?;
+                Ok(conn)
+            }
+            .boxed()
+        });
+        AsyncDatabaseInfra::with_config(&postgresql, config)
+    }
+
+    /// A fresh database file in `dir` with the SQLite migrations applied.
+    fn sqlite_infra(dir: &std::path::Path) -> StdDatabaseInfra {
+        let path = dir.join("cortexmap.sqlite");
+        let db = StdDatabaseInfra::sqlite(path.to_str().unwrap()).unwrap();
+        db.migrate().unwrap();
+        db
+    }
+
+    fn new_paper(pmc_id: &str, query: &str) -> NewPaper {
+        NewPaper {
+            pmc_id: pmc_id.to_string(),
+            s3_key: format!("papers/{pmc_id}/{pmc_id}.pdf"),
+            uid: format!("uid-{pmc_id}"),
+            query: query.to_string(),
+            sha256: "abc".to_string(),
+            size_bytes: 3,
+            pdf_source_url: None,
+            reached_via: "query".to_string(),
+            reached_from: None,
+            snowball_depth: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_paper_lookups() {
+        let dir = tempfile::tempdir().unwrap();
+        check_paper_lookups(sqlite_infra(dir.path())).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_paper_lookups_postgres() {
+        check_paper_lookups(postgres_infra()).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_paper_lookups_async_postgres() {
+        check_paper_lookups(async_postgres_infra()).await;
+    }
+
+    async fn check_paper_lookups(db: impl DatabaseInfra) {
+        let first = db.insert_paper(new_paper("PMC1", "cortex")).await.unwrap();
+        db.insert_paper(new_paper("PMC2", "hippocampus"))
+            .await
+            .unwrap();
+        let third = db.insert_paper(new_paper("PMC3", "cortex")).await.unwrap();
+
+        let found = db.get_paper_by_uid("uid-PMC3").await.unwrap().unwrap();
+        assert_eq!(found.id, third.id);
+        assert!(db.get_paper_by_uid("uid-PMC4").await.unwrap().is_none());
+
+        let existing = db
+            .get_existing_pmcids(&["PMC1".to_string(), "PMC4".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(existing, HashSet::from(["PMC1".to_string()]));
+
+        let filter = PaperFilter {
+            query: Some("cortex".to_string()),
+            ..Default::default()
+        };
+        let page = PageRequest::first(1);
+        let papers = db.list_papers(filter.clone(), page).await.unwrap();
+        assert_eq!(papers.iter().map(|v| v.id).collect::<Vec<_>>(), [first.id]);
+        let page = page.next(&papers).unwrap();
+        let papers = db.list_papers(filter.clone(), page).await.unwrap();
+        assert_eq!(papers.iter().map(|v| v.id).collect::<Vec<_>>(), [third.id]);
+        let page = page.next(&papers).unwrap();
+        let papers = db.list_papers(filter, page).await.unwrap();
+        assert!(papers.is_empty());
+        assert_eq!(page.next(&papers), None);
+
+        let stored_at = first.created_at;
+        let between = PaperFilter {
+            created_from: Some(stored_at),
+            created_until: Some(third.created_at + std::time::Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let papers = db
+            .list_papers(between, PageRequest::first(10))
+            .await
+            .unwrap();
+        assert_eq!(papers.len(), 3);
+        let before = PaperFilter {
+            created_until: Some(stored_at),
+            ..Default::default()
+        };
+        let papers = db
+            .list_papers(before, PageRequest::first(10))
+            .await
+            .unwrap();
+        assert!(papers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_update_and_delete_paper() {
+        let dir = tempfile::tempdir().unwrap();
+        check_update_and_delete_paper(sqlite_infra(dir.path())).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_update_and_delete_paper_postgres() {
+        check_update_and_delete_paper(postgres_infra()).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_update_and_delete_paper_async_postgres() {
+        check_update_and_delete_paper(async_postgres_infra()).await;
+    }
+
+    async fn check_update_and_delete_paper(db: impl DatabaseInfra) {
+        let paper = db.insert_paper(new_paper("PMC1", "cortex")).await.unwrap();
+        db.insert_artifacts(vec![NewPaperArtifact {
+            paper_id: paper.id,
+            kind: "pdf".to_string(),
+            s3_key: paper.s3_key.clone(),
+            sha256: "abc".to_string(),
+            size_bytes: 3,
+        }])
+        .await
+        .unwrap();
+        db.insert_supplementary_file(NewSupplementaryFile {
+            paper_id: paper.id,
+            filename: "data/table1.csv".to_string(),
+            s3_key: "papers/PMC1/supplementary/data/table1.csv".to_string(),
+            content_type: "text/csv".to_string(),
+            sha256: "abc".to_string(),
+            size_bytes: 3,
+        })
+        .await
+        .unwrap();
+        let files = db.get_supplementary_files(paper.id).await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].filename, "data/table1.csv");
+
+        let update = PaperUpdate {
+            sha256: Some("def".to_string()),
+            size_bytes: Some(4),
+            pdf_source_url: Some(Some("https://example.com/PMC1.pdf".to_string())),
+            ..Default::default()
+        };
+        let updated = db.update_paper(paper.id, update).await.unwrap().unwrap();
+        assert_eq!(updated.sha256.as_deref(), Some("def"));
+        assert_eq!(updated.size_bytes, Some(4));
+        assert_eq!(updated.s3_key, paper.s3_key);
+        assert!(updated.pdf_source_url.is_some());
+        let unchanged = db
+            .update_paper(paper.id, PaperUpdate::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(unchanged.sha256.as_deref(), Some("def"));
+        assert!(unchanged.pdf_source_url.is_some());
+        let cleared = PaperUpdate {
+            pdf_source_url: Some(None),
+            ..Default::default()
+        };
+        let cleared = db.update_paper(paper.id, cleared).await.unwrap().unwrap();
+        assert_eq!(cleared.pdf_source_url, None);
+        assert!(
+            db.update_paper(paper.id + 1, PaperUpdate::default())
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        assert!(db.delete_paper(paper.id).await.unwrap());
+        assert!(db.get_paper_by_pmcid("PMC1").await.unwrap().is_none());
+        assert!(db.get_artifacts(paper.id).await.unwrap().is_empty());
+        assert!(
+            db.get_supplementary_files(paper.id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(!db.delete_paper(paper.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_pending_papers() {
+        let dir = tempfile::tempdir().unwrap();
+        check_pending_papers(sqlite_infra(dir.path())).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_pending_papers_postgres() {
+        check_pending_papers(postgres_infra()).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_pending_papers_async_postgres() {
+        check_pending_papers(async_postgres_infra()).await;
+    }
+
+    // West of UTC, `created_at` is behind a UTC clock, so a sweep by
+    // the UTC clock would take fresh papers for abandoned ones.
+    const WEST_OF_UTC: &str = "SET TIME ZONE 'Pacific/Honolulu'";
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_pending_papers_postgres_time_zone() {
+        let db = postgres_infra();
+        let DbPool::Postgresql(pool) = &db.pool else {
+            unreachable!()
+        };
+        diesel::sql_query(WEST_OF_UTC)
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        check_pending_papers(db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_pending_papers_async_postgres_time_zone() {
+        let db = async_postgres_infra();
+        diesel_async::RunQueryDsl::execute(
+            diesel::sql_query(WEST_OF_UTC),
+            &mut db.pool().get().await.unwrap(),
+        )
+        .await
+        .unwrap();
+        check_pending_papers(db).await;
+    }
+
+    async fn check_pending_papers(db: impl DatabaseInfra) {
+        let pending_paper = |pmc_id: &str| NewPendingPaper {
+            pmc_id: pmc_id.to_string(),
+            s3_key: format!("papers/{pmc_id}/{pmc_id}.pdf"),
+            uid: format!("uid-{pmc_id}"),
+            query: "cortex".to_string(),
+            reached_via: "query".to_string(),
+            reached_from: None,
+            snowball_depth: 0,
+        };
+        let artifact = |paper_id: i64| NewPaperArtifact {
+            paper_id,
+            kind: "pdf".to_string(),
+            s3_key: "papers/PMC1/PMC1.pdf".to_string(),
+            sha256: "abc".to_string(),
+            size_bytes: 3,
+        };
+
+        let stored = db.insert_paper(new_paper("PMC1", "cortex")).await.unwrap();
+        assert_eq!(stored.status, Paper::STORED);
+        assert!(
+            db.insert_pending_paper(pending_paper("PMC1"))
+                .await
+                .is_err()
+        );
+
+        let pending = db
+            .insert_pending_paper(pending_paper("PMC2"))
+            .await
+            .unwrap();
+        assert_eq!(pending.status, Paper::PENDING);
+        assert_eq!(pending.sha256, None);
+        // Only stored papers count as existing and are listed.
+        let pmc_ids = ["PMC2".to_string()];
+        assert!(db.get_existing_pmcids(&pmc_ids).await.unwrap().is_empty());
+        let listed_ids = async || {
+            db.list_papers(PaperFilter::default(), PageRequest::first(10))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|v| v.id)
+                .collect::<Vec<_>>()
+        };
+        assert!(!listed_ids().await.contains(&pending.id));
+        let update = PaperUpdate {
+            sha256: Some("abc".to_string()),
+            size_bytes: Some(3),
+            ..Default::default()
+        };
+        // A colliding artifact leaves the paper pending.
+        let colliding = vec![artifact(pending.id), artifact(pending.id)];
+        assert!(
+            db.finalize_paper(pending.id, update.clone(), colliding)
+                .await
+                .is_err()
+        );
+        let paper = db.get_paper_by_pmcid("PMC2").await.unwrap().unwrap();
+        assert_eq!(paper.status, Paper::PENDING);
+        assert!(db.get_artifacts(pending.id).await.unwrap().is_empty());
+
+        let finalized = db
+            .finalize_paper(pending.id, update.clone(), vec![artifact(pending.id)])
+            .await
+            .unwrap();
+        assert_eq!(finalized.status, Paper::STORED);
+        assert_eq!(finalized.sha256.as_deref(), Some("abc"));
+        assert_eq!(
+            db.get_existing_pmcids(&pmc_ids).await.unwrap(),
+            HashSet::from(pmc_ids.clone())
+        );
+        assert!(listed_ids().await.contains(&pending.id));
+        assert_eq!(db.get_artifacts(pending.id).await.unwrap().len(), 1);
+        assert!(matches!(
+            db.finalize_paper(pending.id, update.clone(), Vec::new())
+                .await,
+            Err(InfraError::Database(diesel::result::Error::NotFound))
+        ));
+
+        let left = db
+            .insert_pending_paper(pending_paper("PMC3"))
+            .await
+            .unwrap();
+        // Only papers pending for at least the given time are abandoned.
+        let hour = std::time::Duration::from_secs(3600);
+        assert!(db.abandon_pending_papers(hour).await.unwrap().is_empty());
+        let any_age = std::time::Duration::ZERO;
+        let abandoned = db.abandon_pending_papers(any_age).await.unwrap();
+        assert_eq!(
+            abandoned.iter().map(|v| v.id).collect::<Vec<_>>(),
+            [left.id]
+        );
+        assert_eq!(abandoned[0].status, Paper::ABANDONED);
+        assert!(
+            db.finalize_paper(left.id, update, vec![artifact(left.id)])
+                .await
+                .is_err()
+        );
+        // Still listed until removed, e.g. by a later sweep.
+        assert_eq!(db.abandon_pending_papers(any_age).await.unwrap().len(), 1);
+        assert!(db.delete_paper(left.id).await.unwrap());
+        assert!(db.abandon_pending_papers(any_age).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ignored_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        check_ignored_conflicts(sqlite_infra(dir.path())).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_ignored_conflicts_postgres() {
+        check_ignored_conflicts(postgres_infra()).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_ignored_conflicts_async_postgres() {
+        check_ignored_conflicts(async_postgres_infra()).await;
+    }
+
+    async fn check_ignored_conflicts(db: impl DatabaseInfra) {
+        let citation = |citing: &str, cited: &str| Citation {
+            citing: citing.to_string(),
+            cited: cited.to_string(),
+        };
+        let inserted = db
+            .insert_citations(vec![citation("A", "B"), citation("A", "C")])
+            .await
+            .unwrap();
+        assert_eq!(inserted, 2);
+        let inserted = db
+            .insert_citations(vec![citation("A", "B"), citation("B", "C")])
+            .await
+            .unwrap();
+        assert_eq!(inserted, 1);
+        assert_eq!(db.get_citations().await.unwrap().len(), 3);
+
+        let paper = db.insert_paper(new_paper("PMC1", "cortex")).await.unwrap();
+        let keyword = PaperKeyword {
+            paper_id: paper.id,
+            keyword: "neurons".to_string(),
+        };
+        db.insert_bibliography(Bibliography {
+            metadata: PaperMetadata {
+                paper_id: paper.id,
+                title: Some("Cortex".to_string()),
+                abstract_text: None,
+                journal_title: None,
+                journal_issn: None,
+                publication_date: "2024-02-29".parse().ok(),
+                doi: None,
+                pmid: None,
+                license: None,
+                is_open_access: Some(true),
+            },
+            authors: Vec::new(),
+            mesh_headings: Vec::new(),
+            keywords: vec![keyword.clone(), keyword],
+        })
+        .await
+        .unwrap();
+        let bibliography = db.get_bibliography(paper.id).await.unwrap().unwrap();
+        assert_eq!(bibliography.keywords.len(), 1);
+        let published = bibliography.metadata.publication_date.unwrap();
+        assert_eq!(published.to_string(), "2024-02-29");
+    }
+
+    #[tokio::test]
+    async fn test_citation_graph() {
+        let dir = tempfile::tempdir().unwrap();
+        check_citation_graph(sqlite_infra(dir.path())).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_citation_graph_postgres() {
+        check_citation_graph(postgres_infra()).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_citation_graph_async_postgres() {
+        check_citation_graph(async_postgres_infra()).await;
+    }
+
+    async fn check_citation_graph(db: impl DatabaseInfra) {
+        let citation = |citing: &str, cited: &str| Citation {
+            citing: citing.to_string(),
+            cited: cited.to_string(),
+        };
+        let paper = db.insert_paper(new_paper("PMC1", "cortex")).await.unwrap();
+        db.insert_bibliography(Bibliography {
+            metadata: PaperMetadata {
+                paper_id: paper.id,
+                title: None,
+                abstract_text: None,
+                journal_title: None,
+                journal_issn: None,
+                publication_date: None,
+                doi: None,
+                pmid: Some("11".to_string()),
+                license: None,
+                is_open_access: None,
+            },
+            authors: Vec::new(),
+            mesh_headings: Vec::new(),
+            keywords: Vec::new(),
+        })
+        .await
+        .unwrap();
+        let pmcids = db
+            .get_pmcids_by_pmids(&["11".to_string(), "12".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            pmcids,
+            HashMap::from([("11".to_string(), "PMC1".to_string())])
+        );
+
+        db.insert_citations(vec![
+            citation("MED:10", "MED:11"),
+            citation("MED:10", "PMC:PMC1"),
+            citation("MED:11", "MED:12"),
+        ])
+        .await
+        .unwrap();
+        // The edge from MED:10 is there already.
+        let gained = db.rename_work("MED:11", "PMC:PMC1").await.unwrap();
+        assert_eq!(gained, 1);
+        assert_eq!(
+            db.get_citations().await.unwrap(),
+            [
+                citation("MED:10", "PMC:PMC1"),
+                citation("PMC:PMC1", "MED:12")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        check_fetch_pages(sqlite_infra(dir.path())).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_fetch_pages_postgres() {
+        check_fetch_pages(postgres_infra()).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    async fn test_fetch_pages_async_postgres() {
+        check_fetch_pages(async_postgres_infra()).await;
+    }
+
+    async fn check_fetch_pages(db: impl DatabaseInfra) {
+        let run = db
+            .insert_fetch_run(NewFetchRun {
+                query: "cortex".to_string(),
+                page_size: 2,
+            })
+            .await
+            .unwrap();
+        let item = |pmc_id: &str| NewFetchItem {
+            run_id: run.id,
+            pmc_id: pmc_id.to_string(),
+            search_record: None,
+        };
+        let inserted = db
+            .insert_fetch_page(run.id, vec![item("PMC1"), item("PMC2")], "next")
+            .await
+            .unwrap();
+        assert_eq!(inserted, 2);
+        // Overlapping pages only count their new items.
+        let inserted = db
+            .insert_fetch_page(run.id, vec![item("PMC2"), item("PMC3")], "last")
+            .await
+            .unwrap();
+        assert_eq!(inserted, 1);
+        assert_eq!(db.get_fetch_items(run.id).await.unwrap().len(), 3);
+        let run = db.get_fetch_run(run.id).await.unwrap().unwrap();
+        assert_eq!(run.cursor_mark, "last");
+    }
+
+    #[test]
+    #[ignore = "needs a migrated Postgres at CORTEXMAP_TEST_DATABASE_URL"]
+    fn test_migration_status_postgres() {
+        assert!(postgres_infra().migration_status().unwrap().is_current());
     }
 }
@@ -0,0 +1,468 @@
+use bytes::BytesMut;
+use cortexmap_infra::{ContentStream, ContentType, InfraError, ObjectHead, ObjectList, S3Infra};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Suffix of the sidecar file next to each object,
+/// holding its content type and metadata.
+const SIDECAR_SUFFIX: &str = ".meta.json";
+
+/// Size of the chunks objects are read back in.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Keys listed per page, as many as S3 lists.
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// Stores objects as files under a root directory, keys being
+/// their relative paths, e.g. for development and CI where
+/// no S3 endpoint is around.
+pub struct FsS3Infra {
+    root: PathBuf,
+    list_page_size: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Sidecar {
+    content_type: String,
+    metadata: HashMap<String, String>,
+}
+
+impl FsS3Infra {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            list_page_size: LIST_PAGE_SIZE,
+        }
+    }
+
+    /// Maps the key to a path under the root, rejecting keys
+    /// that would end up outside of it.
+    fn path(&self, key: &str) -> Result<PathBuf, InfraError> {
+        let relative = Path::new(key);
+        let is_plain = relative
+            .components()
+            .all(|v| matches!(v, Component::Normal(_)));
+        if key.is_empty() || !is_plain || key.ends_with(SIDECAR_SUFFIX) {
+            return Err(InfraError::InvalidKey(key.to_string()));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    fn sidecar_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(SIDECAR_SUFFIX);
+        PathBuf::from(name)
+    }
+
+    /// A path next to `path`, so that renaming it over `path` is atomic.
+    fn temp_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+        PathBuf::from(name)
+    }
+
+    async fn write_sidecar(path: &Path, sidecar: &Sidecar) -> Result<(), InfraError> {
+        let content = serde_json::to_vec_pretty(sidecar).map_err(std::io::Error::other)?;
+        let sidecar_path = Self::sidecar_path(path);
+        let temp = Self::temp_path(&sidecar_path);
+        tokio::fs::write(&temp, content).await?;
+        tokio::fs::rename(&temp, &sidecar_path).await?;
+        Ok(())
+    }
+
+    /// The sidecar of the object at `path`, objects
+    /// without one are taken to be plain bytes.
+    async fn read_sidecar(path: &Path) -> Result<Sidecar, InfraError> {
+        match tokio::fs::read(Self::sidecar_path(path)).await {
+            Ok(content) => Ok(serde_json::from_slice(&content).map_err(std::io::Error::other)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Sidecar {
+                content_type: "application/octet-stream".to_string(),
+                metadata: HashMap::new(),
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether the file name is that of an upload in progress.
+    fn is_temp(name: &str) -> bool {
+        name.strip_suffix(".tmp")
+            .and_then(|v| v.rsplit_once('.'))
+            .is_some_and(|(_, id)| uuid::Uuid::parse_str(id).is_ok())
+    }
+
+    /// Keys of all stored objects, sorted.
+    async fn keys(&self) -> Result<Vec<String>, InfraError> {
+        let mut keys = Vec::new();
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(v) => v,
+                // Nothing was stored yet.
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let Some(name) = path.file_name().and_then(|v| v.to_str()) else {
+                    continue;
+                };
+                if name.ends_with(SIDECAR_SUFFIX) || Self::is_temp(name) {
+                    continue;
+                }
+                let Ok(relative) = path.strip_prefix(&self.root) else {
+                    continue;
+                };
+                let segments = relative
+                    .components()
+                    .map(|v| v.as_os_str().to_str())
+                    .collect::<Option<Vec<_>>>();
+                if let Some(segments) = segments {
+                    keys.push(segments.join("/"));
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn write_content(path: &Path, mut content: ContentStream) -> Result<(), InfraError> {
+        let mut temp = TempFile {
+            path: Self::temp_path(path),
+            persisted: false,
+        };
+        let mut file = tokio::fs::File::create(&temp.path).await?;
+        while let Some(chunk) = content.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.sync_all().await?;
+        drop(file);
+
+        tokio::fs::rename(&temp.path, path).await?;
+        temp.persisted = true;
+        Ok(())
+    }
+}
+
+/// Removes the temp file of an upload that didn't make it, be it
+/// because of an error or because the upload was dropped midway,
+/// so nothing is left behind for it, like with S3.
+struct TempFile {
+    path: PathBuf,
+    /// Renamed to the object's path
+    persisted: bool,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl S3Infra for FsS3Infra {
+    async fn put_s3(
+        &self,
+        key: &str,
+        content_type: ContentType,
+        content: ContentStream,
+    ) -> Result<(), InfraError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        Self::write_content(&path, content).await?;
+        Self::write_sidecar(
+            &path,
+            &Sidecar {
+                content_type: content_type.to_string(),
+                metadata: HashMap::new(),
+            },
+        )
+        .await
+    }
+
+    async fn get_s3(&self, key: &str) -> Result<ContentStream, InfraError> {
+        let file = tokio::fs::File::open(self.path(key)?).await?;
+
+        let stream = futures::stream::unfold(Some(file), |file| async move {
+            let mut file = file?;
+            let mut buf = BytesMut::zeroed(READ_CHUNK_SIZE);
+            match file.read(&mut buf).await {
+                Ok(0) => None,
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some((Ok(buf.freeze()), Some(file)))
+                }
+                Err(e) => Some((Err(e.into()), None)),
+            }
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn set_metadata_s3(
+        &self,
+        key: &str,
+        content_type: ContentType,
+        metadata: HashMap<String, String>,
+    ) -> Result<(), InfraError> {
+        let path = self.path(key)?;
+        // Like S3, only existing objects have metadata.
+        tokio::fs::metadata(&path).await?;
+
+        Self::write_sidecar(
+            &path,
+            &Sidecar {
+                content_type: content_type.to_string(),
+                metadata,
+            },
+        )
+        .await
+    }
+
+    async fn head_s3(&self, key: &str) -> Result<Option<ObjectHead>, InfraError> {
+        let path = self.path(key)?;
+        let size = match tokio::fs::metadata(&path).await {
+            Ok(v) => v.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let sidecar = Self::read_sidecar(&path).await?;
+
+        Ok(Some(ObjectHead {
+            size,
+            content_type: sidecar.content_type,
+            metadata: sidecar.metadata,
+        }))
+    }
+
+    async fn list_s3(
+        &self,
+        prefix: &str,
+        continuation: Option<String>,
+    ) -> Result<ObjectList, InfraError> {
+        // The continuation is the last key of the previous page.
+        let mut keys = self
+            .keys()
+            .await?
+            .into_iter()
+            .filter(|v| v.starts_with(prefix))
+            .filter(|v| continuation.as_ref().is_none_or(|after| v > after))
+            .collect::<Vec<_>>();
+        let continuation = if keys.len() > self.list_page_size {
+            keys.truncate(self.list_page_size);
+            keys.last().cloned()
+        } else {
+            None
+        };
+
+        Ok(ObjectList { keys, continuation })
+    }
+
+    async fn delete_s3(&self, key: &str) -> Result<(), InfraError> {
+        let path = self.path(key)?;
+        for path in [Self::sidecar_path(&path), path] {
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn copy_s3(&self, from: &str, to: &str) -> Result<(), InfraError> {
+        let from = self.path(from)?;
+        let to = self.path(to)?;
+        let sidecar = Self::read_sidecar(&from).await?;
+        if let Some(parent) = to.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut temp = TempFile {
+            path: Self::temp_path(&to),
+            persisted: false,
+        };
+        tokio::fs::copy(&from, &temp.path).await?;
+        tokio::fs::rename(&temp.path, &to).await?;
+        temp.persisted = true;
+        Self::write_sidecar(&to, &sidecar).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+
+    fn content(chunks: Vec<Result<Bytes, InfraError>>) -> ContentStream {
+        Box::pin(futures::stream::iter(chunks))
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        let infra = FsS3Infra::new(&root);
+        let key = "papers/PMC1/PMC1.pdf";
+
+        infra
+            .put_s3(
+                key,
+                ContentType::Pdf,
+                content(vec![
+                    Ok(Bytes::from_static(b"%PDF-")),
+                    Ok(Bytes::from_static(b"1.7")),
+                ]),
+            )
+            .await
+            .unwrap();
+        let read = infra
+            .get_s3(key)
+            .await
+            .unwrap()
+            .map_ok(|v| v.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(read, b"%PDF-1.7");
+
+        let metadata = HashMap::from([("sha256".to_string(), "abc".to_string())]);
+        infra
+            .set_metadata_s3(key, ContentType::Pdf, metadata.clone())
+            .await
+            .unwrap();
+        let sidecar: Sidecar = serde_json::from_slice(
+            &std::fs::read(root.join("papers/PMC1/PMC1.pdf.meta.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(sidecar.content_type, "application/pdf");
+        assert_eq!(sidecar.metadata, metadata);
+    }
+
+    #[tokio::test]
+    async fn test_aborted_put_leaves_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        let infra = FsS3Infra::new(&root);
+
+        let res = infra
+            .put_s3(
+                "a/b.pdf",
+                ContentType::Pdf,
+                content(vec![
+                    Ok(Bytes::from_static(b"%PDF-")),
+                    Err(InfraError::StreamAborted("truncated".to_string())),
+                ]),
+            )
+            .await;
+        assert!(res.is_err());
+        assert_eq!(std::fs::read_dir(root.join("a")).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let infra = FsS3Infra::new(dir.path());
+        for key in [
+            "",
+            "../escape",
+            "a/../../escape",
+            "/etc/passwd",
+            "a/b.meta.json",
+        ] {
+            assert!(
+                matches!(infra.path(key), Err(InfraError::InvalidKey(_))),
+                "{key}"
+            );
+        }
+        assert!(
+            infra
+                .set_metadata_s3("missing", ContentType::Text, HashMap::new())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_object_api() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        let mut infra = FsS3Infra::new(&root);
+        infra.list_page_size = 2;
+        for key in ["papers/PMC1/PMC1.pdf", "papers/PMC2/PMC2.pdf", "other.txt"] {
+            infra
+                .put_s3(
+                    key,
+                    ContentType::Pdf,
+                    content(vec![Ok(Bytes::from_static(b"%PDF-"))]),
+                )
+                .await
+                .unwrap();
+        }
+        let metadata = HashMap::from([("sha256".to_string(), "abc".to_string())]);
+        infra
+            .set_metadata_s3("papers/PMC1/PMC1.pdf", ContentType::Pdf, metadata.clone())
+            .await
+            .unwrap();
+        // Left by an upload in progress.
+        std::fs::write(
+            FsS3Infra::temp_path(&root.join("papers/PMC3.pdf")),
+            b"%PDF-",
+        )
+        .unwrap();
+
+        let head = infra
+            .head_s3("papers/PMC1/PMC1.pdf")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            head,
+            ObjectHead {
+                size: 5,
+                content_type: "application/pdf".to_string(),
+                metadata: metadata.clone(),
+            }
+        );
+        assert_eq!(infra.head_s3("papers/PMC9.pdf").await.unwrap(), None);
+
+        infra
+            .copy_s3("papers/PMC1/PMC1.pdf", "papers/PMC0/PMC0.pdf")
+            .await
+            .unwrap();
+        let copy = infra
+            .head_s3("papers/PMC0/PMC0.pdf")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(copy, head);
+
+        let first = infra.list_s3("papers/", None).await.unwrap();
+        assert_eq!(first.keys, ["papers/PMC0/PMC0.pdf", "papers/PMC1/PMC1.pdf"]);
+        let second = infra.list_s3("papers/", first.continuation).await.unwrap();
+        assert_eq!(
+            second,
+            ObjectList {
+                keys: vec!["papers/PMC2/PMC2.pdf".to_string()],
+                continuation: None,
+            }
+        );
+
+        infra.delete_s3("papers/PMC1/PMC1.pdf").await.unwrap();
+        infra.delete_s3("papers/PMC1/PMC1.pdf").await.unwrap();
+        assert_eq!(infra.head_s3("papers/PMC1/PMC1.pdf").await.unwrap(), None);
+        assert!(!root.join("papers/PMC1/PMC1.pdf.meta.json").exists());
+    }
+}
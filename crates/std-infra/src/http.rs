@@ -1,30 +1,83 @@
-use bytes::Bytes;
-use cortexmap_infra::{HttpInfra, InfraError};
-use reqwest::Response;
+use cortexmap_infra::{HttpInfra, InfraError, MethodCM, RequestCM, ResponseCM};
+use reqwest::Method;
 
 pub struct StdHttpInfra {
     client: reqwest::Client,
+    /// For requests that handle redirects themselves, the policy
+    /// is set per client only
+    no_redirects: reqwest::Client,
 }
 
 impl StdHttpInfra {
     pub fn new() -> Self {
         Self {
             client: reqwest::Client::new(),
+            // Fails like `Client::new` would, if TLS can't be set up.
+            no_redirects: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("TLS backend can't be initialized"),
         }
     }
 }
 
-#[async_trait::async_trait]
-impl HttpInfra for StdHttpInfra {
-    async fn get(&self, url: &str) -> Result<Response, InfraError> {
-        Ok(self.client.get(url).send().await?.error_for_status()?)
+impl Default for StdHttpInfra {
+    fn default() -> Self {
+        Self::new()
     }
+}
+
+fn method(method: MethodCM) -> Method {
+    match method {
+        MethodCM::GET => Method::GET,
+        MethodCM::POST => Method::POST,
+        MethodCM::PUT => Method::PUT,
+        MethodCM::PATCH => Method::PATCH,
+        MethodCM::DELETE => Method::DELETE,
+        MethodCM::HEAD => Method::HEAD,
+        MethodCM::OPTIONS => Method::OPTIONS,
+        MethodCM::CONNECT => Method::CONNECT,
+        MethodCM::TRACE => Method::TRACE,
+    }
+}
+
+fn http_error(e: reqwest::Error) -> InfraError {
+    InfraError::HttpError(Box::new(e))
+}
 
-    async fn post(&self, url: &str, body: Option<Bytes>) -> Result<Response, InfraError> {
-        let mut req = self.client.post(url);
-        if let Some(body) = body {
-            req = req.body(body);
+#[async_trait::async_trait]
+impl HttpInfra for StdHttpInfra {
+    async fn send(&self, request: RequestCM) -> Result<ResponseCM, InfraError> {
+        let client = if request.follow_redirects {
+            &self.client
+        } else {
+            &self.no_redirects
+        };
+        let mut builder = client
+            .request(method(request.method), &request.url)
+            .headers(request.headers);
+        if !request.query.is_empty() {
+            builder = builder.query(&request.query);
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
         }
-        Ok(req.send().await?.error_for_status()?)
+        if let Some(timeout) = request.timeout {
+            builder = builder.timeout(timeout);
+        }
+        let response = builder.send().await.map_err(http_error)?;
+
+        let status = response.status();
+        let url = response.url().to_string();
+        let headers = response.headers().clone();
+        let body = futures::stream::unfold(response, |mut resp| async move {
+            match resp.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), resp)),
+                Ok(None) => None,
+                Err(e) => Some((Err(http_error(e)), resp)),
+            }
+        });
+
+        Ok(ResponseCM::new(status, url, Box::pin(body)).with_headers(headers))
     }
 }
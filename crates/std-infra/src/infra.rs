@@ -1,31 +1,69 @@
-use crate::StdDatabaseInfra;
+use crate::fs::FsS3Infra;
 use crate::http::StdHttpInfra;
 use crate::s3::StdS3Infra;
-use bytes::Bytes;
+use crate::{AsyncDatabaseInfra, StdDatabaseInfra};
+use cortexmap_core::blueprint::{Database, Migrations, Storage};
 use cortexmap_infra::{
-    ContentType, DatabaseInfra, HttpInfra, InfraError, NewPaper, Paper, S3Infra,
+    Bibliography, Citation, ContentStream, ContentType, DatabaseInfra, FetchItem, FetchItemOutcome,
+    FetchRun, HttpInfra, InfraError, NewFetchItem, NewFetchRun, NewPaper, NewPaperArtifact,
+    NewPendingPaper, NewSupplementaryFile, ObjectHead, ObjectList, PageRequest, Paper,
+    PaperArtifact, PaperFilter, PaperUpdate, RequestCM, ResponseCM, S3Infra, SupplementaryFile,
 };
-use futures::Stream;
-use reqwest::Response;
-use std::pin::Pin;
+use std::collections::{HashMap, HashSet};
 
 pub struct StdInfra {
     http_infra: StdHttpInfra,
-    db_infra: StdDatabaseInfra,
-    s3_infra: StdS3Infra,
+    db_infra: Box<dyn DatabaseInfra + Send + Sync>,
+    s3_infra: Box<dyn S3Infra + Send + Sync>,
 }
 
 impl StdInfra {
-    pub fn new(
-        database_url: &str,
-        endpoint: &str,
-        access_key: &str,
-        secret_key: &str,
-        bucket: &str,
+    pub async fn new(
+        database: &Database,
+        storage: &Storage,
+        migrations: Migrations,
     ) -> Result<Self, InfraError> {
         let http_infra = StdHttpInfra::new();
-        let db_infra = StdDatabaseInfra::new(database_url)?;
-        let s3_infra = StdS3Infra::new(endpoint, access_key, secret_key, bucket);
+        // Migrations (and opening the SQLite pool) block on their
+        // connections, so they run off the runtime's workers.
+        let db_infra: Box<dyn DatabaseInfra + Send + Sync> = match database {
+            Database::Postgresql(db) => {
+                let infra = AsyncDatabaseInfra::new(db);
+                let infra = tokio::task::spawn_blocking(move || {
+                    infra.check_migrations(migrations)?;
+                    Ok::<_, InfraError>(infra)
+                })
+                .await??;
+                Box::new(infra)
+            }
+            Database::Sqlite(db) => {
+                let path = db.path.clone();
+                let infra = tokio::task::spawn_blocking(move || {
+                    let infra = StdDatabaseInfra::sqlite(&path)?;
+                    infra.check_migrations(migrations)?;
+                    Ok::<_, InfraError>(infra)
+                })
+                .await??;
+                Box::new(infra)
+            }
+        };
+        let s3_infra: Box<dyn S3Infra + Send + Sync> = match storage {
+            Storage::S3(info) => {
+                info.multipart
+                    .validate()
+                    .map_err(|e| InfraError::InvalidConfig(format!("multipart: {e}")))?;
+                Box::new(
+                    StdS3Infra::new(
+                        &info.endpoint,
+                        &info.access_key,
+                        &info.secret_key,
+                        &info.bucket,
+                    )
+                    .with_multipart(info.multipart.clone()),
+                )
+            }
+            Storage::Filesystem(fs) => Box::new(FsS3Infra::new(&fs.root)),
+        };
         Ok(Self {
             http_infra,
             db_infra,
@@ -36,12 +74,8 @@ impl StdInfra {
 
 #[async_trait::async_trait]
 impl HttpInfra for StdInfra {
-    async fn get(&self, url: &str) -> Result<Response, InfraError> {
-        self.http_infra.get(url).await
-    }
-
-    async fn post(&self, url: &str, body: Option<Bytes>) -> Result<Response, InfraError> {
-        self.http_infra.post(url, body).await
+    async fn send(&self, request: RequestCM) -> Result<ResponseCM, InfraError> {
+        self.http_infra.send(request).await
     }
 }
 
@@ -50,6 +84,163 @@ impl DatabaseInfra for StdInfra {
     async fn insert_paper(&self, new_paper: NewPaper) -> Result<Paper, InfraError> {
         self.db_infra.insert_paper(new_paper).await
     }
+
+    async fn insert_pending_paper(&self, paper: NewPendingPaper) -> Result<Paper, InfraError> {
+        self.db_infra.insert_pending_paper(paper).await
+    }
+
+    async fn finalize_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Paper, InfraError> {
+        self.db_infra
+            .finalize_paper(paper_id, update, artifacts)
+            .await
+    }
+
+    async fn abandon_pending_papers(
+        &self,
+        older_than: std::time::Duration,
+    ) -> Result<Vec<Paper>, InfraError> {
+        self.db_infra.abandon_pending_papers(older_than).await
+    }
+
+    async fn get_paper_by_pmcid(&self, pmc_id: &str) -> Result<Option<Paper>, InfraError> {
+        self.db_infra.get_paper_by_pmcid(pmc_id).await
+    }
+
+    async fn get_paper_by_uid(&self, uid: &str) -> Result<Option<Paper>, InfraError> {
+        self.db_infra.get_paper_by_uid(uid).await
+    }
+
+    async fn get_existing_pmcids(&self, pmc_ids: &[String]) -> Result<HashSet<String>, InfraError> {
+        self.db_infra.get_existing_pmcids(pmc_ids).await
+    }
+
+    async fn list_papers(
+        &self,
+        filter: PaperFilter,
+        page: PageRequest,
+    ) -> Result<Vec<Paper>, InfraError> {
+        self.db_infra.list_papers(filter, page).await
+    }
+
+    async fn update_paper(
+        &self,
+        paper_id: i64,
+        update: PaperUpdate,
+    ) -> Result<Option<Paper>, InfraError> {
+        self.db_infra.update_paper(paper_id, update).await
+    }
+
+    async fn delete_paper(&self, paper_id: i64) -> Result<bool, InfraError> {
+        self.db_infra.delete_paper(paper_id).await
+    }
+
+    async fn insert_artifacts(
+        &self,
+        artifacts: Vec<NewPaperArtifact>,
+    ) -> Result<Vec<PaperArtifact>, InfraError> {
+        self.db_infra.insert_artifacts(artifacts).await
+    }
+
+    async fn get_artifacts(&self, paper_id: i64) -> Result<Vec<PaperArtifact>, InfraError> {
+        self.db_infra.get_artifacts(paper_id).await
+    }
+
+    async fn insert_supplementary_file(
+        &self,
+        file: NewSupplementaryFile,
+    ) -> Result<SupplementaryFile, InfraError> {
+        self.db_infra.insert_supplementary_file(file).await
+    }
+
+    async fn get_supplementary_files(
+        &self,
+        paper_id: i64,
+    ) -> Result<Vec<SupplementaryFile>, InfraError> {
+        self.db_infra.get_supplementary_files(paper_id).await
+    }
+
+    async fn insert_bibliography(&self, bibliography: Bibliography) -> Result<(), InfraError> {
+        self.db_infra.insert_bibliography(bibliography).await
+    }
+
+    async fn get_bibliography(&self, paper_id: i64) -> Result<Option<Bibliography>, InfraError> {
+        self.db_infra.get_bibliography(paper_id).await
+    }
+
+    async fn insert_citations(&self, citations: Vec<Citation>) -> Result<usize, InfraError> {
+        self.db_infra.insert_citations(citations).await
+    }
+
+    async fn get_cited(&self, work: &str) -> Result<Vec<Citation>, InfraError> {
+        self.db_infra.get_cited(work).await
+    }
+
+    async fn get_citing(&self, work: &str) -> Result<Vec<Citation>, InfraError> {
+        self.db_infra.get_citing(work).await
+    }
+
+    async fn get_citations(&self) -> Result<Vec<Citation>, InfraError> {
+        self.db_infra.get_citations().await
+    }
+
+    async fn get_pmcids_by_pmids(
+        &self,
+        pmids: &[String],
+    ) -> Result<HashMap<String, String>, InfraError> {
+        self.db_infra.get_pmcids_by_pmids(pmids).await
+    }
+
+    async fn rename_work(&self, from: &str, to: &str) -> Result<usize, InfraError> {
+        self.db_infra.rename_work(from, to).await
+    }
+
+    async fn insert_fetch_run(&self, run: NewFetchRun) -> Result<FetchRun, InfraError> {
+        self.db_infra.insert_fetch_run(run).await
+    }
+
+    async fn get_fetch_run(&self, run_id: i64) -> Result<Option<FetchRun>, InfraError> {
+        self.db_infra.get_fetch_run(run_id).await
+    }
+
+    async fn get_unfinished_fetch_runs(&self) -> Result<Vec<FetchRun>, InfraError> {
+        self.db_infra.get_unfinished_fetch_runs().await
+    }
+
+    async fn insert_fetch_page(
+        &self,
+        run_id: i64,
+        items: Vec<NewFetchItem>,
+        next_cursor_mark: &str,
+    ) -> Result<usize, InfraError> {
+        self.db_infra
+            .insert_fetch_page(run_id, items, next_cursor_mark)
+            .await
+    }
+
+    async fn get_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError> {
+        self.db_infra.get_fetch_items(run_id).await
+    }
+
+    async fn get_unfinished_fetch_items(&self, run_id: i64) -> Result<Vec<FetchItem>, InfraError> {
+        self.db_infra.get_unfinished_fetch_items(run_id).await
+    }
+
+    async fn update_fetch_item(
+        &self,
+        item_id: i64,
+        outcome: FetchItemOutcome,
+    ) -> Result<FetchItem, InfraError> {
+        self.db_infra.update_fetch_item(item_id, outcome).await
+    }
+
+    async fn update_fetch_run_status(&self, run_id: i64, status: &str) -> Result<(), InfraError> {
+        self.db_infra.update_fetch_run_status(run_id, status).await
+    }
 }
 
 #[async_trait::async_trait]
@@ -58,8 +249,43 @@ impl S3Infra for StdInfra {
         &self,
         key: &str,
         content_type: ContentType,
-        content: Pin<Box<dyn Stream<Item = Bytes> + Send + Sync>>,
+        content: ContentStream,
     ) -> Result<(), InfraError> {
         self.s3_infra.put_s3(key, content_type, content).await
     }
+
+    async fn get_s3(&self, key: &str) -> Result<ContentStream, InfraError> {
+        self.s3_infra.get_s3(key).await
+    }
+
+    async fn set_metadata_s3(
+        &self,
+        key: &str,
+        content_type: ContentType,
+        metadata: HashMap<String, String>,
+    ) -> Result<(), InfraError> {
+        self.s3_infra
+            .set_metadata_s3(key, content_type, metadata)
+            .await
+    }
+
+    async fn head_s3(&self, key: &str) -> Result<Option<ObjectHead>, InfraError> {
+        self.s3_infra.head_s3(key).await
+    }
+
+    async fn list_s3(
+        &self,
+        prefix: &str,
+        continuation: Option<String>,
+    ) -> Result<ObjectList, InfraError> {
+        self.s3_infra.list_s3(prefix, continuation).await
+    }
+
+    async fn delete_s3(&self, key: &str) -> Result<(), InfraError> {
+        self.s3_infra.delete_s3(key).await
+    }
+
+    async fn copy_s3(&self, from: &str, to: &str) -> Result<(), InfraError> {
+        self.s3_infra.copy_s3(from, to).await
+    }
 }
@@ -1,38 +1,91 @@
+mod async_database;
 mod database;
+mod fs;
 mod http;
 mod infra;
+mod migrations;
+mod multipart;
 mod s3;
+mod shutdown;
 
+pub use async_database::*;
 pub use database::*;
+pub use fs::FsS3Infra;
+pub use http::StdHttpInfra;
+pub use migrations::*;
+pub use shutdown::cancel_on_shutdown_signal;
 
 use crate::infra::StdInfra;
+use cortexmap_core::blueprint::{Connections, Database, Migrations, Storage};
 use cortexmap_infra::{InfraContext, InfraError};
 use std::sync::Arc;
 
 #[derive(derive_builder::Builder)]
 pub struct StdInfraContext {
-    pub database_url: String,
-    pub endpoint: String,
-    pub access_key: String,
-    pub secret_key: String,
-    pub bucket: String,
+    /// Postgres server or SQLite file the records go to
+    pub database: Database,
+    /// S3 bucket or local directory the files go to
+    pub storage: Storage,
+    /// Whether pending migrations are applied or refused at startup
+    #[builder(default)]
+    pub migrations: Migrations,
 }
 
 impl StdInfraContext {
+    /// Takes the database and the storage from the blueprint's connections.
+    pub fn from_connections(connections: Connections) -> Self {
+        Self {
+            database: connections.db,
+            storage: connections.storage,
+            migrations: connections.migrations,
+        }
+    }
+
     // maybe consume self?
-    pub fn get(&self) -> Result<InfraContext<StdInfra>, InfraError> {
+    pub async fn get(&self) -> Result<InfraContext<StdInfra>, InfraError> {
         // TODO: ideally this function should only be called ones
         // but it's easy to make mistakes here,
         // so maybe we could initiate this statically
         // and always return the same instance.
         Ok(InfraContext {
-            infra: Arc::new(StdInfra::new(
-                &self.database_url,
-                &self.endpoint,
-                &self.access_key,
-                &self.secret_key,
-                &self.bucket,
-            )?),
+            infra: Arc::new(StdInfra::new(&self.database, &self.storage, self.migrations).await?),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cortexmap_core::blueprint::{Filesystem, Sqlite};
+    use cortexmap_infra::DatabaseInfra;
+
+    #[tokio::test]
+    async fn test_get_sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        let context = StdInfraContextBuilder::default()
+            .database(Database::Sqlite(Sqlite {
+                path: dir.path().join("cortexmap.sqlite").to_string_lossy().into(),
+            }))
+            .storage(Storage::Filesystem(Filesystem {
+                root: dir.path().join("objects").to_string_lossy().into(),
+            }))
+            .build()
+            .unwrap();
+
+        let ctx = context.get().await.unwrap();
+        // The migrations ran, so the tables are there.
+        assert!(
+            ctx.infra
+                .get_paper_by_pmcid("PMC1")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let verified = StdInfraContext {
+            migrations: Migrations::Verify,
+            ..context
+        };
+        verified.get().await.unwrap();
+    }
+}
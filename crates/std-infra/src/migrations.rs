@@ -0,0 +1,181 @@
+use crate::{AsyncDatabaseInfra, DbPool, StdDatabaseInfra};
+use cortexmap_core::blueprint::Migrations;
+use cortexmap_infra::InfraError;
+use diesel::backend::Backend;
+use diesel::migration::MigrationSource;
+use diesel::{Connection, PgConnection};
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
+use std::collections::HashSet;
+
+pub const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("../../migrations");
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("../../migrations-sqlite");
+
+/// How the database schema compares to the migrations built in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationStatus {
+    /// Names of the migrations not applied yet, oldest first
+    pub pending: Vec<String>,
+    /// Versions applied to the database that this binary doesn't know,
+    /// left by a newer build
+    pub unknown: Vec<String>,
+}
+
+impl MigrationStatus {
+    pub fn is_current(&self) -> bool {
+        self.pending.is_empty() && self.unknown.is_empty()
+    }
+}
+
+impl StdDatabaseInfra {
+    /// Compares the applied migrations with the ones built in.
+    pub fn migration_status(&self) -> Result<MigrationStatus, InfraError> {
+        match self.pool() {
+            DbPool::Postgresql(pool) => status(&mut *pool.get()?, &POSTGRES_MIGRATIONS),
+            DbPool::Sqlite(pool) => status(&mut *pool.get()?, &SQLITE_MIGRATIONS),
+        }
+    }
+
+    /// Applies the pending migrations, returns their versions.
+    pub fn migrate(&self) -> Result<Vec<String>, InfraError> {
+        match self.pool() {
+            DbPool::Postgresql(pool) => run_pending(&mut *pool.get()?, POSTGRES_MIGRATIONS),
+            DbPool::Sqlite(pool) => run_pending(&mut *pool.get()?, SQLITE_MIGRATIONS),
+        }
+    }
+
+    /// Brings the schema in line with the binary as the policy asks,
+    /// or fails if it can't be.
+    pub fn check_migrations(&self, policy: Migrations) -> Result<(), InfraError> {
+        match self.pool() {
+            DbPool::Postgresql(pool) => check(&mut *pool.get()?, POSTGRES_MIGRATIONS, policy),
+            DbPool::Sqlite(pool) => check(&mut *pool.get()?, SQLITE_MIGRATIONS, policy),
+        }
+    }
+}
+
+impl AsyncDatabaseInfra {
+    /// Like [`StdDatabaseInfra::check_migrations`]. The migrations run
+    /// over a connection of their own, since diesel applies them
+    /// synchronously.
+    pub fn check_migrations(&self, policy: Migrations) -> Result<(), InfraError> {
+        let conn = &mut PgConnection::establish(self.url())
+            .map_err(|e| InfraError::Migration(Box::new(e)))?;
+        check(conn, POSTGRES_MIGRATIONS, policy)
+    }
+}
+
+fn check<DB: Backend>(
+    conn: &mut impl MigrationHarness<DB>,
+    source: EmbeddedMigrations,
+    policy: Migrations,
+) -> Result<(), InfraError> {
+    let status = status(conn, &source)?;
+    if !status.unknown.is_empty() {
+        let message = format!(
+            "applied migrations unknown to this build: {}",
+            status.unknown.join(", ")
+        );
+        match policy {
+            Migrations::Apply => tracing::warn!("{message}"),
+            Migrations::Verify => return Err(InfraError::SchemaMismatch(message)),
+        }
+    }
+    if !status.pending.is_empty() {
+        match policy {
+            Migrations::Apply => {
+                let applied = run_pending(conn, source)?;
+                tracing::info!("Applied migrations {}", applied.join(", "));
+            }
+            Migrations::Verify => {
+                return Err(InfraError::SchemaMismatch(format!(
+                    "pending migrations: {}",
+                    status.pending.join(", ")
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_pending<DB: Backend>(
+    conn: &mut impl MigrationHarness<DB>,
+    source: EmbeddedMigrations,
+) -> Result<Vec<String>, InfraError> {
+    let applied = conn
+        .run_pending_migrations(source)
+        .map_err(InfraError::Migration)?;
+    Ok(applied.iter().map(|v| v.to_string()).collect())
+}
+
+fn status<DB: Backend>(
+    conn: &mut impl MigrationHarness<DB>,
+    source: &EmbeddedMigrations,
+) -> Result<MigrationStatus, InfraError> {
+    let migrations = MigrationSource::<DB>::migrations(source).map_err(InfraError::Migration)?;
+    let applied = conn
+        .applied_migrations()
+        .map_err(InfraError::Migration)?
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>();
+    let known = migrations
+        .iter()
+        .map(|v| v.name().version().to_string())
+        .collect::<HashSet<_>>();
+    let pending = migrations
+        .iter()
+        .filter(|v| !applied.contains(&v.name().version().to_string()))
+        .map(|v| v.name().to_string())
+        .collect();
+    let unknown = applied.into_iter().filter(|v| !known.contains(v)).collect();
+    Ok(MigrationStatus { pending, unknown })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+
+    #[test]
+    fn test_check_migrations() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = StdDatabaseInfra::sqlite(dir.path().join("cortexmap.sqlite").to_str().unwrap())
+            .unwrap();
+
+        let status = db.migration_status().unwrap();
+        assert_eq!(
+            status.pending.len(),
+            MigrationSource::<diesel::sqlite::Sqlite>::migrations(&SQLITE_MIGRATIONS)
+                .unwrap()
+                .len()
+        );
+        assert!(status.pending[0].ends_with("_create_papers"));
+        assert!(status.unknown.is_empty());
+        assert!(matches!(
+            db.check_migrations(Migrations::Verify),
+            Err(InfraError::SchemaMismatch(_))
+        ));
+
+        db.check_migrations(Migrations::Apply).unwrap();
+        assert!(db.migration_status().unwrap().is_current());
+        db.check_migrations(Migrations::Verify).unwrap();
+        assert!(db.migrate().unwrap().is_empty());
+
+        let DbPool::Sqlite(pool) = db.pool() else {
+            unreachable!()
+        };
+        pool.get()
+            .unwrap()
+            .batch_execute(
+                "INSERT INTO __diesel_schema_migrations (version) VALUES ('99990101000000')",
+            )
+            .unwrap();
+        let status = db.migration_status().unwrap();
+        assert_eq!(status.unknown, ["99990101000000"]);
+        assert!(matches!(
+            db.check_migrations(Migrations::Verify),
+            Err(InfraError::SchemaMismatch(_))
+        ));
+        db.check_migrations(Migrations::Apply).unwrap();
+    }
+}
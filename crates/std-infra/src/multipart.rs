@@ -0,0 +1,211 @@
+use bytes::{Bytes, BytesMut};
+use cortexmap_core::blueprint::Multipart;
+use cortexmap_infra::{ContentStream, InfraError};
+use futures::{StreamExt, TryStreamExt};
+use std::time::Duration;
+
+/// Pause before the second attempt at a part, doubled for every further one.
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Buffers a content stream, so it can be cut into parts
+/// regardless of the size of the chunks it comes in.
+pub(crate) struct PartReader {
+    content: ContentStream,
+    buf: BytesMut,
+    done: bool,
+}
+
+impl PartReader {
+    pub(crate) fn new(content: ContentStream) -> Self {
+        Self {
+            content,
+            buf: BytesMut::new(),
+            done: false,
+        }
+    }
+
+    /// Buffers until `size` bytes are at hand or the stream ended,
+    /// returns how many bytes are buffered.
+    pub(crate) async fn fill(&mut self, size: usize) -> Result<usize, InfraError> {
+        while self.buf.len() < size && !self.done {
+            match self.content.next().await {
+                Some(chunk) => self.buf.extend_from_slice(&chunk?),
+                None => self.done = true,
+            }
+        }
+        Ok(self.buf.len())
+    }
+
+    /// The next `size` bytes, less for the last part,
+    /// `None` once the stream is consumed.
+    pub(crate) async fn next_part(&mut self, size: usize) -> Result<Option<Bytes>, InfraError> {
+        let len = self.fill(size).await?.min(size);
+        if len == 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.buf.split_to(len).freeze()))
+    }
+}
+
+/// Uploads the parts of one multipart upload.
+#[async_trait::async_trait]
+pub(crate) trait PartUploader: Sync {
+    /// Uploads the part numbered `number` (from 1), returns its ETag.
+    async fn upload_part(&self, number: i32, body: Bytes) -> Result<String, InfraError>;
+}
+
+/// Uploads the rest of `reader` as parts of `config.part_size`, up to
+/// `config.concurrency` at a time, retrying each part on failure.
+/// Returns the ETags of the parts by part number.
+pub(crate) async fn upload_parts<U: PartUploader>(
+    uploader: &U,
+    reader: PartReader,
+    config: &Multipart,
+) -> Result<Vec<(i32, String)>, InfraError> {
+    let part_size = config.part_size;
+    let parts = futures::stream::try_unfold((reader, 1), move |(mut reader, number)| async move {
+        let part = reader.next_part(part_size).await?;
+        Ok::<_, InfraError>(part.map(|body| ((number, body), (reader, number + 1))))
+    });
+
+    let mut etags = parts
+        .map_ok(|(number, body)| async move {
+            let etag = upload_with_retries(uploader, number, body, config.part_attempts).await?;
+            Ok((number, etag))
+        })
+        .try_buffer_unordered(config.concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+    etags.sort_by_key(|(number, _)| *number);
+    Ok(etags)
+}
+
+async fn upload_with_retries<U: PartUploader>(
+    uploader: &U,
+    number: i32,
+    body: Bytes,
+    attempts: u32,
+) -> Result<String, InfraError> {
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match uploader.upload_part(number, body.clone()).await {
+            Ok(etag) => return Ok(etag),
+            Err(e) if attempt < attempts => {
+                tracing::warn!("Retrying part {number} after attempt {attempt} failed: {e}");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct FakeUploader {
+        parts: Mutex<HashMap<i32, Bytes>>,
+        /// Failures left by part number
+        failures: Mutex<HashMap<i32, u32>>,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl PartUploader for FakeUploader {
+        async fn upload_part(&self, number: i32, body: Bytes) -> Result<String, InfraError> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            if let Some(left) = self.failures.lock().unwrap().get_mut(&number)
+                && *left > 0
+            {
+                *left -= 1;
+                return Err(InfraError::StreamAborted(format!("part {number}")));
+            }
+            self.parts.lock().unwrap().insert(number, body);
+            Ok(format!("etag-{number}"))
+        }
+    }
+
+    fn reader(chunks: Vec<Result<&'static [u8], &'static str>>) -> PartReader {
+        PartReader::new(Box::pin(futures::stream::iter(chunks.into_iter().map(
+            |v| {
+                v.map(Bytes::from_static)
+                    .map_err(|e| InfraError::StreamAborted(e.to_string()))
+            },
+        ))))
+    }
+
+    fn config(part_attempts: u32) -> Multipart {
+        Multipart {
+            threshold: 4,
+            part_size: 4,
+            concurrency: 2,
+            part_attempts,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_part_reader() {
+        let mut reader = reader(vec![Ok(b"ab"), Ok(b"cdefg"), Ok(b""), Ok(b"hi")]);
+        assert_eq!(reader.fill(3).await.unwrap(), 7);
+        assert_eq!(reader.next_part(4).await.unwrap().unwrap(), "abcd");
+        assert_eq!(reader.next_part(4).await.unwrap().unwrap(), "efgh");
+        assert_eq!(reader.next_part(4).await.unwrap().unwrap(), "i");
+        assert_eq!(reader.next_part(4).await.unwrap(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_upload_parts() {
+        let uploader = FakeUploader::default();
+        uploader.failures.lock().unwrap().insert(2, 2);
+        let etags = upload_parts(
+            &uploader,
+            reader(vec![Ok(b"abc"), Ok(b"defghij"), Ok(b"k")]),
+            &config(3),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            etags,
+            [
+                (1, "etag-1".to_string()),
+                (2, "etag-2".to_string()),
+                (3, "etag-3".to_string())
+            ]
+        );
+        let parts = uploader.parts.lock().unwrap();
+        assert_eq!(parts[&1], "abcd");
+        assert_eq!(parts[&2], "efgh");
+        assert_eq!(parts[&3], "ijk");
+        assert_eq!(uploader.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_upload_parts_failures() {
+        let uploader = FakeUploader::default();
+        uploader.failures.lock().unwrap().insert(1, 3);
+        let res = upload_parts(&uploader, reader(vec![Ok(b"abcdefgh")]), &config(3)).await;
+        assert!(matches!(res, Err(InfraError::StreamAborted(e)) if e == "part 1"));
+
+        let uploader = FakeUploader::default();
+        let res = upload_parts(
+            &uploader,
+            reader(vec![Ok(b"abcdefgh"), Err("truncated")]),
+            &config(3),
+        )
+        .await;
+        assert!(matches!(res, Err(InfraError::StreamAborted(e)) if e == "truncated"));
+    }
+}
@@ -1,14 +1,90 @@
+use crate::multipart::{PartReader, PartUploader, upload_parts};
 use aws_credential_types::Credentials;
 use aws_sdk_s3::Client;
 use aws_sdk_s3::config::Region;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, MetadataDirective};
 use bytes::Bytes;
-use cortexmap_infra::{ContentType, InfraError, S3Infra};
-use futures::{Stream, StreamExt};
-use std::pin::Pin;
+use cortexmap_core::blueprint::Multipart;
+use cortexmap_infra::{ContentStream, ContentType, InfraError, ObjectHead, ObjectList, S3Infra};
+use std::collections::HashMap;
 
 pub struct StdS3Infra {
     client: Client,
     bucket: String,
+    multipart: Multipart,
+}
+
+struct SdkPartUploader<'a> {
+    client: &'a Client,
+    bucket: &'a str,
+    key: &'a str,
+    upload_id: &'a str,
+}
+
+#[async_trait::async_trait]
+impl PartUploader for SdkPartUploader<'_> {
+    async fn upload_part(&self, number: i32, body: Bytes) -> Result<String, InfraError> {
+        let output = self
+            .client
+            .upload_part()
+            .bucket(self.bucket)
+            .key(self.key)
+            .upload_id(self.upload_id)
+            .part_number(number)
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+
+        output
+            .e_tag()
+            .map(str::to_string)
+            .ok_or_else(|| InfraError::MultipartUpload(format!("no ETag for part {number}").into()))
+    }
+}
+
+/// Aborts a multipart upload that is dropped before it completed or
+/// was aborted, e.g. when the task uploading it is cancelled. The
+/// abort is spawned since `drop` can't wait for it.
+struct AbortOnDrop {
+    client: Client,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    armed: bool,
+}
+
+impl AbortOnDrop {
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!(
+                "Upload of {} dropped outside a runtime, not aborted",
+                self.key
+            );
+            return;
+        };
+        let abort = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id);
+        let key = std::mem::take(&mut self.key);
+        runtime.spawn(async move {
+            if let Err(e) = abort.send().await {
+                tracing::warn!("Failed to abort the dropped upload of {key}: {e}");
+            }
+        });
+    }
 }
 
 impl StdS3Infra {
@@ -25,7 +101,111 @@ impl StdS3Infra {
         Self {
             client,
             bucket: bucket.to_owned(),
+            multipart: Multipart::default(),
+        }
+    }
+
+    /// Sets how large objects are uploaded.
+    pub fn with_multipart(mut self, multipart: Multipart) -> Self {
+        self.multipart = multipart;
+        self
+    }
+
+    /// Uploads the content in parts, aborting the upload if any part
+    /// (or the content stream) fails, or if the upload is dropped
+    /// half way, so no parts are left behind.
+    async fn put_multipart(
+        &self,
+        key: &str,
+        content_type: ContentType,
+        reader: PartReader,
+    ) -> Result<(), InfraError> {
+        let output = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type.to_string())
+            .send()
+            .await?;
+        let upload_id = output
+            .upload_id()
+            .ok_or_else(|| InfraError::MultipartUpload("no upload id in the response".into()))?;
+        let guard = AbortOnDrop {
+            client: self.client.clone(),
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            upload_id: upload_id.to_string(),
+            armed: true,
+        };
+
+        let uploader = SdkPartUploader {
+            client: &self.client,
+            bucket: &self.bucket,
+            key,
+            upload_id,
+        };
+        let res = match upload_parts(&uploader, reader, &self.multipart).await {
+            Ok(etags) => self.complete_multipart(key, upload_id, etags).await,
+            Err(e) => Err(e),
+        };
+        if res.is_err() {
+            let abort = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+            if let Err(e) = abort {
+                tracing::warn!("Failed to abort the upload of {key}: {e}");
+            }
         }
+        guard.disarm();
+        res
+    }
+
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        etags: Vec<(i32, String)>,
+    ) -> Result<(), InfraError> {
+        let parts = etags
+            .into_iter()
+            .map(|(number, etag)| {
+                CompletedPart::builder()
+                    .part_number(number)
+                    .e_tag(etag)
+                    .build()
+            })
+            .collect();
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // `CopySource` is `bucket/key` and has to be url-encoded,
+    // but the separators must stay as they are.
+    fn copy_source(&self, key: &str) -> String {
+        let key = key
+            .split('/')
+            .map(|segment| urlencoding::encode(segment).into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+        format!("{}/{key}", self.bucket)
     }
 }
 
@@ -35,29 +215,202 @@ impl S3Infra for StdS3Infra {
         &self,
         key: &str,
         content_type: ContentType,
-        content: Pin<Box<dyn Stream<Item = Bytes> + Send + Sync>>,
+        content: ContentStream,
     ) -> Result<(), InfraError> {
-        // Convert the stream into http_body_util::StreamBody
-        let stream_body = http_body_util::StreamBody::new(
-            content.map(|bytes| Ok::<_, std::convert::Infallible>(http_body::Frame::data(bytes))),
-        );
-
-        // Convert to AWS SDK types
-        let byte_stream = aws_sdk_s3::primitives::ByteStream::from_body_1_x(
-            aws_smithy_types::body::SdkBody::from_body_1_x(
-                http_body_util::combinators::BoxBody::new(stream_body),
-            ),
-        );
+        let mut reader = PartReader::new(content);
+        if reader.fill(self.multipart.threshold + 1).await? > self.multipart.threshold {
+            return self.put_multipart(key, content_type, reader).await;
+        }
 
+        // Small enough for a single request, with its length known up front.
+        let body = reader.next_part(self.multipart.threshold).await?;
         self.client
             .put_object()
             .bucket(&self.bucket)
             .key(key)
-            .body(byte_stream)
+            .body(ByteStream::from(body.unwrap_or_default()))
+            .content_type(content_type.to_string())
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_s3(&self, key: &str) -> Result<ContentStream, InfraError> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await?;
+
+        let stream = futures::stream::unfold(output.body, |mut body| async move {
+            match body.try_next().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), body)),
+                Ok(None) => None,
+                Err(e) => Some((Err(e.into()), body)),
+            }
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn set_metadata_s3(
+        &self,
+        key: &str,
+        content_type: ContentType,
+        metadata: HashMap<String, String>,
+    ) -> Result<(), InfraError> {
+        // S3 has no way to edit metadata in place,
+        // so the object is copied onto itself.
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .copy_source(self.copy_source(key))
+            .metadata_directive(MetadataDirective::Replace)
             .content_type(content_type.to_string())
+            .set_metadata(Some(metadata))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn head_s3(&self, key: &str) -> Result<Option<ObjectHead>, InfraError> {
+        let output = match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(v) => v,
+            Err(e) if e.as_service_error().is_some_and(|v| v.is_not_found()) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(ObjectHead {
+            size: output.content_length().unwrap_or_default().max(0) as u64,
+            content_type: output
+                .content_type()
+                .unwrap_or("application/octet-stream")
+                .to_string(),
+            metadata: output.metadata().cloned().unwrap_or_default(),
+        }))
+    }
+
+    async fn list_s3(
+        &self,
+        prefix: &str,
+        continuation: Option<String>,
+    ) -> Result<ObjectList, InfraError> {
+        let output = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .set_continuation_token(continuation)
+            .send()
+            .await?;
+
+        Ok(ObjectList {
+            keys: output
+                .contents()
+                .iter()
+                .filter_map(|v| v.key().map(str::to_string))
+                .collect(),
+            continuation: output.next_continuation_token().map(str::to_string),
+        })
+    }
+
+    async fn delete_s3(&self, key: &str) -> Result<(), InfraError> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
             .send()
             .await?;
 
         Ok(())
     }
+
+    async fn copy_s3(&self, from: &str, to: &str) -> Result<(), InfraError> {
+        // The content type and metadata are copied along by default.
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .key(to)
+            .copy_source(self.copy_source(from))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// These run against an S3 compatible service like MinIO, named by the
+/// `CORTEXMAP_TEST_S3_*` variables. The bucket has to exist already.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+
+    fn test_infra() -> StdS3Infra {
+        let var = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{name} is not set"));
+        StdS3Infra::new(
+            &var("CORTEXMAP_TEST_S3_ENDPOINT"),
+            &var("CORTEXMAP_TEST_S3_ACCESS_KEY"),
+            &var("CORTEXMAP_TEST_S3_SECRET_KEY"),
+            &var("CORTEXMAP_TEST_S3_BUCKET"),
+        )
+    }
+
+    #[tokio::test]
+    #[ignore = "needs an S3 compatible service at CORTEXMAP_TEST_S3_ENDPOINT"]
+    async fn test_object_api() {
+        let s3 = test_infra();
+        let prefix = format!("cortexmap-test-{}/", uuid::Uuid::new_v4());
+        let key = format!("{prefix}PMC1 (1).pdf");
+        let copy = format!("{prefix}PMC2.pdf");
+
+        let content: ContentStream =
+            Box::pin(futures::stream::iter([Ok(Bytes::from_static(b"%PDF-1.7"))]));
+        s3.put_s3(&key, ContentType::Pdf, content).await.unwrap();
+        let metadata = HashMap::from([("sha256".to_string(), "abc".to_string())]);
+        s3.set_metadata_s3(&key, ContentType::Pdf, metadata.clone())
+            .await
+            .unwrap();
+        s3.copy_s3(&key, &copy).await.unwrap();
+
+        let head = s3.head_s3(&copy).await.unwrap().unwrap();
+        assert_eq!(
+            head,
+            ObjectHead {
+                size: 8,
+                content_type: "application/pdf".to_string(),
+                metadata,
+            }
+        );
+        let read = s3
+            .get_s3(&copy)
+            .await
+            .unwrap()
+            .map_ok(|v| v.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(read, b"%PDF-1.7");
+        let list = s3.list_s3(&prefix, None).await.unwrap();
+        assert_eq!(list.keys, [key.clone(), copy.clone()]);
+        assert_eq!(list.continuation, None);
+
+        s3.delete_s3(&key).await.unwrap();
+        s3.delete_s3(&copy).await.unwrap();
+        assert_eq!(s3.head_s3(&key).await.unwrap(), None);
+        assert!(s3.list_s3(&prefix, None).await.unwrap().keys.is_empty());
+    }
 }
@@ -0,0 +1,35 @@
+use tokio_util::sync::CancellationToken;
+
+/// Cancels `token` on SIGINT (Ctrl-C) or SIGTERM, so that a binary
+/// can hand it to `cortexmap_fetcher::fetch` and stop cleanly.
+/// Has to be called from within a tokio runtime.
+pub fn cancel_on_shutdown_signal(token: CancellationToken) {
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("Shutdown requested, finishing in-flight work");
+        token.cancel();
+    });
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("Can't listen for SIGTERM: {e}");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
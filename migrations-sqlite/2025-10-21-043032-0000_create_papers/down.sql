@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_papers_query;
+DROP INDEX IF EXISTS idx_papers_pmc_id;
+DROP TABLE IF EXISTS papers;
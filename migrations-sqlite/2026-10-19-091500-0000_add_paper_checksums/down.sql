@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_papers_sha256;
+ALTER TABLE papers DROP COLUMN size_bytes;
+ALTER TABLE papers DROP COLUMN sha256;
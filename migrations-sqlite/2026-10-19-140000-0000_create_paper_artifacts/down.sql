@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_paper_artifacts_kind;
+DROP TABLE IF EXISTS paper_artifacts;
@@ -0,0 +1,17 @@
+CREATE TABLE paper_artifacts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    paper_id BIGINT NOT NULL REFERENCES papers(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    s3_key TEXT NOT NULL,
+    sha256 TEXT,
+    size_bytes BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (paper_id, kind)
+);
+
+-- Index for faster lookups by kind
+CREATE INDEX idx_paper_artifacts_kind ON paper_artifacts(kind);
+
+-- Every paper stored so far has exactly one PDF
+INSERT INTO paper_artifacts (paper_id, kind, s3_key, sha256, size_bytes, created_at)
+SELECT id, 'pdf', s3_key, sha256, size_bytes, created_at FROM papers;
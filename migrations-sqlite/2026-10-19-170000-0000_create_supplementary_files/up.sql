@@ -0,0 +1,11 @@
+CREATE TABLE supplementary_files (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    paper_id BIGINT NOT NULL REFERENCES papers(id) ON DELETE CASCADE,
+    filename TEXT NOT NULL,
+    s3_key TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    sha256 TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (paper_id, filename)
+);
@@ -0,0 +1,42 @@
+CREATE TABLE paper_metadata (
+    paper_id BIGINT PRIMARY KEY REFERENCES papers(id) ON DELETE CASCADE,
+    title TEXT,
+    abstract_text TEXT,
+    journal_title TEXT,
+    journal_issn TEXT,
+    publication_date DATE,
+    doi TEXT,
+    pmid TEXT,
+    license TEXT,
+    is_open_access BOOLEAN
+);
+
+CREATE TABLE paper_authors (
+    paper_id BIGINT NOT NULL REFERENCES papers(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    full_name TEXT NOT NULL,
+    first_name TEXT,
+    last_name TEXT,
+    orcid TEXT,
+    PRIMARY KEY (paper_id, position)
+);
+
+CREATE TABLE mesh_headings (
+    paper_id BIGINT NOT NULL REFERENCES papers(id) ON DELETE CASCADE,
+    descriptor_name TEXT NOT NULL,
+    major_topic BOOLEAN NOT NULL,
+    PRIMARY KEY (paper_id, descriptor_name)
+);
+
+CREATE TABLE paper_keywords (
+    paper_id BIGINT NOT NULL REFERENCES papers(id) ON DELETE CASCADE,
+    keyword TEXT NOT NULL,
+    PRIMARY KEY (paper_id, keyword)
+);
+
+-- Indexes for filtering the corpus
+CREATE INDEX idx_paper_metadata_publication_date ON paper_metadata(publication_date);
+CREATE INDEX idx_paper_metadata_journal_issn ON paper_metadata(journal_issn);
+CREATE INDEX idx_paper_authors_orcid ON paper_authors(orcid);
+CREATE INDEX idx_mesh_headings_descriptor_name ON mesh_headings(descriptor_name);
+CREATE INDEX idx_paper_keywords_keyword ON paper_keywords(keyword);
@@ -0,0 +1 @@
+ALTER TABLE papers DROP COLUMN pdf_source_url;
@@ -0,0 +1,32 @@
+-- A harvest of the results of one query, paged through with Europe PMC's cursorMark
+CREATE TABLE fetch_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    query TEXT NOT NULL,
+    page_size BIGINT NOT NULL,
+    -- Cursor of the next page to fetch, `*` is the first page
+    cursor_mark TEXT NOT NULL DEFAULT '*',
+    -- `running` until every page was fetched and every item attempted, then `completed`
+    status TEXT NOT NULL DEFAULT 'running',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_fetch_runs_status ON fetch_runs(status);
+
+-- A search hit of a run, recorded before its files are fetched
+CREATE TABLE fetch_items (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id BIGINT NOT NULL REFERENCES fetch_runs(id) ON DELETE CASCADE,
+    pmc_id TEXT NOT NULL,
+    -- The search hit as JSON, so the item can be retried without searching again
+    search_record TEXT,
+    -- `pending`, `done` or `failed`
+    state TEXT NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    paper_id BIGINT REFERENCES papers(id) ON DELETE SET NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(run_id, pmc_id)
+);
+
+CREATE INDEX idx_fetch_items_run_id_state ON fetch_items(run_id, state);
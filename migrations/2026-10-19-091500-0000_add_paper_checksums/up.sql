@@ -0,0 +1,6 @@
+-- Nullable, since papers stored before this migration have no checksum
+ALTER TABLE papers ADD COLUMN sha256 TEXT;
+ALTER TABLE papers ADD COLUMN size_bytes BIGINT;
+
+-- Index for finding identical objects stored under different PMCIDs
+CREATE INDEX idx_papers_sha256 ON papers(sha256);
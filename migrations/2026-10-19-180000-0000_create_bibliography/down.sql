@@ -0,0 +1,9 @@
+DROP INDEX IF EXISTS idx_paper_keywords_keyword;
+DROP INDEX IF EXISTS idx_mesh_headings_descriptor_name;
+DROP INDEX IF EXISTS idx_paper_authors_orcid;
+DROP INDEX IF EXISTS idx_paper_metadata_journal_issn;
+DROP INDEX IF EXISTS idx_paper_metadata_publication_date;
+DROP TABLE IF EXISTS paper_keywords;
+DROP TABLE IF EXISTS mesh_headings;
+DROP TABLE IF EXISTS paper_authors;
+DROP TABLE IF EXISTS paper_metadata;
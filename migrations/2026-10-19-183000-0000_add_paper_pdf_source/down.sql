@@ -0,0 +1 @@
+ALTER TABLE papers DROP COLUMN IF EXISTS pdf_source_url;
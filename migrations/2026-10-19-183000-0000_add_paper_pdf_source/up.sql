@@ -0,0 +1,3 @@
+-- URL the stored PDF was downloaded from, NULL for papers
+-- stored before it was recorded or without a PDF
+ALTER TABLE papers ADD COLUMN pdf_source_url TEXT;
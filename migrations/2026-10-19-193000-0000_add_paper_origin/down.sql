@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_papers_reached_from;
+ALTER TABLE papers DROP COLUMN IF EXISTS snowball_depth;
+ALTER TABLE papers DROP COLUMN IF EXISTS reached_from;
+ALTER TABLE papers DROP COLUMN IF EXISTS reached_via;
@@ -0,0 +1,9 @@
+-- How a paper was reached: `query` for hits of the harvest query,
+-- `reference` or `citation` for papers found while snowballing
+ALTER TABLE papers ADD COLUMN reached_via TEXT NOT NULL DEFAULT 'query';
+-- PMCID of the paper whose references or citations led here
+ALTER TABLE papers ADD COLUMN reached_from TEXT;
+-- Citation hops away from the query's papers, 0 for query hits
+ALTER TABLE papers ADD COLUMN snowball_depth INT NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_papers_reached_from ON papers(reached_from);
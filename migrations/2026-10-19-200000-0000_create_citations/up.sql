@@ -0,0 +1,11 @@
+-- Edges of the citation graph. Works are identified as `SOURCE:ID`
+-- the way Europe PMC knows them, e.g. `MED:12345` or `PMC:PMC67890`,
+-- since cited works are often not stored (nor open access).
+CREATE TABLE citations (
+    citing TEXT NOT NULL,
+    cited TEXT NOT NULL,
+    PRIMARY KEY (citing, cited)
+);
+
+-- Index for finding the works citing a work
+CREATE INDEX idx_citations_cited ON citations(cited);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_papers_status;
+ALTER TABLE papers DROP COLUMN IF EXISTS status;
@@ -0,0 +1,6 @@
+-- `pending` while the paper's objects are being uploaded, `stored` once
+-- they all are, `abandoned` when a recovery sweep takes the paper over.
+-- Papers stored before this are complete.
+ALTER TABLE papers ADD COLUMN status TEXT NOT NULL DEFAULT 'stored';
+
+CREATE INDEX idx_papers_status ON papers(status);